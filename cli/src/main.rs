@@ -1,7 +1,9 @@
-use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+
+use clap::{Parser, Subcommand, ValueEnum};
 use qmp::types::InvokeCommand;
-use vm_types::vm::DriveBus;
-use yave::{DefaultYaveContext, builders::{CloudInitBuilder, VmLaunchRequestBuilder}, cloudinit::CloudInitInstaller, net::NetworkManager, registry::{AddIPv4Address, CreateDrive, CreateNetworkInterface, CreateVirtualMachine}, storage::{DriveInstallMode, InstallOptions}};
+use vm_types::vm::{DiskMediaKind, DriveBus, DriveFormat, DriveOptions, IoLimits, NicModel, NicOptions};
+use yave::{DefaultYaveContext, backup::BackupManager, builders::{CloudInitBuilder, VmLaunchRequestBuilder}, clone::CloneManager, cloudinit::CloudInitInstaller, drive::DriveManager, ipam::IpamManager, migration::{MigrationManager, MigrationTarget}, net::NetworkManager, registry::{AddSecurityGroupRule, AddressMode, AddressRange, CreateIpPool, CloneVirtualMachine, CreateDrive, CreateNetwork, CreateNetworkInterface, CreateSocketNetwork, CreateVirtualMachine, ForwardProtocol, AddPortForward, RuleDirection, RuleProtocol, SocketMode}, port_forward::PortForwardManager, security_group::SecurityGroupManager, storage::{DriveInstallMode, InstallOptions}};
 
 
 #[derive(Parser, Debug)]
@@ -11,10 +13,210 @@ struct Args {
     cmd: Commands,
 }
 
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Bus {
+    VirtioBlk,
+    VirtioScsi,
+    Nvme,
+    Ide,
+    Usb,
+}
+
+impl Bus {
+    fn drive_bus(self, drive_id: &str, boot_index: Option<u32>) -> DriveBus {
+        match self {
+            Bus::VirtioBlk => DriveBus::VirtioBlk { boot_index },
+            Bus::VirtioScsi => DriveBus::VirtioScsi { lun: 0, boot_index },
+            Bus::Nvme => DriveBus::Nvme { serial: drive_id.to_string(), boot_index },
+            Bus::Ide => DriveBus::Ide { media_type: DiskMediaKind::Disk, boot_index },
+            Bus::Usb => DriveBus::UsbStorage { boot_index },
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Direction {
+    Ingress,
+    Egress,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Protocol {
+    Any,
+    Tcp,
+    Udp,
+    Icmp,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum AddrMode {
+    Static,
+    Slaac,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum ForwardProto {
+    Tcp,
+    Udp,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum Model {
+    Virtio,
+    E1000,
+    Rtl8139,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum SocketKind {
+    Mcast,
+    Udp,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+enum LinkState {
+    Up,
+    Down,
+}
+
 #[derive(Debug, Subcommand)]
 enum NetdevCommand {
     Up,
     Down,
+    Vlan {
+        #[arg(long)]
+        id: Option<u16>,
+    },
+    Options {
+        #[arg(long, value_enum)]
+        model: Option<Model>,
+        #[arg(long)]
+        vhost: Option<bool>,
+        #[arg(long)]
+        multiqueue: Option<bool>,
+        #[arg(long)]
+        mtu: Option<u32>,
+    },
+    Link {
+        #[arg(value_enum)]
+        state: LinkState,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum GenerationCommand {
+    Create,
+    List,
+    Restore {
+        #[arg(short, long)]
+        id: i64,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum NetworkCommand {
+    Create {
+        #[arg(short, long)]
+        bridge: String,
+        #[arg(short, long)]
+        address: String,
+        #[arg(short, long)]
+        prefix_len: u32,
+        #[arg(long)]
+        nat: bool,
+        #[arg(long)]
+        dhcp: bool,
+        #[arg(long)]
+        mtu: Option<u32>,
+        #[arg(long)]
+        isolated: bool,
+    },
+    CreateSocket {
+        #[arg(short, long, value_enum)]
+        mode: SocketKind,
+        #[arg(short, long)]
+        address: String,
+        #[arg(short, long)]
+        port: u16,
+        #[arg(short, long)]
+        local_address: Option<String>,
+    },
+    Start,
+    Stop,
+    Delete,
+}
+
+#[derive(Debug, Subcommand)]
+enum SecurityGroupCommand {
+    Create {
+        #[arg(short, long)]
+        description: Option<String>,
+    },
+    Show,
+    Delete,
+    AddRule {
+        #[arg(short, long, value_enum)]
+        direction: Direction,
+        #[arg(short, long, value_enum, default_value = "any")]
+        protocol: Protocol,
+        #[arg(long)]
+        port_from: Option<u16>,
+        #[arg(long)]
+        port_to: Option<u16>,
+        #[arg(short, long)]
+        cidr: Option<String>,
+    },
+    DeleteRule {
+        #[arg(short, long)]
+        id: i64,
+    },
+    Attach {
+        #[arg(short, long)]
+        ifname: String,
+    },
+    Detach {
+        #[arg(short, long)]
+        ifname: String,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum IpPoolCommand {
+    Create {
+        #[arg(short, long)]
+        cidr: String,
+        #[arg(short, long)]
+        gateway: Option<String>,
+        #[arg(long)]
+        network: Option<String>,
+        #[arg(short, long)]
+        reserve: Vec<String>,
+        #[arg(long)]
+        nameserver: Vec<String>,
+    },
+    Show,
+    Delete,
+}
+
+#[derive(Debug, Subcommand)]
+enum PortForwardCommand {
+    Add {
+        #[arg(short, long)]
+        ifname: String,
+        #[arg(long)]
+        host_ip: Option<String>,
+        #[arg(long)]
+        host_port: u16,
+        #[arg(short, long, value_enum, default_value = "tcp")]
+        protocol: ForwardProto,
+        #[arg(long)]
+        guest_port: u16,
+    },
+    List,
+    Delete {
+        #[arg(short, long)]
+        id: i64,
+    },
 }
 
 #[derive(Debug, Subcommand)]
@@ -30,6 +232,18 @@ enum Commands {
         capacity: u64,
         #[arg(short, long)]
         image: Option<String>,
+        #[arg(short, long, value_enum, default_value = "virtio-blk")]
+        bus: Bus,
+        #[arg(short, long)]
+        encrypted: bool,
+        #[arg(long)]
+        iso: Option<String>,
+        #[arg(long, requires = "iso")]
+        boot_iso: bool,
+        #[arg(long = "network")]
+        networks: Vec<String>,
+        #[arg(long = "mac")]
+        macs: Vec<String>,
     },
     List,
     Install {
@@ -45,16 +259,26 @@ enum Commands {
         ifname: String,
         #[arg(short, long)]
         address: String,
-        #[arg(short, long)]
-        netmask: u32,
+        #[arg(short, long, alias = "netmask")]
+        prefix_len: u32,
         #[arg(short, long)]
         gateway: Option<String>,
+        #[arg(short, long, value_enum, default_value = "static")]
+        mode: AddrMode,
     },
     Run {
         #[arg(short, long)]
         name: String,
         #[arg(short, long)]
         vnc: Option<String>,
+        #[arg(short, long)]
+        resume: bool,
+        #[arg(short, long, conflicts_with = "resume")]
+        discard: bool,
+    },
+    Save {
+        #[arg(short, long)]
+        name: String,
     },
     Shutdown {
         #[arg(short, long)]
@@ -74,30 +298,202 @@ enum Commands {
         #[arg(short, long)]
         name: String,
     },
+    Resize {
+        #[arg(short, long)]
+        name: String,
+        #[arg(short, long, default_value = "drive0")]
+        drive: String,
+        #[arg(short, long)]
+        size: u64,
+        #[arg(short, long)]
+        force: bool,
+    },
+    Throttle {
+        #[arg(short, long)]
+        name: String,
+        #[arg(short, long, default_value = "drive0")]
+        drive: String,
+        #[arg(long)]
+        iops_total: Option<u64>,
+        #[arg(long)]
+        iops_read: Option<u64>,
+        #[arg(long)]
+        iops_write: Option<u64>,
+        #[arg(long)]
+        bps_total: Option<u64>,
+        #[arg(long)]
+        bps_read: Option<u64>,
+        #[arg(long)]
+        bps_write: Option<u64>,
+    },
+    RotateKey {
+        #[arg(short, long)]
+        name: String,
+        #[arg(short, long, default_value = "drive0")]
+        drive: String,
+    },
+    Media {
+        #[arg(short, long)]
+        name: String,
+        #[arg(short, long)]
+        drive: Option<String>,
+        #[arg(short, long, required_unless_present = "eject")]
+        iso: Option<String>,
+        #[arg(short, long, conflicts_with = "iso")]
+        eject: bool,
+    },
+    Backup {
+        #[arg(short, long)]
+        name: String,
+        #[arg(short, long)]
+        out: PathBuf,
+    },
+    Restore {
+        #[arg(short, long)]
+        archive: PathBuf,
+        #[arg(short, long)]
+        name: String,
+    },
+    Clone {
+        #[arg(short, long)]
+        name: String,
+        #[arg(short, long)]
+        target: String,
+        #[arg(long)]
+        hostname: Option<String>,
+        #[arg(short, long)]
+        linked: bool,
+    },
+    Migrate {
+        #[arg(short, long)]
+        name: String,
+        #[arg(long)]
+        host: String,
+        #[arg(short, long, default_value = "4444")]
+        port: u16,
+        #[arg(long)]
+        nbd_port: Option<u16>,
+        #[arg(short, long)]
+        auto_converge: bool,
+    },
+    MigrateIncoming {
+        #[arg(short, long)]
+        name: String,
+        #[arg(short, long, default_value = "0.0.0.0")]
+        listen: String,
+        #[arg(short, long, default_value = "4444")]
+        port: u16,
+        #[arg(long)]
+        nbd_port: Option<u16>,
+    },
+    MigrateCancel {
+        #[arg(short, long)]
+        name: String,
+    },
+    Generations {
+        #[arg(short, long)]
+        name: String,
+        #[command(subcommand)]
+        command: GenerationCommand,
+    },
+    Networks,
+    Network {
+        #[arg(short, long)]
+        name: String,
+        #[command(subcommand)]
+        command: NetworkCommand,
+    },
+    SecurityGroups,
+    SecurityGroup {
+        #[arg(short, long)]
+        name: String,
+        #[command(subcommand)]
+        command: SecurityGroupCommand,
+    },
+    IpPools,
+    IpPool {
+        #[arg(short, long)]
+        name: String,
+        #[command(subcommand)]
+        command: IpPoolCommand,
+    },
+    PortForward {
+        #[arg(short, long)]
+        name: String,
+        #[command(subcommand)]
+        command: PortForwardCommand,
+    },
 }
 
 #[tokio::main]
 async fn main() {
     let args = Args::parse();
     match args.cmd {
-        Commands::Create { name, vcpu, memory, capacity, image } => {
+        Commands::Create { name, vcpu, memory, capacity, image, bus, encrypted, iso, boot_iso, networks, macs } => {
             let context = DefaultYaveContext::create().await.expect("Error creating context");
             let registry = context.registry();
             registry.create_tables().await.expect("Error creating tables");
+            let mut drives = vec![CreateDrive {
+                id: "drive0".to_string(),
+                drive_bus: bus.drive_bus("drive0", Some(1)),
+                size: capacity,
+                options: DriveOptions {
+                    format: match (encrypted, &image) {
+                        (true, _) => Some(DriveFormat::Qcow2),
+                        (false, None) => Some(DriveFormat::Raw),
+                        (false, Some(_)) => None,
+                    },
+                    ..Default::default()
+                },
+                encrypted,
+                media: None,
+            }];
+            if let Some(iso) = &iso {
+                if !context.storage().iso_path(iso).exists() {
+                    eprintln!("ISO not found: {}", iso);
+                    std::process::exit(1);
+                }
+                drives.push(CreateDrive::cdrom("cdrom0", iso, boot_iso));
+            }
+            for drive in &drives {
+                yave::drive::validate_drive_options(&drive.options).expect("Invalid drive options");
+            }
+            let nm = NetworkManager::new(&context);
+            for network in &networks {
+                if !nm.network_exists(network).await.expect("Error resolving network") {
+                    eprintln!("Network not found: {}", network);
+                    std::process::exit(1);
+                }
+            }
+            let network_interfaces = match networks.is_empty() {
+                true => vec![CreateNetworkInterface {
+                    id: "net0".to_string(),
+                    network: None,
+                    vlan: None,
+                    options: NicOptions::default(),
+                    mac_address: macs.first().cloned(),
+                }],
+                false => networks.into_iter().enumerate().map(|(idx, network)| CreateNetworkInterface {
+                    id: format!("net{}", idx),
+                    network: Some(network),
+                    vlan: None,
+                    options: NicOptions::default(),
+                    mac_address: macs.get(idx).cloned(),
+                }).collect(),
+            };
             registry.create_vm(CreateVirtualMachine {
                 id: name.to_string(),
                 hostname: name.to_string(),
                 vcpu,
                 memory,
                 ovmf: true,
-                network_interfaces: vec![CreateNetworkInterface {
-                    id: "net0".to_string(),
-                }],
-                drives: vec![CreateDrive {
-                    id: "drive0".to_string(),
-                    drive_bus: DriveBus::VirtioBlk { boot_index: Some(1) },
-                }],
+                network_interfaces,
+                drives,
             }).await.expect("Error creating VM");
+            if let Err(err) = IpamManager::new(&context).allocate_vm(&name).await {
+                registry.delete_vm(&name).await.expect("Error removing VM");
+                panic!("Error allocating addresses: {:?}", err);
+            }
             let storage = context.storage();
             storage.install_vm(
                 &name,
@@ -108,10 +504,12 @@ async fn main() {
                                 id: "drive0".to_string(),
                                 resize: capacity,
                                 image: image_path,
+                                encrypted,
                             },
                             None => DriveInstallMode::New {
                                 id: "drive0".to_string(),
                                 size: capacity,
+                                encrypted,
                             },
                         }
                     ],
@@ -134,16 +532,26 @@ async fn main() {
                 println!("VM: {}", vm.id);
             }
         },
-        Commands::Run { name, vnc } => {
+        Commands::Run { name, vnc, resume, discard } => {
             let context = DefaultYaveContext::create().await.expect("Error creating context");
+            if discard {
+                context.storage().discard_state(&name).expect("Error discarding saved state");
+            }
             let builder = yave::builders::VmLaunchRequestBuilder::new(&context);
             let launch_request = builder.build(&name).await.expect("Error building launch request");
             let runtime = context.runtime();
             let vnc = vnc.unwrap_or("changeme".to_string());
-            runtime.run_vm(&launch_request).await.expect("Error running VM");
+            runtime.run_vm(&launch_request, resume).await.expect("Error running VM");
             runtime.qmp_connect(&launch_request).await.expect("Error connecting to QMP")
                 .invoke(InvokeCommand::set_vnc_password(&vnc)).await.expect("Error setting VNC password");
         },
+        Commands::Save { name } => {
+            let context = DefaultYaveContext::create().await.expect("Error creating context");
+            let builder = yave::builders::VmLaunchRequestBuilder::new(&context);
+            let launch_request = builder.build(&name).await.expect("Error building launch request");
+            let state_path = context.storage().state_path(&name);
+            context.runtime().save_state(&launch_request, &state_path).await.expect("Error saving VM state");
+        },
         Commands::Shutdown { name } => {
             let context = DefaultYaveContext::create().await.expect("Error creating context");
             let builder = yave::builders::VmLaunchRequestBuilder::new(&context);
@@ -167,8 +575,29 @@ async fn main() {
                     nm.up_interface(&ifname).await.expect("Error bringing up interface");
                 },
                 NetdevCommand::Down => {
-                    // Currently not implemented
-                    println!("Not implemented yet");
+                    nm.down_interface(&ifname).await.expect("Error bringing down interface");
+                },
+                NetdevCommand::Vlan { id } => {
+                    nm.set_vlan(&ifname, id).await.expect("Error setting VLAN");
+                },
+                NetdevCommand::Options { model, vhost, multiqueue, mtu } => {
+                    let nic = context.registry().get_network_interface_by_ifname(&ifname).await.expect("Error getting interface");
+                    let mut options = nic.options;
+                    if let Some(model) = model {
+                        options.model = match model {
+                            Model::Virtio => NicModel::Virtio,
+                            Model::E1000 => NicModel::E1000,
+                            Model::Rtl8139 => NicModel::Rtl8139,
+                        };
+                    }
+                    options.vhost = vhost.unwrap_or(options.vhost);
+                    options.multiqueue = multiqueue.unwrap_or(options.multiqueue);
+                    options.mtu = mtu.or(options.mtu);
+                    nm.set_options(&ifname, &options).await.expect("Error setting interface options");
+                    println!("Options: {:?}", options);
+                },
+                NetdevCommand::Link { state } => {
+                    nm.set_link(&ifname, matches!(state, LinkState::Up)).await.expect("Error setting link state");
                 },
             }
         },
@@ -178,28 +607,294 @@ async fn main() {
             let vm = registry.get_vm_full(&name).await.expect("Error inspecting VM");
             println!("VM: {:?}", vm);
         },
-        Commands::Address { ifname, address, netmask, gateway } => {
+        Commands::Address { ifname, address, prefix_len, gateway, mode } => {
             let context = DefaultYaveContext::create().await.expect("Error creating context");
-            let registry = context.registry();
-            registry.add_ipv4_address(AddIPv4Address {
-                ifname,
-                address,
-                netmask,
-                gateway,
-            }).await.expect("Error adding address");
+            let mode = match mode {
+                AddrMode::Static => AddressMode::Static,
+                AddrMode::Slaac => AddressMode::Slaac,
+            };
+            let record = NetworkManager::new(&context).add_address(&ifname, &address, prefix_len, gateway, mode)
+                .await.expect("Error adding address");
+            println!("Address: {}/{}", record.address, record.prefix_len);
         },
         Commands::Delete { name } => {
             let context = DefaultYaveContext::create().await.expect("Error creating context");
             let builder = VmLaunchRequestBuilder::new(&context);
             let launch_request = builder.build(&name).await.expect("Error building launch request");
             let runtime = context.runtime();
-            if runtime.is_running(&launch_request).await.expect("Error checking if VM is running") {
-                runtime.shutdown_vm(&launch_request).await.expect("Error shutting down VM");
+            match runtime.is_running(&launch_request).await.expect("Error checking if VM is running") {
+                true => runtime.shutdown_vm(&launch_request).await.expect("Error shutting down VM"),
+                false => runtime.delete_interfaces(&launch_request).await.expect("Error deleting interfaces"),
             }
             let storage = context.storage();
             storage.delete_vm(&name).await.expect("Error deleting VM");
             let registry = context.registry();
             registry.delete_vm(&name).await.expect("Error deleting VM from registry");
+            PortForwardManager::new(&context).apply().await.expect("Error removing port forwards");
+        },
+        Commands::Resize { name, drive, size, force } => {
+            let context = DefaultYaveContext::create().await.expect("Error creating context");
+            DriveManager::new(&context).resize_drive(&name, &drive, size, force).await.expect("Error resizing drive");
+        },
+        Commands::Throttle { name, drive, iops_total, iops_read, iops_write, bps_total, bps_read, bps_write } => {
+            let context = DefaultYaveContext::create().await.expect("Error creating context");
+            DriveManager::new(&context).set_io_limits(&name, &drive, IoLimits {
+                iops_total,
+                iops_read,
+                iops_write,
+                bps_total,
+                bps_read,
+                bps_write,
+            }).await.expect("Error setting I/O limits");
+        },
+        Commands::RotateKey { name, drive } => {
+            let context = DefaultYaveContext::create().await.expect("Error creating context");
+            DriveManager::new(&context).rotate_key(&name, &drive).await.expect("Error rotating drive key");
+        },
+        Commands::Media { name, drive, iso, eject: _ } => {
+            let context = DefaultYaveContext::create().await.expect("Error creating context");
+            DriveManager::new(&context).change_media(&name, drive.as_deref(), iso.as_deref()).await.expect("Error changing media");
+        },
+        Commands::Backup { name, out } => {
+            let context = DefaultYaveContext::create().await.expect("Error creating context");
+            BackupManager::new(&context).backup(&name, &out).await.expect("Error backing up VM");
+        },
+        Commands::Restore { archive, name } => {
+            let context = DefaultYaveContext::create().await.expect("Error creating context");
+            let registry = context.registry();
+            registry.create_tables().await.expect("Error creating tables");
+            let vm = BackupManager::new(&context).restore(&archive, &name).await.expect("Error restoring VM");
+            println!("VM: {}", vm.id);
+        },
+        Commands::Clone { name, target, hostname, linked } => {
+            let context = DefaultYaveContext::create().await.expect("Error creating context");
+            let vm = CloneManager::new(&context).clone_vm(&name, CloneVirtualMachine { id: target, hostname, linked }).await.expect("Error cloning VM");
+            println!("VM: {}", vm.id);
+        },
+        Commands::Migrate { name, host, port, nbd_port, auto_converge } => {
+            let context = DefaultYaveContext::create().await.expect("Error creating context");
+            let target = MigrationTarget { host, port, nbd_port, auto_converge };
+            MigrationManager::new(&context).migrate(&name, &target, |status| {
+                println!("{}: {}/{} bytes, {} remaining", status.status, status.transferred, status.total, status.remaining);
+            }).await.expect("Error migrating VM");
+        },
+        Commands::MigrateIncoming { name, listen, port, nbd_port } => {
+            let context = DefaultYaveContext::create().await.expect("Error creating context");
+            let mm = MigrationManager::new(&context);
+            mm.incoming(&name, &listen, port, nbd_port).await.expect("Error preparing incoming migration");
+            mm.wait_incoming(&name, nbd_port.is_some(), |status| {
+                println!("{}: {} bytes", status.status, status.transferred);
+            }).await.expect("Error waiting for incoming migration");
+        },
+        Commands::MigrateCancel { name } => {
+            let context = DefaultYaveContext::create().await.expect("Error creating context");
+            MigrationManager::new(&context).cancel(&name).await.expect("Error cancelling migration");
+        },
+        Commands::Generations { name, command } => {
+            let context = DefaultYaveContext::create().await.expect("Error creating context");
+            let registry = context.registry();
+            registry.create_tables().await.expect("Error creating tables");
+            let bm = BackupManager::new(&context);
+            match command {
+                GenerationCommand::Create => {
+                    let backup = bm.create_generation(&name).await.expect("Error creating backup generation");
+                    println!("Backup: {}", backup.id);
+                },
+                GenerationCommand::List => {
+                    let backups = bm.list_generations(&name).await.expect("Error listing backup generations");
+                    for backup in backups {
+                        println!("{} {}", backup.id, backup.created_at);
+                    }
+                },
+                GenerationCommand::Restore { id } => {
+                    bm.restore_generation(&name, id).await.expect("Error restoring backup generation");
+                },
+            }
+        },
+        Commands::Networks => {
+            let context = DefaultYaveContext::create().await.expect("Error creating context");
+            let registry = context.registry();
+            registry.create_tables().await.expect("Error creating tables");
+            let networks = registry.get_networks().await.expect("Error listing networks");
+            for network in networks {
+                println!("{} {} {}/{} nat={} dhcp={} isolated={}", network.name, network.bridge, network.address, network.prefix_len, network.nat, network.dhcp, network.isolated);
+            }
+            let sockets = registry.get_socket_networks().await.expect("Error listing socket networks");
+            for network in sockets {
+                match &network.local_address {
+                    Some(local_address) => println!("{} {:?} {}:{} local={}", network.name, network.mode, network.address, network.port, local_address),
+                    None => println!("{} {:?} {}:{}", network.name, network.mode, network.address, network.port),
+                }
+            }
+        },
+        Commands::Network { name, command } => {
+            let context = DefaultYaveContext::create().await.expect("Error creating context");
+            let registry = context.registry();
+            registry.create_tables().await.expect("Error creating tables");
+            let nm = NetworkManager::new(&context);
+            match command {
+                NetworkCommand::Create { bridge, address, prefix_len, nat, dhcp, mtu, isolated } => {
+                    nm.create_network(CreateNetwork {
+                        name,
+                        bridge,
+                        address,
+                        prefix_len,
+                        nat,
+                        dhcp,
+                        mtu,
+                        isolated,
+                    }).await.expect("Error creating network");
+                },
+                NetworkCommand::CreateSocket { mode, address, port, local_address } => {
+                    nm.create_socket_network(CreateSocketNetwork {
+                        name,
+                        mode: match mode {
+                            SocketKind::Mcast => SocketMode::Mcast,
+                            SocketKind::Udp => SocketMode::Udp,
+                        },
+                        address,
+                        port,
+                        local_address,
+                    }).await.expect("Error creating socket network");
+                },
+                NetworkCommand::Start => {
+                    nm.start_network(&name).await.expect("Error starting network");
+                },
+                NetworkCommand::Stop => {
+                    nm.stop_network(&name).await.expect("Error stopping network");
+                },
+                NetworkCommand::Delete => {
+                    nm.delete_network(&name).await.expect("Error deleting network");
+                },
+            }
+        },
+        Commands::SecurityGroups => {
+            let context = DefaultYaveContext::create().await.expect("Error creating context");
+            let registry = context.registry();
+            registry.create_tables().await.expect("Error creating tables");
+            let groups = registry.get_security_groups().await.expect("Error listing security groups");
+            for group in groups {
+                println!("{} {}", group.name, group.description.unwrap_or_default());
+            }
+        },
+        Commands::SecurityGroup { name, command } => {
+            let context = DefaultYaveContext::create().await.expect("Error creating context");
+            let registry = context.registry();
+            registry.create_tables().await.expect("Error creating tables");
+            let sgm = SecurityGroupManager::new(&context);
+            match command {
+                SecurityGroupCommand::Create { description } => {
+                    sgm.create_group(&name, description.as_deref()).await.expect("Error creating security group");
+                },
+                SecurityGroupCommand::Show => {
+                    let (_, rules) = sgm.get_group(&name).await.expect("Error getting security group");
+                    for rule in rules {
+                        println!("{}: {:?}", rule.id, rule);
+                    }
+                },
+                SecurityGroupCommand::Delete => {
+                    sgm.delete_group(&name).await.expect("Error deleting security group");
+                },
+                SecurityGroupCommand::AddRule { direction, protocol, port_from, port_to, cidr } => {
+                    let rule = sgm.add_rule(&name, AddSecurityGroupRule {
+                        direction: match direction {
+                            Direction::Ingress => RuleDirection::Ingress,
+                            Direction::Egress => RuleDirection::Egress,
+                        },
+                        protocol: match protocol {
+                            Protocol::Any => RuleProtocol::Any,
+                            Protocol::Tcp => RuleProtocol::Tcp,
+                            Protocol::Udp => RuleProtocol::Udp,
+                            Protocol::Icmp => RuleProtocol::Icmp,
+                        },
+                        port_from,
+                        port_to,
+                        cidr,
+                    }).await.expect("Error adding security group rule");
+                    println!("Rule: {}", rule.id);
+                },
+                SecurityGroupCommand::DeleteRule { id } => {
+                    sgm.delete_rule(&name, id).await.expect("Error deleting security group rule");
+                },
+                SecurityGroupCommand::Attach { ifname } => {
+                    sgm.attach(&ifname, &name).await.expect("Error attaching security group");
+                },
+                SecurityGroupCommand::Detach { ifname } => {
+                    sgm.detach(&ifname, &name).await.expect("Error detaching security group");
+                },
+            }
+        },
+        Commands::IpPools => {
+            let context = DefaultYaveContext::create().await.expect("Error creating context");
+            context.registry().create_tables().await.expect("Error creating tables");
+            let pools = IpamManager::new(&context).get_pools().await.expect("Error listing IP pools");
+            for (pool, usage) in pools {
+                println!("{} {} {}/{} used", pool.name, pool.cidr, usage.used, usage.total);
+            }
+        },
+        Commands::IpPool { name, command } => {
+            let context = DefaultYaveContext::create().await.expect("Error creating context");
+            context.registry().create_tables().await.expect("Error creating tables");
+            let ipam = IpamManager::new(&context);
+            match command {
+                IpPoolCommand::Create { cidr, gateway, network, reserve, nameserver } => {
+                    let reserved = reserve.iter()
+                        .map(|range| match range.split_once('-') {
+                            Some((start, end)) => AddressRange { start: start.to_string(), end: end.to_string() },
+                            None => AddressRange { start: range.clone(), end: range.clone() },
+                        })
+                        .collect();
+                    ipam.create_pool(CreateIpPool {
+                        name,
+                        cidr,
+                        gateway,
+                        reserved,
+                        nameservers: nameserver,
+                        network,
+                    }).await.expect("Error creating IP pool");
+                },
+                IpPoolCommand::Show => {
+                    let (pool, usage) = ipam.get_pool(&name).await.expect("Error getting IP pool");
+                    println!("{:?}", pool);
+                    println!("{}/{} used", usage.used, usage.total);
+                    for address in usage.addresses {
+                        println!("{} {}", address.address, address.ifname);
+                    }
+                },
+                IpPoolCommand::Delete => {
+                    ipam.delete_pool(&name).await.expect("Error deleting IP pool");
+                },
+            }
+        },
+        Commands::PortForward { name, command } => {
+            let context = DefaultYaveContext::create().await.expect("Error creating context");
+            let registry = context.registry();
+            registry.create_tables().await.expect("Error creating tables");
+            let pfm = PortForwardManager::new(&context);
+            match command {
+                PortForwardCommand::Add { ifname, host_ip, host_port, protocol, guest_port } => {
+                    let forward = pfm.add_forward(&name, AddPortForward {
+                        ifname,
+                        host_ip,
+                        host_port,
+                        protocol: match protocol {
+                            ForwardProto::Tcp => ForwardProtocol::Tcp,
+                            ForwardProto::Udp => ForwardProtocol::Udp,
+                        },
+                        guest_port,
+                    }).await.expect("Error adding port forward");
+                    println!("Port forward: {}", forward.id);
+                },
+                PortForwardCommand::List => {
+                    let forwards = pfm.get_forwards(&name).await.expect("Error listing port forwards");
+                    for forward in forwards {
+                        println!("{}: {:?}", forward.id, forward);
+                    }
+                },
+                PortForwardCommand::Delete { id } => {
+                    pfm.delete_forward(&name, id).await.expect("Error deleting port forward");
+                },
+            }
         },
     }
 
@@ -0,0 +1,237 @@
+use serde_json::{Value as Json, json};
+
+use crate::types::{Chain, Map, Rule, Set, Table};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Object {
+    Table(Table),
+    Chain(Chain),
+    Set(Set),
+    Map(Map),
+    Rule(Rule),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Add(Object),
+    Flush(Object),
+    Delete(Object),
+}
+
+/// Ordered list of commands nft applies as a single transaction.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Batch {
+    commands: Vec<Command>,
+}
+
+impl From<Table> for Object {
+    fn from(table: Table) -> Self {
+        Object::Table(table)
+    }
+}
+
+impl From<Chain> for Object {
+    fn from(chain: Chain) -> Self {
+        Object::Chain(chain)
+    }
+}
+
+impl From<Set> for Object {
+    fn from(set: Set) -> Self {
+        Object::Set(set)
+    }
+}
+
+impl From<Map> for Object {
+    fn from(map: Map) -> Self {
+        Object::Map(map)
+    }
+}
+
+impl From<Rule> for Object {
+    fn from(rule: Rule) -> Self {
+        Object::Rule(rule)
+    }
+}
+
+impl Object {
+    fn kind(&self) -> &'static str {
+        match self {
+            Object::Table(_) => "table",
+            Object::Chain(_) => "chain",
+            Object::Set(_) => "set",
+            Object::Map(_) => "map",
+            Object::Rule(_) => "rule",
+        }
+    }
+
+    /// Full definition for `add`.
+    fn to_text(&self) -> String {
+        match self {
+            Object::Table(table) => table.to_text(),
+            Object::Chain(chain) => chain.to_text(),
+            Object::Set(set) => set.to_text(),
+            Object::Map(map) => map.to_text(),
+            Object::Rule(rule) => rule.to_text(),
+        }
+    }
+
+    /// Bare reference for `flush` and `delete`. Rules are referenced by their handle.
+    fn to_reference(&self) -> crate::Result<String> {
+        Ok(match self {
+            Object::Table(table) => table.to_text(),
+            Object::Chain(chain) => format!("chain {} {} {}", chain.family.as_str(), chain.table, chain.name),
+            Object::Set(set) => format!("set {} {} {}", set.family.as_str(), set.table, set.name),
+            Object::Map(map) => format!("map {} {} {}", map.family.as_str(), map.table, map.name),
+            Object::Rule(rule) => format!("rule {} {} {} handle {}", rule.family.as_str(), rule.table, rule.chain, Self::rule_handle(rule)?),
+        })
+    }
+
+    fn rule_handle(rule: &Rule) -> crate::Result<u64> {
+        rule.handle.ok_or_else(|| crate::Error::MissingHandle(format!("{} {} {}", rule.family.as_str(), rule.table, rule.chain)))
+    }
+
+    fn to_json(&self) -> Json {
+        match self {
+            Object::Table(table) => table.to_json(),
+            Object::Chain(chain) => chain.to_json(),
+            Object::Set(set) => set.to_json(),
+            Object::Map(map) => map.to_json(),
+            Object::Rule(rule) => rule.to_json(),
+        }
+    }
+
+    fn to_json_reference(&self) -> crate::Result<Json> {
+        if let Object::Rule(rule) = self {
+            Self::rule_handle(rule)?;
+        }
+        let mut object = self.to_json();
+        if let Some(object) = object.as_object_mut() {
+            object.retain(|key, _| matches!(key.as_str(), "family" | "table" | "name" | "chain" | "handle"));
+        }
+        Ok(object)
+    }
+}
+
+impl Batch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, object: impl Into<Object>) -> &mut Self {
+        self.commands.push(Command::Add(object.into()));
+        self
+    }
+
+    pub fn flush(&mut self, object: impl Into<Object>) -> &mut Self {
+        self.commands.push(Command::Flush(object.into()));
+        self
+    }
+
+    pub fn delete(&mut self, object: impl Into<Object>) -> &mut Self {
+        self.commands.push(Command::Delete(object.into()));
+        self
+    }
+
+    pub fn extend(&mut self, batch: Batch) -> &mut Self {
+        self.commands.extend(batch.commands);
+        self
+    }
+
+    pub fn commands(&self) -> &[Command] {
+        &self.commands
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    /// Script in the syntax of `nft -f`, one command per line. Fails on a rule to flush or
+    /// delete that has no handle.
+    pub fn to_script(&self) -> crate::Result<String> {
+        self.commands.iter()
+            .map(|command| Ok(match command {
+                Command::Add(object) => format!("add {}\n", object.to_text()),
+                Command::Flush(object) => format!("flush {}\n", object.to_reference()?),
+                Command::Delete(object) => format!("delete {}\n", object.to_reference()?),
+            }))
+            .collect()
+    }
+
+    /// Document in the schema of `nft -j -f`. Fails like [`Batch::to_script`].
+    pub fn to_json(&self) -> crate::Result<Json> {
+        let commands = self.commands.iter()
+            .map(|command| Ok(match command {
+                Command::Add(object) => json!({ "add": { object.kind(): object.to_json() } }),
+                Command::Flush(object) => json!({ "flush": { object.kind(): object.to_json_reference()? } }),
+                Command::Delete(object) => json!({ "delete": { object.kind(): object.to_json_reference()? } }),
+            }))
+            .collect::<crate::Result<Vec<Json>>>()?;
+        Ok(json!({ "nftables": commands }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{builders::Family, types::{ChainType, Field, Hook, Policy, Statement, Value}};
+
+    fn nat_batch() -> Batch {
+        let table = Table::new(Family::Ip, "yave_lan");
+        let postrouting = table.base_chain("postrouting", ChainType::Nat, Hook::Postrouting, 100).policy(Policy::Accept);
+        let mut batch = Batch::new();
+        batch
+            .add(table.clone())
+            .flush(table.clone())
+            .add(postrouting.clone())
+            .add(postrouting.rule()
+                .matches(Field::payload("ip", "saddr"), Value::prefix("10.0.0.0", 24))
+                .statement(Statement::Masquerade))
+            .delete(postrouting.rule().handle(7));
+        batch
+    }
+
+    #[test]
+    fn script() {
+        assert_eq!(nat_batch().to_script().unwrap(), "\
+add table ip yave_lan
+flush table ip yave_lan
+add chain ip yave_lan postrouting { type nat hook postrouting priority 100 ; policy accept ; }
+add rule ip yave_lan postrouting ip saddr 10.0.0.0/24 masquerade
+delete rule ip yave_lan postrouting handle 7
+");
+    }
+
+    #[test]
+    fn json() {
+        assert_eq!(nat_batch().to_json().unwrap(), json!({ "nftables": [
+            { "add": { "table": { "family": "ip", "name": "yave_lan" } } },
+            { "flush": { "table": { "family": "ip", "name": "yave_lan" } } },
+            { "add": { "chain": {
+                "family": "ip", "table": "yave_lan", "name": "postrouting",
+                "type": "nat", "hook": "postrouting", "prio": 100, "policy": "accept",
+            } } },
+            { "add": { "rule": {
+                "family": "ip", "table": "yave_lan", "chain": "postrouting",
+                "expr": [
+                    { "match": {
+                        "op": "==",
+                        "left": { "payload": { "protocol": "ip", "field": "saddr" } },
+                        "right": { "prefix": { "addr": "10.0.0.0", "len": 24 } },
+                    } },
+                    { "masquerade": null },
+                ],
+            } } },
+            { "delete": { "rule": { "family": "ip", "table": "yave_lan", "chain": "postrouting", "handle": 7 } } },
+        ] }));
+    }
+
+    #[test]
+    fn rule_without_handle() {
+        let chain = Table::new(Family::Inet, "yave").chain("input");
+        let mut batch = Batch::new();
+        batch.delete(chain.rule().accept());
+        assert!(matches!(batch.to_script(), Err(crate::Error::MissingHandle(_))));
+        assert!(matches!(batch.to_json(), Err(crate::Error::MissingHandle(_))));
+    }
+}
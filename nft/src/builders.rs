@@ -1,14 +1,18 @@
 use crate::Nft;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Family {
     Inet,
+    Ip,
+    Ip6,
+    Bridge,
+    Arp,
+    Netdev,
 }
 
 impl ToString for Family {
     fn to_string(&self) -> String {
-        match self {
-            Family::Inet => "inet".to_string(),
-        }
+        self.as_str().to_string()
     }
 }
 
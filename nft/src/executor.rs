@@ -0,0 +1,76 @@
+use std::process::Stdio;
+
+use tokio::{io::AsyncWriteExt, process::Command};
+
+use crate::{batch::Batch, ruleset::Ruleset};
+
+/// Runs nft, applying batches in JSON mode through stdin.
+pub struct Executor {
+    bin: String,
+}
+
+impl Executor {
+    pub fn new(bin: &str) -> Self {
+        Self { bin: bin.to_string() }
+    }
+
+    /// Command line of `apply`, the batch JSON is written to its stdin.
+    /// nft commits everything read with `-f` in one transaction, so a batch is
+    /// either applied completely or not at all.
+    pub fn apply_args(&self) -> Vec<String> {
+        vec![self.bin.clone(), "-j".to_string(), "-f".to_string(), "-".to_string()]
+    }
+
+    pub fn list_args(&self) -> Vec<String> {
+        vec![self.bin.clone(), "-j".to_string(), "list".to_string(), "ruleset".to_string()]
+    }
+
+    pub async fn apply(&self, batch: &Batch) -> crate::Result<()> {
+        if batch.is_empty() {
+            return Ok(());
+        }
+        let document = batch.to_json()?;
+        let args = self.apply_args();
+        let mut child = Command::new(&args[0])
+            .args(&args[1..])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin.write_all(document.to_string().as_bytes()).await?;
+        }
+        let output = child.wait_with_output().await?;
+        if !output.status.success() {
+            return Err(crate::Error::Command(String::from_utf8_lossy(&output.stderr).trim().to_string()));
+        }
+        Ok(())
+    }
+
+    pub async fn list_ruleset(&self) -> crate::Result<Ruleset> {
+        let args = self.list_args();
+        let output = Command::new(&args[0])
+            .args(&args[1..])
+            .output()
+            .await?;
+        if !output.status.success() {
+            return Err(crate::Error::Command(String::from_utf8_lossy(&output.stderr).trim().to_string()));
+        }
+        Ruleset::parse(&String::from_utf8_lossy(&output.stdout))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn apply_args() {
+        assert_eq!(Executor::new("/usr/sbin/nft").apply_args(), ["/usr/sbin/nft", "-j", "-f", "-"]);
+    }
+
+    #[test]
+    fn list_args() {
+        assert_eq!(Executor::new("nft").list_args(), ["nft", "-j", "list", "ruleset"]);
+    }
+}
@@ -1,4 +1,24 @@
+pub mod batch;
 pub mod builders;
+pub mod executor;
+pub mod ruleset;
+pub mod types;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("nft IO error: {0}")]
+    IO(#[from] std::io::Error),
+    #[error("nft serde error: {0}")]
+    Serde(#[from] serde_json::Error),
+    #[error("nft listing parse error: {0}")]
+    Parse(String),
+    #[error("nft command failed: {0}")]
+    Command(String),
+    #[error("nft rule in {0} has no handle")]
+    MissingHandle(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
 
 pub struct Nft {
     args: Vec<String>
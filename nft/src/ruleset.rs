@@ -0,0 +1,215 @@
+use serde_json::Value as Json;
+
+use crate::{builders::Family, types::{BaseChain, Chain, ChainType, Hook, Map, Policy, Set, SetFlag, SetType, Table, Value}};
+
+/// Rule as listed by nft. Its expressions are kept as JSON since listings may contain
+/// statements this crate does not model.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListedRule {
+    pub family: Family,
+    pub table: String,
+    pub chain: String,
+    pub handle: u64,
+    pub comment: Option<String>,
+    pub expr: Vec<Json>,
+}
+
+/// Objects of a `nft -j list ruleset` listing.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Ruleset {
+    pub tables: Vec<Table>,
+    pub chains: Vec<Chain>,
+    pub sets: Vec<Set>,
+    pub maps: Vec<Map>,
+    pub rules: Vec<ListedRule>,
+}
+
+fn str_field(object: &Json, key: &str) -> Option<String> {
+    object.get(key)?.as_str().map(str::to_string)
+}
+
+fn family_field(object: &Json) -> Option<Family> {
+    Family::from_name(object.get("family")?.as_str()?)
+}
+
+fn flags_field(object: &Json) -> Vec<SetFlag> {
+    match object.get("flags") {
+        Some(Json::Array(flags)) => flags.iter().filter_map(|flag| SetFlag::from_name(flag.as_str()?)).collect(),
+        Some(Json::String(flag)) => SetFlag::from_name(flag).into_iter().collect(),
+        _ => vec![],
+    }
+}
+
+fn type_field(object: &Json, key: &str) -> Option<SetType> {
+    match object.get(key)? {
+        Json::String(name) => Some(SetType::from_name(name)),
+        // Concatenations, e.g. ["ipv4_addr", "inet_service"]
+        Json::Array(names) => Some(SetType::Custom(names.iter()
+            .filter_map(Json::as_str)
+            .collect::<Vec<_>>()
+            .join(" . "))),
+        _ => None,
+    }
+}
+
+fn parse_table(object: &Json) -> Option<Table> {
+    Some(Table {
+        family: family_field(object)?,
+        name: str_field(object, "name")?,
+    })
+}
+
+fn parse_chain(object: &Json) -> Option<Chain> {
+    let base = match (str_field(object, "type"), str_field(object, "hook")) {
+        (Some(chain_type), Some(hook)) => Some(BaseChain {
+            chain_type: ChainType::from_name(&chain_type)?,
+            hook: Hook::from_name(&hook)?,
+            priority: object.get("prio").and_then(Json::as_i64).unwrap_or(0) as i32,
+            policy: str_field(object, "policy").and_then(|policy| Policy::from_name(&policy)),
+        }),
+        _ => None,
+    };
+    Some(Chain {
+        family: family_field(object)?,
+        table: str_field(object, "table")?,
+        name: str_field(object, "name")?,
+        base,
+    })
+}
+
+fn parse_set(object: &Json) -> Option<Set> {
+    let elements = match object.get("elem") {
+        Some(Json::Array(elements)) => elements.iter().filter_map(Value::from_json).collect(),
+        _ => vec![],
+    };
+    Some(Set {
+        family: family_field(object)?,
+        table: str_field(object, "table")?,
+        name: str_field(object, "name")?,
+        set_type: type_field(object, "type")?,
+        flags: flags_field(object),
+        elements,
+    })
+}
+
+fn parse_map(object: &Json) -> Option<Map> {
+    let elements = match object.get("elem") {
+        Some(Json::Array(elements)) => elements.iter()
+            .filter_map(|element| match element.as_array()?.as_slice() {
+                [key, value] => Some((Value::from_json(key)?, Value::from_json(value)?)),
+                _ => None,
+            })
+            .collect(),
+        _ => vec![],
+    };
+    Some(Map {
+        family: family_field(object)?,
+        table: str_field(object, "table")?,
+        name: str_field(object, "name")?,
+        key_type: type_field(object, "type")?,
+        value_type: type_field(object, "map")?,
+        flags: flags_field(object),
+        elements,
+    })
+}
+
+fn parse_rule(object: &Json) -> Option<ListedRule> {
+    Some(ListedRule {
+        family: family_field(object)?,
+        table: str_field(object, "table")?,
+        chain: str_field(object, "chain")?,
+        handle: object.get("handle")?.as_u64()?,
+        comment: str_field(object, "comment"),
+        expr: object.get("expr").and_then(Json::as_array).cloned().unwrap_or_default(),
+    })
+}
+
+impl Ruleset {
+    /// Parses the output of `nft -j list ruleset` (or `list table`). Objects of unknown
+    /// kinds, such as `metainfo` or flowtables, are skipped.
+    pub fn parse(listing: &str) -> crate::Result<Self> {
+        let document: Json = serde_json::from_str(listing)?;
+        let objects = document.get("nftables")
+            .and_then(Json::as_array)
+            .ok_or(crate::Error::Parse("missing nftables array".to_string()))?;
+
+        let mut ruleset = Ruleset::default();
+        for object in objects {
+            let Some((kind, object)) = object.as_object().and_then(|object| object.iter().next()) else {
+                continue;
+            };
+            let parsed = match kind.as_str() {
+                "table" => parse_table(object).map(|table| ruleset.tables.push(table)),
+                "chain" => parse_chain(object).map(|chain| ruleset.chains.push(chain)),
+                "set" => parse_set(object).map(|set| ruleset.sets.push(set)),
+                "map" => parse_map(object).map(|map| ruleset.maps.push(map)),
+                "rule" => parse_rule(object).map(|rule| ruleset.rules.push(rule)),
+                _ => Some(()),
+            };
+            if parsed.is_none() {
+                return Err(crate::Error::Parse(format!("invalid {}: {}", kind, object)));
+            }
+        }
+        Ok(ruleset)
+    }
+
+    pub fn has_table(&self, family: Family, name: &str) -> bool {
+        self.tables.iter().any(|table| table.family == family && table.name == name)
+    }
+
+    pub fn chains_of<'a>(&'a self, table: &'a Table) -> impl Iterator<Item = &'a Chain> {
+        self.chains.iter().filter(|chain| chain.family == table.family && chain.table == table.name)
+    }
+
+    pub fn rules_of<'a>(&'a self, chain: &'a Chain) -> impl Iterator<Item = &'a ListedRule> {
+        self.rules.iter().filter(|rule| rule.family == chain.family && rule.table == chain.table && rule.chain == chain.name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LISTING: &str = r#"{"nftables": [
+        {"metainfo": {"version": "1.0.9", "release_name": "Old Doc Yak #3", "json_schema_version": 1}},
+        {"table": {"family": "bridge", "name": "yave_if_yave0", "handle": 3}},
+        {"chain": {"family": "bridge", "table": "yave_if_yave0", "name": "forward", "handle": 1, "type": "filter", "hook": "forward", "prio": -200, "policy": "accept"}},
+        {"chain": {"family": "bridge", "table": "yave_if_yave0", "name": "guest", "handle": 2}},
+        {"set": {"family": "bridge", "name": "ipv4", "table": "yave_if_yave0", "type": "ipv4_addr", "handle": 4, "flags": ["interval"], "elem": ["10.0.0.5", {"prefix": {"addr": "10.0.1.0", "len": 24}}]}},
+        {"rule": {"family": "bridge", "table": "yave_if_yave0", "chain": "forward", "handle": 5, "comment": "guest traffic", "expr": [{"match": {"op": "==", "left": {"meta": {"key": "iifname"}}, "right": "yave0"}}, {"jump": {"target": "guest"}}]}}
+    ]}"#;
+
+    #[test]
+    fn parse() {
+        let ruleset = Ruleset::parse(LISTING).unwrap();
+        let table = Table::new(Family::Bridge, "yave_if_yave0");
+        assert_eq!(ruleset.tables, std::slice::from_ref(&table));
+        assert!(ruleset.has_table(Family::Bridge, "yave_if_yave0"));
+        assert!(!ruleset.has_table(Family::Inet, "yave_if_yave0"));
+
+        let chains = ruleset.chains_of(&table).collect::<Vec<_>>();
+        assert_eq!(chains, [
+            &table.base_chain("forward", ChainType::Filter, Hook::Forward, -200).policy(Policy::Accept),
+            &table.chain("guest"),
+        ]);
+
+        assert_eq!(ruleset.sets, [table.set("ipv4", SetType::Ipv4Addr)
+            .flag(SetFlag::Interval)
+            .element("10.0.0.5")
+            .element(Value::prefix("10.0.1.0", 24))]);
+
+        let rules = ruleset.rules_of(chains[0]).collect::<Vec<_>>();
+        assert_eq!(rules.len(), 1);
+        assert_eq!(rules[0].handle, 5);
+        assert_eq!(rules[0].comment.as_deref(), Some("guest traffic"));
+        assert_eq!(rules[0].expr.len(), 2);
+        assert!(ruleset.rules_of(chains[1]).next().is_none());
+    }
+
+    #[test]
+    fn parse_invalid() {
+        assert!(matches!(Ruleset::parse(r#"{"tables": []}"#), Err(crate::Error::Parse(_))));
+        assert!(matches!(Ruleset::parse(r#"{"nftables": [{"table": {"name": "t"}}]}"#), Err(crate::Error::Parse(_))));
+        assert!(matches!(Ruleset::parse("not json"), Err(crate::Error::Serde(_))));
+    }
+}
@@ -0,0 +1,826 @@
+use std::net::IpAddr;
+
+use serde_json::{Value as Json, json};
+
+use crate::builders::Family;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Table {
+    pub family: Family,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChainType {
+    Filter,
+    Nat,
+    Route,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hook {
+    Prerouting,
+    Input,
+    Forward,
+    Output,
+    Postrouting,
+    Ingress,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Policy {
+    Accept,
+    Drop,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BaseChain {
+    pub chain_type: ChainType,
+    pub hook: Hook,
+    pub priority: i32,
+    pub policy: Option<Policy>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chain {
+    pub family: Family,
+    pub table: String,
+    pub name: String,
+    /// Hook of a base chain, `None` for a regular chain only reachable by jumps.
+    pub base: Option<BaseChain>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SetType {
+    Ipv4Addr,
+    Ipv6Addr,
+    EtherAddr,
+    InetProto,
+    InetService,
+    IfName,
+    Mark,
+    Custom(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetFlag {
+    Constant,
+    Interval,
+    Timeout,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Set {
+    pub family: Family,
+    pub table: String,
+    pub name: String,
+    pub set_type: SetType,
+    pub flags: Vec<SetFlag>,
+    pub elements: Vec<Value>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Map {
+    pub family: Family,
+    pub table: String,
+    pub name: String,
+    pub key_type: SetType,
+    pub value_type: SetType,
+    pub flags: Vec<SetFlag>,
+    pub elements: Vec<(Value, Value)>,
+}
+
+/// Right hand side of a match, or an element of a set.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Str(String),
+    Number(u64),
+    Prefix(String, u8),
+    Range(Box<Value>, Box<Value>),
+    Set(Vec<Value>),
+    /// Named set, `@name`.
+    SetRef(String),
+}
+
+/// Left hand side of a match.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Field {
+    Meta(String),
+    Payload { protocol: String, field: String },
+    Ct(String),
+    /// Route lookup, e.g. `fib daddr type` to match addresses local to the host.
+    Fib { flags: Vec<String>, result: String },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+    /// Flag test, e.g. `ct state established,related`.
+    In,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Statement {
+    Match { left: Field, op: Op, right: Value },
+    Counter,
+    Accept,
+    Drop,
+    Reject,
+    Return,
+    Jump(String),
+    Goto(String),
+    Masquerade,
+    Snat { addr: IpAddr },
+    Dnat { addr: IpAddr, port: Option<u16> },
+    Log { prefix: String },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+    pub family: Family,
+    pub table: String,
+    pub chain: String,
+    pub statements: Vec<Statement>,
+    pub comment: Option<String>,
+    /// Handle of an existing rule, only needed to delete it.
+    pub handle: Option<u64>,
+}
+
+impl Table {
+    pub fn new(family: Family, name: &str) -> Self {
+        Self { family, name: name.to_string() }
+    }
+
+    pub fn chain(&self, name: &str) -> Chain {
+        Chain {
+            family: self.family,
+            table: self.name.clone(),
+            name: name.to_string(),
+            base: None,
+        }
+    }
+
+    pub fn base_chain(&self, name: &str, chain_type: ChainType, hook: Hook, priority: i32) -> Chain {
+        Chain {
+            base: Some(BaseChain { chain_type, hook, priority, policy: None }),
+            ..self.chain(name)
+        }
+    }
+
+    pub fn set(&self, name: &str, set_type: SetType) -> Set {
+        Set {
+            family: self.family,
+            table: self.name.clone(),
+            name: name.to_string(),
+            set_type,
+            flags: vec![],
+            elements: vec![],
+        }
+    }
+
+    pub fn map(&self, name: &str, key_type: SetType, value_type: SetType) -> Map {
+        Map {
+            family: self.family,
+            table: self.name.clone(),
+            name: name.to_string(),
+            key_type,
+            value_type,
+            flags: vec![],
+            elements: vec![],
+        }
+    }
+}
+
+impl Chain {
+    pub fn policy(mut self, policy: Policy) -> Self {
+        if let Some(base) = &mut self.base {
+            base.policy = Some(policy);
+        }
+        self
+    }
+
+    pub fn rule(&self) -> Rule {
+        Rule {
+            family: self.family,
+            table: self.table.clone(),
+            chain: self.name.clone(),
+            statements: vec![],
+            comment: None,
+            handle: None,
+        }
+    }
+}
+
+impl Set {
+    pub fn flag(mut self, flag: SetFlag) -> Self {
+        self.flags.push(flag);
+        self
+    }
+
+    pub fn element(mut self, element: impl Into<Value>) -> Self {
+        self.elements.push(element.into());
+        self
+    }
+
+    pub fn elements<V: Into<Value>>(mut self, elements: impl IntoIterator<Item = V>) -> Self {
+        self.elements.extend(elements.into_iter().map(Into::into));
+        self
+    }
+}
+
+impl Map {
+    pub fn flag(mut self, flag: SetFlag) -> Self {
+        self.flags.push(flag);
+        self
+    }
+
+    pub fn element(mut self, key: impl Into<Value>, value: impl Into<Value>) -> Self {
+        self.elements.push((key.into(), value.into()));
+        self
+    }
+}
+
+impl Rule {
+    pub fn matches(self, left: Field, right: impl Into<Value>) -> Self {
+        self.matches_op(left, Op::Eq, right)
+    }
+
+    pub fn not_matches(self, left: Field, right: impl Into<Value>) -> Self {
+        self.matches_op(left, Op::Ne, right)
+    }
+
+    pub fn matches_op(mut self, left: Field, op: Op, right: impl Into<Value>) -> Self {
+        self.statements.push(Statement::Match { left, op, right: right.into() });
+        self
+    }
+
+    pub fn statement(mut self, statement: Statement) -> Self {
+        self.statements.push(statement);
+        self
+    }
+
+    pub fn counter(self) -> Self {
+        self.statement(Statement::Counter)
+    }
+
+    pub fn accept(self) -> Self {
+        self.statement(Statement::Accept)
+    }
+
+    pub fn drop(self) -> Self {
+        self.statement(Statement::Drop)
+    }
+
+    pub fn jump(self, chain: &str) -> Self {
+        self.statement(Statement::Jump(chain.to_string()))
+    }
+
+    pub fn comment(mut self, comment: &str) -> Self {
+        self.comment = Some(comment.to_string());
+        self
+    }
+
+    pub fn handle(mut self, handle: u64) -> Self {
+        self.handle = Some(handle);
+        self
+    }
+}
+
+impl Field {
+    pub fn meta(key: &str) -> Self {
+        Field::Meta(key.to_string())
+    }
+
+    pub fn payload(protocol: &str, field: &str) -> Self {
+        Field::Payload { protocol: protocol.to_string(), field: field.to_string() }
+    }
+
+    pub fn ct(key: &str) -> Self {
+        Field::Ct(key.to_string())
+    }
+
+    pub fn fib(flags: &[&str], result: &str) -> Self {
+        Field::Fib {
+            flags: flags.iter().map(|flag| flag.to_string()).collect(),
+            result: result.to_string(),
+        }
+    }
+}
+
+impl Value {
+    pub fn prefix(addr: &str, len: u8) -> Self {
+        Value::Prefix(addr.to_string(), len)
+    }
+
+    pub fn range(from: impl Into<Value>, to: impl Into<Value>) -> Self {
+        Value::Range(Box::new(from.into()), Box::new(to.into()))
+    }
+
+    pub fn set<V: Into<Value>>(elements: impl IntoIterator<Item = V>) -> Self {
+        Value::Set(elements.into_iter().map(Into::into).collect())
+    }
+
+    pub fn set_ref(name: &str) -> Self {
+        Value::SetRef(name.to_string())
+    }
+}
+
+impl From<&str> for Value {
+    fn from(value: &str) -> Self {
+        Value::Str(value.to_string())
+    }
+}
+
+impl From<String> for Value {
+    fn from(value: String) -> Self {
+        Value::Str(value)
+    }
+}
+
+impl From<u64> for Value {
+    fn from(value: u64) -> Self {
+        Value::Number(value)
+    }
+}
+
+impl From<u16> for Value {
+    fn from(value: u16) -> Self {
+        Value::Number(value as u64)
+    }
+}
+
+impl From<IpAddr> for Value {
+    fn from(value: IpAddr) -> Self {
+        Value::Str(value.to_string())
+    }
+}
+
+// ============================================================================
+// Keywords
+// ============================================================================
+
+impl Family {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Family::Inet => "inet",
+            Family::Ip => "ip",
+            Family::Ip6 => "ip6",
+            Family::Bridge => "bridge",
+            Family::Arp => "arp",
+            Family::Netdev => "netdev",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "inet" => Some(Family::Inet),
+            "ip" => Some(Family::Ip),
+            "ip6" => Some(Family::Ip6),
+            "bridge" => Some(Family::Bridge),
+            "arp" => Some(Family::Arp),
+            "netdev" => Some(Family::Netdev),
+            _ => None,
+        }
+    }
+}
+
+impl ChainType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChainType::Filter => "filter",
+            ChainType::Nat => "nat",
+            ChainType::Route => "route",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "filter" => Some(ChainType::Filter),
+            "nat" => Some(ChainType::Nat),
+            "route" => Some(ChainType::Route),
+            _ => None,
+        }
+    }
+}
+
+impl Hook {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Hook::Prerouting => "prerouting",
+            Hook::Input => "input",
+            Hook::Forward => "forward",
+            Hook::Output => "output",
+            Hook::Postrouting => "postrouting",
+            Hook::Ingress => "ingress",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "prerouting" => Some(Hook::Prerouting),
+            "input" => Some(Hook::Input),
+            "forward" => Some(Hook::Forward),
+            "output" => Some(Hook::Output),
+            "postrouting" => Some(Hook::Postrouting),
+            "ingress" => Some(Hook::Ingress),
+            _ => None,
+        }
+    }
+}
+
+impl Policy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Policy::Accept => "accept",
+            Policy::Drop => "drop",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "accept" => Some(Policy::Accept),
+            "drop" => Some(Policy::Drop),
+            _ => None,
+        }
+    }
+}
+
+impl SetType {
+    pub fn as_str(&self) -> &str {
+        match self {
+            SetType::Ipv4Addr => "ipv4_addr",
+            SetType::Ipv6Addr => "ipv6_addr",
+            SetType::EtherAddr => "ether_addr",
+            SetType::InetProto => "inet_proto",
+            SetType::InetService => "inet_service",
+            SetType::IfName => "ifname",
+            SetType::Mark => "mark",
+            SetType::Custom(name) => name,
+        }
+    }
+
+    pub fn from_name(name: &str) -> Self {
+        match name {
+            "ipv4_addr" => SetType::Ipv4Addr,
+            "ipv6_addr" => SetType::Ipv6Addr,
+            "ether_addr" => SetType::EtherAddr,
+            "inet_proto" => SetType::InetProto,
+            "inet_service" => SetType::InetService,
+            "ifname" => SetType::IfName,
+            "mark" => SetType::Mark,
+            name => SetType::Custom(name.to_string()),
+        }
+    }
+}
+
+impl SetFlag {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SetFlag::Constant => "constant",
+            SetFlag::Interval => "interval",
+            SetFlag::Timeout => "timeout",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "constant" => Some(SetFlag::Constant),
+            "interval" => Some(SetFlag::Interval),
+            "timeout" => Some(SetFlag::Timeout),
+            _ => None,
+        }
+    }
+}
+
+impl Op {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Op::Eq => "==",
+            Op::Ne => "!=",
+            Op::Lt => "<",
+            Op::Gt => ">",
+            Op::Le => "<=",
+            Op::Ge => ">=",
+            Op::In => "in",
+        }
+    }
+}
+
+// ============================================================================
+// Text rendering, the syntax of `nft -f`
+// ============================================================================
+
+impl Value {
+    pub fn to_text(&self) -> String {
+        match self {
+            Value::Str(value) => value.clone(),
+            Value::Number(value) => value.to_string(),
+            Value::Prefix(addr, len) => format!("{}/{}", addr, len),
+            Value::Range(from, to) => format!("{}-{}", from.to_text(), to.to_text()),
+            Value::Set(elements) => format!("{{ {} }}", elements.iter().map(Value::to_text).collect::<Vec<_>>().join(", ")),
+            Value::SetRef(name) => format!("@{}", name),
+        }
+    }
+}
+
+impl Field {
+    pub fn to_text(&self) -> String {
+        match self {
+            Field::Meta(key) => format!("meta {}", key),
+            Field::Payload { protocol, field } => format!("{} {}", protocol, field),
+            Field::Ct(key) => format!("ct {}", key),
+            Field::Fib { flags, result } => format!("fib {} {}", flags.join(" . "), result),
+        }
+    }
+}
+
+fn nat_family(family: Family, addr: &IpAddr) -> Option<&'static str> {
+    match (family, addr) {
+        (Family::Inet, IpAddr::V4(_)) => Some("ip"),
+        (Family::Inet, IpAddr::V6(_)) => Some("ip6"),
+        _ => None,
+    }
+}
+
+fn nat_target(addr: &IpAddr, port: Option<u16>) -> String {
+    match (addr, port) {
+        (IpAddr::V6(addr), Some(port)) => format!("[{}]:{}", addr, port),
+        (addr, Some(port)) => format!("{}:{}", addr, port),
+        (addr, None) => addr.to_string(),
+    }
+}
+
+impl Statement {
+    pub fn to_text(&self, family: Family) -> String {
+        match self {
+            Statement::Match { left, op, right } => match op {
+                Op::Eq | Op::In => format!("{} {}", left.to_text(), right.to_text()),
+                op => format!("{} {} {}", left.to_text(), op.as_str(), right.to_text()),
+            },
+            Statement::Counter => "counter".to_string(),
+            Statement::Accept => "accept".to_string(),
+            Statement::Drop => "drop".to_string(),
+            Statement::Reject => "reject".to_string(),
+            Statement::Return => "return".to_string(),
+            Statement::Jump(chain) => format!("jump {}", chain),
+            Statement::Goto(chain) => format!("goto {}", chain),
+            Statement::Masquerade => "masquerade".to_string(),
+            Statement::Snat { addr } => match nat_family(family, addr) {
+                Some(nat_family) => format!("snat {} to {}", nat_family, nat_target(addr, None)),
+                None => format!("snat to {}", nat_target(addr, None)),
+            },
+            Statement::Dnat { addr, port } => match nat_family(family, addr) {
+                Some(nat_family) => format!("dnat {} to {}", nat_family, nat_target(addr, *port)),
+                None => format!("dnat to {}", nat_target(addr, *port)),
+            },
+            Statement::Log { prefix } => format!("log prefix {:?}", prefix),
+        }
+    }
+}
+
+impl Table {
+    pub fn to_text(&self) -> String {
+        format!("table {} {}", self.family.as_str(), self.name)
+    }
+}
+
+impl Chain {
+    pub fn to_text(&self) -> String {
+        let chain = format!("chain {} {} {}", self.family.as_str(), self.table, self.name);
+        match &self.base {
+            Some(base) => {
+                let policy = base.policy
+                    .map(|policy| format!(" policy {} ;", policy.as_str()))
+                    .unwrap_or_default();
+                format!("{} {{ type {} hook {} priority {} ;{} }}", chain, base.chain_type.as_str(), base.hook.as_str(), base.priority, policy)
+            },
+            None => chain,
+        }
+    }
+}
+
+fn flags_text(flags: &[SetFlag]) -> String {
+    match flags.is_empty() {
+        true => String::new(),
+        false => format!(" flags {} ;", flags.iter().map(SetFlag::as_str).collect::<Vec<_>>().join(",")),
+    }
+}
+
+impl Set {
+    pub fn to_text(&self) -> String {
+        let elements = match self.elements.is_empty() {
+            true => String::new(),
+            false => format!(" elements = {} ;", Value::Set(self.elements.clone()).to_text()),
+        };
+        format!("set {} {} {} {{ type {} ;{}{} }}", self.family.as_str(), self.table, self.name, self.set_type.as_str(), flags_text(&self.flags), elements)
+    }
+}
+
+impl Map {
+    pub fn to_text(&self) -> String {
+        let elements = match self.elements.is_empty() {
+            true => String::new(),
+            false => format!(" elements = {{ {} }} ;", self.elements.iter()
+                .map(|(key, value)| format!("{} : {}", key.to_text(), value.to_text()))
+                .collect::<Vec<_>>()
+                .join(", ")),
+        };
+        format!("map {} {} {} {{ type {} : {} ;{}{} }}", self.family.as_str(), self.table, self.name, self.key_type.as_str(), self.value_type.as_str(), flags_text(&self.flags), elements)
+    }
+}
+
+impl Rule {
+    pub fn to_text(&self) -> String {
+        let mut text = format!("rule {} {} {}", self.family.as_str(), self.table, self.chain);
+        if let Some(handle) = self.handle {
+            text.push_str(&format!(" handle {}", handle));
+        }
+        for statement in &self.statements {
+            text.push(' ');
+            text.push_str(&statement.to_text(self.family));
+        }
+        if let Some(comment) = &self.comment {
+            text.push_str(&format!(" comment {:?}", comment));
+        }
+        text
+    }
+}
+
+// ============================================================================
+// JSON rendering, the schema of `nft -j`
+// ============================================================================
+
+impl Value {
+    pub fn to_json(&self) -> Json {
+        match self {
+            Value::Str(value) => json!(value),
+            Value::Number(value) => json!(value),
+            Value::Prefix(addr, len) => json!({ "prefix": { "addr": addr, "len": len } }),
+            Value::Range(from, to) => json!({ "range": [from.to_json(), to.to_json()] }),
+            Value::Set(elements) => json!({ "set": elements.iter().map(Value::to_json).collect::<Vec<_>>() }),
+            Value::SetRef(name) => json!(format!("@{}", name)),
+        }
+    }
+
+    /// Reads an expression as listed by `nft -j`, `None` for expressions this crate does not model.
+    pub fn from_json(value: &Json) -> Option<Self> {
+        match value {
+            Json::String(value) => match value.strip_prefix('@') {
+                Some(name) => Some(Value::SetRef(name.to_string())),
+                None => Some(Value::Str(value.clone())),
+            },
+            Json::Number(value) => value.as_u64().map(Value::Number),
+            Json::Array(elements) => elements.iter().map(Value::from_json).collect::<Option<Vec<_>>>().map(Value::Set),
+            Json::Object(object) => {
+                if let Some(prefix) = object.get("prefix") {
+                    let addr = prefix.get("addr")?.as_str()?;
+                    let len = prefix.get("len")?.as_u64()?;
+                    return Some(Value::Prefix(addr.to_string(), len as u8));
+                }
+                if let Some(Json::Array(range)) = object.get("range")
+                    && let [from, to] = range.as_slice()
+                {
+                    return Some(Value::range(Value::from_json(from)?, Value::from_json(to)?));
+                }
+                if let Some(set) = object.get("set") {
+                    return Value::from_json(set);
+                }
+                if let Some(elem) = object.get("elem") {
+                    return Value::from_json(elem.get("val")?);
+                }
+                None
+            },
+            _ => None,
+        }
+    }
+}
+
+impl Field {
+    pub fn to_json(&self) -> Json {
+        match self {
+            Field::Meta(key) => json!({ "meta": { "key": key } }),
+            Field::Payload { protocol, field } => json!({ "payload": { "protocol": protocol, "field": field } }),
+            Field::Ct(key) => json!({ "ct": { "key": key } }),
+            Field::Fib { flags, result } => json!({ "fib": { "result": result, "flags": flags } }),
+        }
+    }
+}
+
+impl Statement {
+    pub fn to_json(&self, family: Family) -> Json {
+        match self {
+            Statement::Match { left, op, right } => json!({
+                "match": { "op": op.as_str(), "left": left.to_json(), "right": right.to_json() }
+            }),
+            Statement::Counter => json!({ "counter": null }),
+            Statement::Accept => json!({ "accept": null }),
+            Statement::Drop => json!({ "drop": null }),
+            Statement::Reject => json!({ "reject": null }),
+            Statement::Return => json!({ "return": null }),
+            Statement::Jump(chain) => json!({ "jump": { "target": chain } }),
+            Statement::Goto(chain) => json!({ "goto": { "target": chain } }),
+            Statement::Masquerade => json!({ "masquerade": null }),
+            Statement::Snat { addr } => {
+                let mut snat = json!({ "addr": addr.to_string() });
+                if let Some(nat_family) = nat_family(family, addr) {
+                    snat["family"] = json!(nat_family);
+                }
+                json!({ "snat": snat })
+            },
+            Statement::Dnat { addr, port } => {
+                let mut dnat = json!({ "addr": addr.to_string() });
+                if let Some(port) = port {
+                    dnat["port"] = json!(port);
+                }
+                if let Some(nat_family) = nat_family(family, addr) {
+                    dnat["family"] = json!(nat_family);
+                }
+                json!({ "dnat": dnat })
+            },
+            Statement::Log { prefix } => json!({ "log": { "prefix": prefix } }),
+        }
+    }
+}
+
+impl Table {
+    pub fn to_json(&self) -> Json {
+        json!({ "family": self.family.as_str(), "name": self.name })
+    }
+}
+
+impl Chain {
+    pub fn to_json(&self) -> Json {
+        let mut chain = json!({ "family": self.family.as_str(), "table": self.table, "name": self.name });
+        if let Some(base) = &self.base {
+            chain["type"] = json!(base.chain_type.as_str());
+            chain["hook"] = json!(base.hook.as_str());
+            chain["prio"] = json!(base.priority);
+            if let Some(policy) = base.policy {
+                chain["policy"] = json!(policy.as_str());
+            }
+        }
+        chain
+    }
+}
+
+impl Set {
+    pub fn to_json(&self) -> Json {
+        let mut set = json!({
+            "family": self.family.as_str(),
+            "table": self.table,
+            "name": self.name,
+            "type": self.set_type.as_str(),
+        });
+        if !self.flags.is_empty() {
+            set["flags"] = json!(self.flags.iter().map(SetFlag::as_str).collect::<Vec<_>>());
+        }
+        if !self.elements.is_empty() {
+            set["elem"] = json!(self.elements.iter().map(Value::to_json).collect::<Vec<_>>());
+        }
+        set
+    }
+}
+
+impl Map {
+    pub fn to_json(&self) -> Json {
+        let mut map = json!({
+            "family": self.family.as_str(),
+            "table": self.table,
+            "name": self.name,
+            "type": self.key_type.as_str(),
+            "map": self.value_type.as_str(),
+        });
+        if !self.flags.is_empty() {
+            map["flags"] = json!(self.flags.iter().map(SetFlag::as_str).collect::<Vec<_>>());
+        }
+        if !self.elements.is_empty() {
+            map["elem"] = json!(self.elements.iter()
+                .map(|(key, value)| json!([key.to_json(), value.to_json()]))
+                .collect::<Vec<_>>());
+        }
+        map
+    }
+}
+
+impl Rule {
+    pub fn to_json(&self) -> Json {
+        let mut rule = json!({
+            "family": self.family.as_str(),
+            "table": self.table,
+            "chain": self.chain,
+            "expr": self.statements.iter().map(|statement| statement.to_json(self.family)).collect::<Vec<_>>(),
+        });
+        if let Some(comment) = &self.comment {
+            rule["comment"] = json!(comment);
+        }
+        if let Some(handle) = self.handle {
+            rule["handle"] = json!(handle);
+        }
+        rule
+    }
+}
@@ -49,11 +49,24 @@ impl KVM {
         self
     }
 
+    /// Waits for an incoming migration on `uri` instead of booting the guest.
+    pub fn incoming(mut self, uri: &str) -> Self {
+        self.args.push("-incoming".to_string());
+        self.args.push(uri.to_string());
+        self
+    }
+
     pub fn nodefaults(mut self) -> Self {
         self.args.push("-nodefaults".to_string());
         self
     }
 
+    pub fn object_secret<P: AsRef<Path>>(mut self, id: &str, file: P) -> Self {
+        self.args.push("-object".to_string());
+        self.args.push(secret_object(id, &file.as_ref().to_string_lossy()));
+        self
+    }
+
     pub fn name(mut self, name: &str) -> Self {
         self.args.push("-name".to_string());
         self.args.push(name.to_string());
@@ -61,6 +74,7 @@ impl KVM {
     }
 }
 
+#[derive(Debug, Clone, Copy)]
 pub enum ImgFormat {
     Qcow2,
     Raw,
@@ -94,4 +108,129 @@ impl Img {
             .arg(path)
             .arg(&format!("{}M", size))
     }
+
+    pub fn shrink(self, path: &str, size: u64) -> Self {
+        self.arg("resize")
+            .arg("--shrink")
+            .arg(path)
+            .arg(&format!("{}M", size))
+    }
+
+    pub fn create_encrypted(self, path: &str, size: u64, secret_id: &str, secret_file: &str) -> Self {
+        self.arg("create")
+            .arg("--object")
+            .arg(&secret_object(secret_id, secret_file))
+            .arg("-f")
+            .arg("qcow2")
+            .arg("-o")
+            .arg(&format!("encrypt.format=luks,encrypt.key-secret={}", secret_id))
+            .arg(path)
+            .arg(&format!("{}M", size))
+    }
+
+    pub fn convert_encrypted(self, src: &str, dest: &str, secret_id: &str, secret_file: &str) -> Self {
+        self.arg("convert")
+            .arg("--object")
+            .arg(&secret_object(secret_id, secret_file))
+            .arg("-O")
+            .arg("qcow2")
+            .arg("-o")
+            .arg(&format!("encrypt.format=luks,encrypt.key-secret={}", secret_id))
+            .arg(src)
+            .arg(dest)
+    }
+
+    pub fn resize_encrypted(self, path: &str, size: u64, shrink: bool, secret_id: &str, secret_file: &str) -> Self {
+        let img = self.arg("resize")
+            .arg("--object")
+            .arg(&secret_object(secret_id, secret_file))
+            .arg("--image-opts");
+        let img = if shrink { img.arg("--shrink") } else { img };
+        img.arg(&encrypted_image_opts(path, secret_id))
+            .arg(&format!("{}M", size))
+    }
+
+    /// Adds a keyslot unlocked by `new_secret` to a LUKS encrypted qcow2 image.
+    pub fn add_key(self, path: &str, old_secret: (&str, &str), new_secret: (&str, &str)) -> Self {
+        self.arg("amend")
+            .arg("--object")
+            .arg(&secret_object(old_secret.0, old_secret.1))
+            .arg("--object")
+            .arg(&secret_object(new_secret.0, new_secret.1))
+            .arg("--image-opts")
+            .arg(&encrypted_image_opts(path, old_secret.0))
+            .arg("-o")
+            .arg(&format!("encrypt.state=active,encrypt.new-secret={}", new_secret.0))
+    }
+
+    /// Erases the keyslots unlocked by `old_secret`, opening the image with `new_secret`.
+    pub fn remove_key(self, path: &str, old_secret: (&str, &str), new_secret: (&str, &str)) -> Self {
+        self.arg("amend")
+            .arg("--object")
+            .arg(&secret_object(old_secret.0, old_secret.1))
+            .arg("--object")
+            .arg(&secret_object(new_secret.0, new_secret.1))
+            .arg("--image-opts")
+            .arg(&encrypted_image_opts(path, new_secret.0))
+            .arg("-o")
+            .arg(&format!("encrypt.state=inactive,encrypt.old-secret={}", old_secret.0))
+    }
+
+    /// Compressed qcow2 copy of `src`, decrypting it with `secret` if given.
+    pub fn convert_compressed(self, src: &str, dest: &str, secret: Option<(&str, &str)>) -> Self {
+        let img = self.arg("convert")
+            .arg("-c")
+            .arg("-O")
+            .arg("qcow2");
+        let img = match secret {
+            Some((secret_id, secret_file)) => img
+                .arg("--object")
+                .arg(&secret_object(secret_id, secret_file))
+                .arg("--image-opts")
+                .arg(&encrypted_image_opts(src, secret_id)),
+            None => img.arg(src),
+        };
+        img.arg(dest)
+    }
+
+    /// Empty qcow2 overlay of `backing`. `size` is in bytes.
+    pub fn create_overlay(self, path: &str, backing: &str, backing_format: ImgFormat, size: u64) -> Self {
+        self.arg("create")
+            .arg("-f")
+            .arg("qcow2")
+            .arg("-b")
+            .arg(backing)
+            .arg("-F")
+            .arg(match backing_format {
+                ImgFormat::Qcow2 => "qcow2",
+                ImgFormat::Raw => "raw",
+            })
+            .arg(path)
+            .arg(&size.to_string())
+    }
+
+    /// Copies the data of the backing chain into a qcow2 overlay and detaches it.
+    pub fn flatten(self, path: &str) -> Self {
+        self.arg("rebase")
+            .arg("-f")
+            .arg("qcow2")
+            .arg("-b")
+            .arg("")
+            .arg(path)
+    }
+
+    pub fn info(self, path: &str) -> Self {
+        self.arg("info")
+            .arg("--output=json")
+            .arg("--force-share")
+            .arg(path)
+    }
+}
+
+fn secret_object(id: &str, file: &str) -> String {
+    format!("secret,id={},file={}", id, file)
+}
+
+fn encrypted_image_opts(path: &str, secret_id: &str) -> String {
+    format!("driver=qcow2,file.filename={},encrypt.key-secret={}", path, secret_id)
 }
@@ -1,10 +1,8 @@
-use std::path::Path;
-
-use vm_types::vm::DiskMediaKind;
+use vm_types::vm::{DiskMediaKind, NicModel};
 
 use crate::KVM;
 
-struct ArgValue {
+pub(crate) struct ArgValue {
     parts: Vec<String>,
 }
 
@@ -60,41 +58,137 @@ impl KVM {
             )
     }
 
+    pub fn virtio_scsi_controller(self, id: &str) -> Self {
+        self
+            .arg("-device")
+            .arg(&ArgValue::new()
+                .arg("virtio-scsi-pci")
+                .key_value("id", id)
+                .build()
+            )
+    }
+
+    pub fn scsi_hd(self, drive_id: &str, controller: &str, lun: u32, boot_index: Option<u32>) -> Self {
+        self
+            .arg("-device")
+            .arg(&ArgValue::new()
+                .arg("scsi-hd")
+                .key_value("drive", drive_id)
+                .key_value("bus", format!("{}.0", controller))
+                .key_value("lun", lun)
+                .key_value_opt("bootindex", boot_index).build()
+            )
+    }
+
+    pub fn nvme(self, drive_id: &str, serial: &str, boot_index: Option<u32>) -> Self {
+        self
+            .arg("-device")
+            .arg(&ArgValue::new()
+                .arg("nvme")
+                .key_value("drive", drive_id)
+                .key_value("serial", serial)
+                .key_value_opt("bootindex", boot_index).build()
+            )
+    }
+
+    pub fn xhci_controller(self, id: &str) -> Self {
+        self
+            .arg("-device")
+            .arg(&ArgValue::new()
+                .arg("qemu-xhci")
+                .key_value("id", id)
+                .build()
+            )
+    }
+
+    pub fn usb_storage(self, drive_id: &str, controller: &str, boot_index: Option<u32>) -> Self {
+        self
+            .arg("-device")
+            .arg(&ArgValue::new()
+                .arg("usb-storage")
+                .key_value("drive", drive_id)
+                .key_value("bus", format!("{}.0", controller))
+                .key_value_opt("bootindex", boot_index).build()
+            )
+    }
+
     pub fn virtio_vga(self) -> Self {
         self
             .arg("-device")
             .arg("virtio-vga")
     }
 
-    pub fn netdev_tap<T: AsRef<Path>, S: AsRef<Path>>(self, id: &str, script: Option<T>, downscript: Option<S>, ifname: &str) -> Self {
-        let script = match script {
-            Some(s) => s.as_ref().to_string_lossy().to_string(),
-            None => "no".to_string(),
-        };
-        let downscript = match downscript {
-            Some(d) => d.as_ref().to_string_lossy().to_string(),
-            None => "no".to_string(),
-        };
+    /// Opens the existing TAP device `ifname`, QEMU runs no scripts on it.
+    pub fn netdev_tap(self, id: &str, ifname: &str, vhost: bool, queues: Option<u32>) -> Self {
         self
             .arg("-netdev")
             .arg(&ArgValue::new()
                 .arg("tap")
                 .key_value("ifname", ifname)
                 .key_value("id", id)
-                .key_value("script", script)
-                .key_value("downscript", downscript)
+                .key_value("script", "no")
+                .key_value("downscript", "no")
+                .key_value_opt("vhost", vhost.then_some("on"))
+                .key_value_opt("queues", queues)
+                .build()
+            )
+    }
+
+    /// Connects the guest to every netdev joining the multicast group `group` (`address:port`)
+    /// on the host interface with the address `localaddr`.
+    pub fn netdev_socket_mcast(self, id: &str, group: &str, localaddr: &str) -> Self {
+        self
+            .arg("-netdev")
+            .arg(&ArgValue::new()
+                .arg("socket")
+                .key_value("id", id)
+                .key_value("mcast", group)
+                .key_value("localaddr", localaddr)
                 .build()
             )
     }
 
-    pub fn network_device(self, netdev_id: &str, mac: &str) -> Self {
+    /// Point-to-point link sending datagrams from `local` to `remote`, both `address:port`.
+    pub fn netdev_socket_udp(self, id: &str, local: &str, remote: &str) -> Self {
+        self
+            .arg("-netdev")
+            .arg(&ArgValue::new()
+                .arg("socket")
+                .key_value("id", id)
+                .key_value("udp", remote)
+                .key_value("localaddr", local)
+                .build()
+            )
+    }
+
+    pub fn network_device(self, netdev_id: &str, mac: &str, model: NicModel) -> Self {
+        let device = match model {
+            NicModel::Virtio => "virtio-net-pci",
+            NicModel::E1000 => "e1000",
+            NicModel::Rtl8139 => "rtl8139",
+        };
+        self
+            .arg("-device")
+            .arg(&ArgValue::new()
+                .arg(device)
+                .key_value("netdev", netdev_id)
+                .key_value("mac", mac)
+                .build()
+            )
+    }
+
+    /// virtio-net device with `queues` queue pairs when set, and the MTU advertised to the guest.
+    pub fn virtio_net_device(self, netdev_id: &str, mac: &str, queues: Option<u32>, mtu: Option<u32>) -> Self {
         self
             .arg("-device")
             .arg(&ArgValue::new()
                 .arg("virtio-net-pci")
                 .key_value("netdev", netdev_id)
                 .key_value("mac", mac)
+                .key_value_opt("mq", queues.map(|_| "on"))
+                .key_value_opt("vectors", queues.map(|queues| 2 * queues + 2))
+                .key_value_opt("host_mtu", mtu)
                 .build()
             )
     }
-}
\ No newline at end of file
+}
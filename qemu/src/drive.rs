@@ -1,12 +1,52 @@
 use std::path::Path;
 
-use crate::KVM;
+use vm_types::vm::{AioMode, CacheMode, DetectZeroes, DiscardMode, DriveFormat, DriveOptions};
+
+use crate::{KVM, device::ArgValue};
 
 
 impl KVM {
-    pub fn drive<P: AsRef<Path>>(self, id: &str, filename: P) -> Self {
+    pub fn drive<P: AsRef<Path>>(self, id: &str, filename: Option<P>, options: &DriveOptions, key_secret: Option<&str>) -> Self {
+        let limits = &options.io_limits;
         self
             .arg("-drive")
-            .arg(&format!("file={},if=none,id={}", filename.as_ref().to_string_lossy(), id))
+            .arg(&ArgValue::new()
+                .key_value_opt("file", filename.map(|filename| filename.as_ref().to_string_lossy().to_string()))
+                .key_value("if", "none")
+                .key_value("id", id)
+                .key_value_opt("format", options.format.map(|format| match format {
+                    DriveFormat::Raw => "raw",
+                    DriveFormat::Qcow2 => "qcow2",
+                }))
+                .key_value_opt("cache", options.cache.map(|cache| match cache {
+                    CacheMode::None => "none",
+                    CacheMode::Writeback => "writeback",
+                    CacheMode::Writethrough => "writethrough",
+                    CacheMode::Directsync => "directsync",
+                    CacheMode::Unsafe => "unsafe",
+                }))
+                .key_value_opt("aio", options.aio.map(|aio| match aio {
+                    AioMode::Threads => "threads",
+                    AioMode::Native => "native",
+                    AioMode::IoUring => "io_uring",
+                }))
+                .key_value_opt("discard", options.discard.map(|discard| match discard {
+                    DiscardMode::Ignore => "ignore",
+                    DiscardMode::Unmap => "unmap",
+                }))
+                .key_value_opt("detect-zeroes", options.detect_zeroes.map(|detect_zeroes| match detect_zeroes {
+                    DetectZeroes::Off => "off",
+                    DetectZeroes::On => "on",
+                    DetectZeroes::Unmap => "unmap",
+                }))
+                .key_value_opt("throttling.iops-total", limits.iops_total)
+                .key_value_opt("throttling.iops-read", limits.iops_read)
+                .key_value_opt("throttling.iops-write", limits.iops_write)
+                .key_value_opt("throttling.bps-total", limits.bps_total)
+                .key_value_opt("throttling.bps-read", limits.bps_read)
+                .key_value_opt("throttling.bps-write", limits.bps_write)
+                .key_value_opt("encrypt.key-secret", key_secret)
+                .build()
+            )
     }
-}
\ No newline at end of file
+}
@@ -13,6 +13,8 @@ pub enum Error {
     ChannelClosed,
     #[error("QMP handshake missing greeting")]
     HandshakeMissing,
+    #[error("QMP command failed: {0}: {1}")]
+    Command(String, String),
 }
 
 
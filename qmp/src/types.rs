@@ -25,11 +25,20 @@ pub struct CommandError {
 pub struct CommandResponse {
     #[serde(default)]
     pub id: Option<Value>,
-    #[serde(rename = "return")]
+    #[serde(rename = "return", default)]
     pub result: Value,
     pub error: Option<CommandError>,
 }
 
+impl CommandResponse {
+    pub fn into_result(self) -> crate::Result<Value> {
+        match self.error {
+            Some(error) => Err(crate::Error::Command(error.class, error.desc)),
+            None => Ok(self.result),
+        }
+    }
+}
+
 #[derive(Debug, Deserialize)]
 pub struct Event {
     pub event: String,
@@ -55,6 +64,17 @@ pub struct CommandWithArgs {
     pub arguments: Option<Value>,
 }
 
+/// Arguments of `block_set_io_throttle`, zero disables a limit.
+#[derive(Debug, Default, Serialize)]
+pub struct IoThrottle {
+    pub bps: u64,
+    pub bps_rd: u64,
+    pub bps_wr: u64,
+    pub iops: u64,
+    pub iops_rd: u64,
+    pub iops_wr: u64,
+}
+
 #[derive(Debug, Serialize)]
 #[serde(untagged)]
 pub enum InvokeCommand {
@@ -84,10 +104,206 @@ impl InvokeCommand {
         InvokeCommand::with_args("set_password", args)
     }
 
+    pub fn block_resize(device: &str, size: u64) -> Self {
+        let args = serde_json::json!({
+            "device": device,
+            "size": size,
+        });
+        InvokeCommand::with_args("block_resize", args)
+    }
+
+    pub fn block_set_io_throttle(device: &str, throttle: &IoThrottle) -> Self {
+        let mut args = serde_json::to_value(throttle).unwrap();
+        args["device"] = Value::String(device.to_string());
+        InvokeCommand::with_args("block_set_io_throttle", args)
+    }
+
+    pub fn blockdev_change_medium(device: &str, filename: &str) -> Self {
+        let args = serde_json::json!({
+            "device": device,
+            "filename": filename,
+            "format": "raw",
+        });
+        InvokeCommand::with_args("blockdev-change-medium", args)
+    }
+
+    pub fn set_link(name: &str, up: bool) -> Self {
+        let args = serde_json::json!({
+            "name": name,
+            "up": up,
+        });
+        InvokeCommand::with_args("set_link", args)
+    }
+
+    pub fn eject(device: &str) -> Self {
+        let args = serde_json::json!({
+            "device": device,
+            "force": true,
+        });
+        InvokeCommand::with_args("eject", args)
+    }
+
+    /// Runs `actions` atomically, each action is given as the command it mirrors.
+    fn transaction_actions(actions: Vec<InvokeCommand>) -> Vec<Value> {
+        actions.into_iter().map(|action| match action {
+            InvokeCommand::Empty(command) => serde_json::json!({
+                "type": command.execute,
+            }),
+            InvokeCommand::WithArgs(command) => serde_json::json!({
+                "type": command.execute,
+                "data": command.arguments,
+            }),
+        }).collect()
+    }
+
+    pub fn transaction(actions: Vec<InvokeCommand>) -> Self {
+        let actions = InvokeCommand::transaction_actions(actions);
+        InvokeCommand::with_args("transaction", serde_json::json!({ "actions": actions }))
+    }
+
+    /// Transaction whose block jobs succeed or fail together.
+    pub fn grouped_transaction(actions: Vec<InvokeCommand>) -> Self {
+        let args = serde_json::json!({
+            "actions": InvokeCommand::transaction_actions(actions),
+            "properties": { "completion-mode": "grouped" },
+        });
+        InvokeCommand::with_args("transaction", args)
+    }
+
+    /// Full backup of `device` into a new compressed qcow2 image, the job is kept until dismissed.
+    pub fn drive_backup(job_id: &str, device: &str, target: &str) -> Self {
+        let args = serde_json::json!({
+            "job-id": job_id,
+            "device": device,
+            "target": target,
+            "format": "qcow2",
+            "sync": "full",
+            "mode": "absolute-paths",
+            "compress": true,
+            "auto-dismiss": false,
+        });
+        InvokeCommand::with_args("drive-backup", args)
+    }
+
+    /// Backup of the clusters marked in `bitmap` into an existing qcow2 image backed by the previous backup.
+    pub fn drive_backup_incremental(job_id: &str, device: &str, target: &str, bitmap: &str) -> Self {
+        let args = serde_json::json!({
+            "job-id": job_id,
+            "device": device,
+            "target": target,
+            "format": "qcow2",
+            "sync": "incremental",
+            "bitmap": bitmap,
+            "mode": "existing",
+            "compress": true,
+            "auto-dismiss": false,
+        });
+        InvokeCommand::with_args("drive-backup", args)
+    }
+
+    pub fn block_dirty_bitmap_add(node: &str, name: &str, persistent: bool) -> Self {
+        let args = serde_json::json!({
+            "node": node,
+            "name": name,
+            "persistent": persistent,
+        });
+        InvokeCommand::with_args("block-dirty-bitmap-add", args)
+    }
+
+    pub fn block_dirty_bitmap_clear(node: &str, name: &str) -> Self {
+        InvokeCommand::with_args("block-dirty-bitmap-clear", serde_json::json!({ "node": node, "name": name }))
+    }
+
+    pub fn block_dirty_bitmap_remove(node: &str, name: &str) -> Self {
+        InvokeCommand::with_args("block-dirty-bitmap-remove", serde_json::json!({ "node": node, "name": name }))
+    }
+
+    pub fn query_block() -> Self {
+        InvokeCommand::empty("query-block")
+    }
+
+    pub fn migrate_set_capabilities(capabilities: &[(&str, bool)]) -> Self {
+        let capabilities = capabilities.iter().map(|(capability, state)| serde_json::json!({
+            "capability": capability,
+            "state": state,
+        })).collect::<Vec<_>>();
+        InvokeCommand::with_args("migrate-set-capabilities", serde_json::json!({ "capabilities": capabilities }))
+    }
+
+    pub fn migrate(uri: &str) -> Self {
+        InvokeCommand::with_args("migrate", serde_json::json!({ "uri": uri }))
+    }
+
+    pub fn query_migrate() -> Self {
+        InvokeCommand::empty("query-migrate")
+    }
+
+    /// Resumes a migration paused in `state`, e.g. `pre-switchover`.
+    pub fn migrate_continue(state: &str) -> Self {
+        InvokeCommand::with_args("migrate-continue", serde_json::json!({ "state": state }))
+    }
+
+    pub fn migrate_cancel() -> Self {
+        InvokeCommand::empty("migrate_cancel")
+    }
+
+    pub fn nbd_server_start(host: &str, port: u16) -> Self {
+        let args = serde_json::json!({
+            "addr": {
+                "type": "inet",
+                "data": { "host": host, "port": port.to_string() },
+            },
+        });
+        InvokeCommand::with_args("nbd-server-start", args)
+    }
+
+    pub fn nbd_server_add(device: &str, writable: bool) -> Self {
+        InvokeCommand::with_args("nbd-server-add", serde_json::json!({ "device": device, "writable": writable }))
+    }
+
+    pub fn nbd_server_stop() -> Self {
+        InvokeCommand::empty("nbd-server-stop")
+    }
+
+    /// Mirrors `device` into an existing raw `target`, the job turns ready once both are in sync.
+    pub fn drive_mirror(job_id: &str, device: &str, target: &str) -> Self {
+        let args = serde_json::json!({
+            "job-id": job_id,
+            "device": device,
+            "target": target,
+            "format": "raw",
+            "sync": "full",
+            "mode": "existing",
+            "auto-dismiss": false,
+        });
+        InvokeCommand::with_args("drive-mirror", args)
+    }
+
+    /// Cancels a block job. A ready mirror job completes without switching to the target.
+    pub fn block_job_cancel(device: &str, force: bool) -> Self {
+        InvokeCommand::with_args("block-job-cancel", serde_json::json!({ "device": device, "force": force }))
+    }
+
+    pub fn query_jobs() -> Self {
+        InvokeCommand::empty("query-jobs")
+    }
+
+    pub fn job_dismiss(id: &str) -> Self {
+        InvokeCommand::with_args("job-dismiss", serde_json::json!({ "id": id }))
+    }
+
     pub fn reboot() -> Self {
         InvokeCommand::Empty(EmptyCommand { execute: "system_reset".to_string() })
     }
 
+    pub fn stop() -> Self {
+        InvokeCommand::empty("stop")
+    }
+
+    pub fn cont() -> Self {
+        InvokeCommand::empty("cont")
+    }
+
     pub fn quit() -> Self {
         InvokeCommand::Empty(EmptyCommand { execute: "quit".to_string() })
     }
@@ -0,0 +1,324 @@
+use std::{os::unix::fs::{DirBuilderExt, PermissionsExt}, path::{Path, PathBuf}};
+
+use qemu::base::ImgFormat;
+use serde::{Deserialize, Serialize};
+use tokio::process::Command;
+
+use crate::{builders::VmLaunchRequestBuilder, context::YaveContext, ipam::IpamManager, launch::{BitmapState, DriveBackupMode, DriveBackupTarget}, net::NetworkManager, registry::{AddIpAddress, AddressMode, BackupRecord, CreateDrive, CreateNetworkInterface, CreateVirtualMachine, DriveRecord, IpAddressRecord, NetworkInterfaceRecord, VirtualMachineRecord}};
+
+const MANIFEST_VERSION: u32 = 1;
+const MANIFEST_FILE: &str = "manifest.json";
+
+/// Registry rows of a backed up VM, stored as `manifest.json` next to the drive images.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct BackupManifest {
+    pub version: u32,
+    pub vm: VirtualMachineRecord,
+    pub drives: Vec<DriveRecord>,
+    pub network_interfaces: Vec<NetworkInterfaceRecord>,
+    #[serde(alias = "ipv4_addresses")]
+    pub ip_addresses: Vec<IpAddressRecord>,
+}
+
+fn image_name(drive_id: &str) -> String {
+    format!("{}.qcow2", drive_id)
+}
+
+pub struct BackupManager<'ctx> {
+    context: &'ctx YaveContext,
+}
+
+impl<'ctx> BackupManager<'ctx> {
+    pub fn new(context: &'ctx YaveContext) -> Self {
+        Self { context }
+    }
+
+    async fn tar(&self, args: &[&std::ffi::OsStr]) -> Result<(), crate::Error> {
+        let status = Command::new(&self.context.config().cli.tar)
+            .args(args)
+            .status()
+            .await?;
+        if !status.success() {
+            return Err(crate::Error::Command(format!("{} exited with {}", self.context.config().cli.tar, status)));
+        }
+        Ok(())
+    }
+
+    /// Writes a tar archive with the VM's registry rows and compressed copies of its disks.
+    /// Running VMs are copied at a single point in time through QMP `drive-backup`.
+    /// Encrypted drives are stored decrypted, so the archive is only readable by its owner.
+    pub async fn backup(&self, vm_id: &str, out: &Path) -> Result<(), crate::Error> {
+        let registry = self.context.registry();
+        let storage = self.context.storage();
+        let (vm, drives, network_interfaces, ip_addresses) = registry.get_vm_full(vm_id).await?;
+        let workdir = tempfile::Builder::new()
+            .prefix(".yave-backup")
+            .tempdir_in(out.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new(".")))?;
+
+        let targets = drives.iter()
+            .filter(|drive| !drive.is_cdrom())
+            .map(|drive| (drive.id.clone(), workdir.path().join(image_name(&drive.id))))
+            .collect::<Vec<(String, PathBuf)>>();
+        let launch_request = VmLaunchRequestBuilder::new(self.context).build(vm_id).await?;
+        let runtime = self.context.runtime();
+        if runtime.is_running(&launch_request).await? {
+            runtime.backup_drives(&launch_request, &targets).await?;
+        } else {
+            for (drive_id, target) in &targets {
+                storage.export_drive(vm_id, drive_id, target).await?;
+            }
+        }
+
+        let manifest = BackupManifest {
+            version: MANIFEST_VERSION,
+            vm,
+            drives,
+            network_interfaces,
+            ip_addresses,
+        };
+        std::fs::write(workdir.path().join(MANIFEST_FILE), serde_json::to_vec_pretty(&manifest)?)?;
+
+        self.tar(&["-cf".as_ref(), out.as_os_str(), "-C".as_ref(), workdir.path().as_os_str(), ".".as_ref()]).await?;
+        std::fs::set_permissions(out, std::fs::Permissions::from_mode(0o600))?;
+        log::debug!("Backed up VM {} to {:?}", vm_id, out);
+        Ok(())
+    }
+
+    /// Recreates a VM from an archive written by [`BackupManager::backup`] under `vm_id`.
+    /// Interface names and the VNC display are allocated anew. The interfaces keep their MAC
+    /// addresses unless another interface holds one, e.g. that of the VM the archive was taken
+    /// of, which then gets a generated one; SLAAC addresses are derived from the MAC the
+    /// interface ends up with. IP addresses still assigned to another interface are skipped.
+    /// If importing the drives fails, the VM is removed again.
+    pub async fn restore(&self, archive: &Path, vm_id: &str) -> Result<VirtualMachineRecord, crate::Error> {
+        let registry = self.context.registry();
+        let storage = self.context.storage();
+        if registry.get_vm_by_id(vm_id).await.is_ok() {
+            return Err(crate::Error::VMExists(vm_id.to_string()));
+        }
+        storage.ensure_storage_dir()?;
+        let workdir = tempfile::Builder::new()
+            .prefix(".yave-restore")
+            .tempdir_in(storage.path_for_vm(vm_id).parent().unwrap_or(Path::new(".")))?;
+        self.tar(&["-xf".as_ref(), archive.as_os_str(), "-C".as_ref(), workdir.path().as_os_str()]).await?;
+
+        let manifest_path = workdir.path().join(MANIFEST_FILE);
+        if !manifest_path.exists() {
+            return Err(crate::Error::InvalidBackup("missing manifest".to_string()));
+        }
+        let manifest: BackupManifest = serde_json::from_slice(&std::fs::read(manifest_path)?)?;
+        if manifest.version != MANIFEST_VERSION {
+            return Err(crate::Error::InvalidBackup(format!("unsupported version {}", manifest.version)));
+        }
+
+        let mut free_macs = vec![];
+        for nic in &manifest.network_interfaces {
+            let in_use = registry.mac_address_in_use(&nic.mac_address).await?;
+            free_macs.push((!in_use).then(|| nic.mac_address.clone()));
+        }
+        let vm = registry.create_vm(CreateVirtualMachine {
+            id: vm_id.to_string(),
+            hostname: manifest.vm.hostname.clone(),
+            vcpu: manifest.vm.vcpu,
+            memory: manifest.vm.memory,
+            ovmf: manifest.vm.ovmf,
+            network_interfaces: manifest.network_interfaces.iter()
+                .zip(free_macs)
+                .map(|(nic, mac_address)| CreateNetworkInterface {
+                    id: nic.id.clone(),
+                    network: nic.network.clone(),
+                    vlan: nic.vlan,
+                    options: nic.options.clone(),
+                    mac_address,
+                })
+                .collect(),
+            drives: manifest.drives.iter()
+                .map(|drive| CreateDrive {
+                    id: drive.id.clone(),
+                    drive_bus: drive.drive_bus.clone(),
+                    size: drive.size,
+                    options: drive.options.clone(),
+                    encrypted: drive.encrypted,
+                    media: drive.media.clone(),
+                })
+                .collect(),
+        }).await?;
+
+        if let Err(err) = self.restore_contents(workdir.path(), &manifest, vm_id).await {
+            storage.delete_vm(vm_id).await?;
+            registry.delete_vm(vm_id).await?;
+            return Err(err);
+        }
+        log::debug!("Restored VM {} from {:?}", vm_id, archive);
+        Ok(vm)
+    }
+
+    /// Imports the drive images and addresses of a restored VM whose registry rows exist.
+    async fn restore_contents(&self, workdir: &Path, manifest: &BackupManifest, vm_id: &str) -> Result<(), crate::Error> {
+        let registry = self.context.registry();
+        let storage = self.context.storage();
+        for drive in manifest.drives.iter().filter(|drive| !drive.is_cdrom()) {
+            let image = workdir.join(image_name(&drive.id));
+            if !image.exists() {
+                return Err(crate::Error::InvalidBackup(format!("missing image of drive {}", drive.id)));
+            }
+            storage.import_drive(vm_id, &drive.id, &image, drive.options.format, drive.encrypted).await?;
+        }
+
+        let nics = registry.get_network_interfaces_by_vm_id(vm_id).await?;
+        let mut addresses = manifest.ip_addresses.iter().collect::<Vec<_>>();
+        addresses.sort_by_key(|addr| !addr.is_default);
+        for addr in addresses {
+            let old_nic = manifest.network_interfaces.iter().find(|nic| nic.ifname == addr.ifname);
+            let new_nic = old_nic.and_then(|old_nic| nics.iter().find(|nic| nic.id == old_nic.id));
+            let Some(new_nic) = new_nic else {
+                continue;
+            };
+            let address = match addr.mode {
+                AddressMode::Slaac => addr.address.parse().ok()
+                    .and_then(|prefix| crate::net::eui64(prefix, &new_nic.mac_address))
+                    .map(|address| address.to_string())
+                    .unwrap_or(addr.address.clone()),
+                AddressMode::Static => addr.address.clone(),
+            };
+            if registry.get_ip_by_address(&address).await?.is_some() {
+                log::warn!("Skipping IP address {} of restored VM {}, already in use", address, vm_id);
+                continue;
+            }
+            registry.add_ip_address(AddIpAddress {
+                ifname: new_nic.ifname.clone(),
+                address,
+                family: addr.family,
+                prefix_len: addr.prefix_len,
+                gateway: addr.gateway.clone(),
+                mode: addr.mode,
+            }).await?;
+        }
+        let ipam = IpamManager::new(self.context);
+        let nm = NetworkManager::new(self.context);
+        for nic in &nics {
+            if ipam.allocate(&nic.ifname).await?.is_none() {
+                nm.addresses_changed(&nic.ifname).await?;
+            }
+        }
+        Ok(())
+    }
+
+    fn generation_dir(&self, vm_id: &str, backup_id: i64) -> PathBuf {
+        Path::new(&self.context.config().backup.path).join(vm_id).join(backup_id.to_string())
+    }
+
+    /// Takes a new backup generation of the disk drives of a VM below `backup.path`.
+    /// On a running VM, qcow2 drives carrying a consistent backup bitmap are copied
+    /// incrementally into an overlay of their previous generation; all other drives, and
+    /// every drive of a stopped VM, start a new chain with a full copy.
+    pub async fn create_generation(&self, vm_id: &str) -> Result<BackupRecord, crate::Error> {
+        let registry = self.context.registry();
+        let backup = registry.create_backup(vm_id).await?;
+        let dir = self.generation_dir(vm_id, backup.id);
+        let result = self.write_generation(vm_id, backup.id, &dir).await;
+        if let Err(err) = result {
+            if dir.exists() {
+                std::fs::remove_dir_all(&dir)?;
+            }
+            registry.delete_backup(backup.id).await?;
+            return Err(err);
+        }
+        log::debug!("Created backup generation {} of VM {}", backup.id, vm_id);
+        self.prune_generations(vm_id).await?;
+        Ok(backup)
+    }
+
+    async fn write_generation(&self, vm_id: &str, backup_id: i64, dir: &Path) -> Result<(), crate::Error> {
+        let registry = self.context.registry();
+        let storage = self.context.storage();
+        let runtime = self.context.runtime();
+        let drives = registry.get_drives_by_vm_id(vm_id).await?;
+        let launch_request = VmLaunchRequestBuilder::new(self.context).build(vm_id).await?;
+        std::fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(dir)?;
+
+        let mut targets = vec![];
+        if runtime.is_running(&launch_request).await? {
+            let states = runtime.drive_backup_states(&launch_request).await?;
+            for drive in drives.iter().filter(|drive| !drive.is_cdrom()) {
+                let path = dir.join(image_name(&drive.id));
+                let previous = registry.get_latest_backup_image(vm_id, &drive.id).await?
+                    .map(|image| PathBuf::from(image.path))
+                    .filter(|previous| previous.exists());
+                let mode = match (states.get(&drive.id), previous) {
+                    (Some(state), Some(previous)) if state.qcow2 && state.bitmap == BitmapState::Consistent => {
+                        storage.create_overlay(&path, &previous, ImgFormat::Qcow2, state.size).await?;
+                        DriveBackupMode::Incremental
+                    }
+                    (Some(state), _) if state.qcow2 => DriveBackupMode::Tracked(state.bitmap),
+                    _ => DriveBackupMode::Full,
+                };
+                targets.push(DriveBackupTarget { drive_id: drive.id.clone(), path, mode });
+            }
+            runtime.backup_drives_incremental(&launch_request, &targets).await?;
+        } else {
+            for drive in drives.iter().filter(|drive| !drive.is_cdrom()) {
+                let path = dir.join(image_name(&drive.id));
+                storage.export_drive(vm_id, &drive.id, &path).await?;
+                targets.push(DriveBackupTarget { drive_id: drive.id.clone(), path, mode: DriveBackupMode::Full });
+            }
+        }
+
+        for target in &targets {
+            std::fs::set_permissions(&target.path, std::fs::Permissions::from_mode(0o600))?;
+            let incremental = matches!(target.mode, DriveBackupMode::Incremental);
+            registry.add_backup_image(backup_id, &target.drive_id, incremental, &target.path.to_string_lossy()).await?;
+        }
+        Ok(())
+    }
+
+    /// Drops the oldest generations beyond `backup.retention`, merging each of their images
+    /// into the incremental image that follows it so the remaining chains stay restorable.
+    async fn prune_generations(&self, vm_id: &str) -> Result<(), crate::Error> {
+        let registry = self.context.registry();
+        let storage = self.context.storage();
+        let retention = self.context.config().backup.retention.max(1) as usize;
+        let backups = registry.get_backups_by_vm_id(vm_id).await?;
+        let expired = backups.len().saturating_sub(retention);
+        for backup in &backups[..expired] {
+            for image in registry.get_backup_images(backup.id).await? {
+                let next = registry.get_next_backup_image(vm_id, &image.drive_id, backup.id).await?;
+                if let Some(next) = next.filter(|next| next.incremental) {
+                    storage.flatten_image(Path::new(&next.path)).await?;
+                    registry.set_backup_image_full(next.backup_id, &next.drive_id).await?;
+                }
+            }
+            registry.delete_backup(backup.id).await?;
+            let dir = self.generation_dir(vm_id, backup.id);
+            if dir.exists() {
+                std::fs::remove_dir_all(dir)?;
+            }
+            log::debug!("Pruned backup generation {} of VM {}", backup.id, vm_id);
+        }
+        Ok(())
+    }
+
+    pub async fn list_generations(&self, vm_id: &str) -> Result<Vec<BackupRecord>, crate::Error> {
+        self.context.registry().get_backups_by_vm_id(vm_id).await
+    }
+
+    /// Rolls the drives of a stopped VM back to the state of backup generation `backup_id`.
+    pub async fn restore_generation(&self, vm_id: &str, backup_id: i64) -> Result<(), crate::Error> {
+        let registry = self.context.registry();
+        let storage = self.context.storage();
+        let backup = registry.get_backup(vm_id, backup_id).await?;
+        let launch_request = VmLaunchRequestBuilder::new(self.context).build(vm_id).await?;
+        if self.context.runtime().is_running(&launch_request).await? {
+            return Err(crate::Error::VMRunning);
+        }
+        for image in registry.get_backup_images(backup.id).await? {
+            let drive = registry.get_drive(vm_id, &image.drive_id).await?;
+            storage.restore_drive(vm_id, &drive.id, Path::new(&image.path), drive.options.format, drive.encrypted).await?;
+        }
+        log::debug!("Restored VM {} from backup generation {}", vm_id, backup.id);
+        Ok(())
+    }
+}
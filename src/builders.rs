@@ -1,8 +1,8 @@
 use std::collections::HashMap;
 
-use vm_types::{cloudinit::{ChpasswdUser, CloudInit, EthernetConfig, MatchInterface, Nameservers, PowerState, PresetNetworkConfig, RouteConfig}, vm::{DriveConfig, NetworkConfig, VmLaunchRequest}};
+use vm_types::{cloudinit::{ChpasswdUser, CloudInit, EthernetConfig, Growpart, MatchInterface, Nameservers, PowerState, PresetNetworkConfig, RouteConfig}, vm::{DiskMediaKind, DriveBus, DriveConfig, NetdevBackend, NetworkConfig, VmLaunchRequest}};
 
-use crate::{context::YaveContext, registry::{IPv4AddressRecord, NetworkInterfaceRecord}};
+use crate::{context::YaveContext, net::NetworkManager, registry::{AddressMode, IpAddressRecord, IpFamily, IpPoolRecord, MCAST_LOCAL_ADDRESS, NetworkInterfaceRecord, SocketMode}};
 
 pub struct VmLaunchRequestBuilder<'ctx> {
     context: &'ctx YaveContext,
@@ -25,26 +25,79 @@ impl<'ctx> VmLaunchRequestBuilder<'ctx> {
             vnc: Some(vm_record.vnc_display),
             drives: vec![],
             networks: vec![],
+            state_file: None,
         };
+        let storage = self.context.storage();
+        let state_path = storage.state_path(vm_id);
+        if state_path.exists() {
+            launch_request.state_file = Some(state_path.to_string_lossy().to_string());
+        }
         for drive in drives {
-            let drive_path = self.context.storage().path_for_vm(&drive.vm_id).join(format!("{}.img", drive.id));
+            let drive_path = match &drive.drive_bus {
+                DriveBus::Ide { media_type: DiskMediaKind::Cdrom, .. } => drive.media.as_ref().map(|iso| storage.iso_path(iso)),
+                _ => Some(storage.drive_path(&drive.vm_id, &drive.id)),
+            };
+            let key_file = match drive.encrypted {
+                true => Some(storage.secrets().key_path(&drive.vm_id, &drive.id).to_string_lossy().to_string()),
+                false => None,
+            };
             launch_request.drives.push(DriveConfig {
                 id: drive.id,
-                path: drive_path.to_string_lossy().to_string(),
+                path: drive_path.map(|path| path.to_string_lossy().to_string()),
                 drive_media: drive.drive_bus,
+                options: drive.options,
+                key_file,
             });
         }
+        let nm = NetworkManager::new(self.context);
         for nic in nics {
+            let mut options = nic.options.clone();
+            if options.mtu.is_none() {
+                options.mtu = nm.network_definition(nic.network.as_deref()).await?.and_then(|network| network.mtu);
+            }
             launch_request.networks.push(NetworkConfig {
                 id: nic.id.clone(),
                 ifname: nic.ifname.clone(),
                 mac: nic.mac_address.clone(),
+                options,
+                backend: self.netdev_backend(&nic).await?,
             });
         }
         Ok(launch_request)
     }
 }
 
+impl<'ctx> VmLaunchRequestBuilder<'ctx> {
+    /// TAP for bridged interfaces, the socket of their network otherwise. The two ends of a UDP
+    /// link take the port of the network and the next one in the order they were created.
+    async fn netdev_backend(&self, nic: &NetworkInterfaceRecord) -> Result<NetdevBackend, crate::Error> {
+        let registry = self.context.registry();
+        let Some(name) = &nic.network else {
+            return Ok(NetdevBackend::Tap);
+        };
+        let Some(network) = registry.find_socket_network(name).await? else {
+            return Ok(NetdevBackend::Tap);
+        };
+        match network.mode {
+            SocketMode::Mcast => Ok(NetdevBackend::Mcast {
+                group: format!("{}:{}", network.address, network.port),
+                local: network.local_address.unwrap_or(MCAST_LOCAL_ADDRESS.to_string()),
+            }),
+            SocketMode::Udp => {
+                let position = registry.get_network_interfaces_by_network(name).await?
+                    .iter()
+                    .position(|other| other.ifname == nic.ifname);
+                let endpoint = |port: u16| format!("{}:{}", network.address, port);
+                match position {
+                    Some(0) => Ok(NetdevBackend::Udp { local: endpoint(network.port), remote: endpoint(network.port + 1) }),
+                    Some(1) => Ok(NetdevBackend::Udp { local: endpoint(network.port + 1), remote: endpoint(network.port) }),
+                    _ => Err(crate::Error::NetworkFull(network.name)),
+                }
+            },
+        }
+    }
+}
+
 pub struct CloudInitBuilder<'ctx> {
     context: &'ctx YaveContext,
 }
@@ -54,37 +107,48 @@ impl<'ctx> CloudInitBuilder<'ctx> {
         CloudInitBuilder { context }
     }
 
-    fn create_network_config(&self, nics: &[NetworkInterfaceRecord], ipv4s: &[IPv4AddressRecord]) -> vm_types::cloudinit::PresetNetworkConfig {
+    /// Netplan configuration of the interfaces. Static addresses are listed, SLAAC addresses
+    /// are left to router advertisements with EUI-64 interface identifiers so the guest ends up
+    /// with the registered address. The gateway of the default address of each family becomes
+    /// a default route. Interfaces whose default IPv4 address belongs to a pool with
+    /// nameservers use those instead of the global ones.
+    fn create_network_config(&self, nics: &[NetworkInterfaceRecord], ips: &[IpAddressRecord], pools: &[IpPoolRecord]) -> vm_types::cloudinit::PresetNetworkConfig {
         let mut interfaces = HashMap::new();
         for nic in nics {
-            let mut addresses = vec![];
-            for addr in ipv4s.iter().filter(|a| a.ifname == nic.ifname) {
-                addresses.push(format!("{}/{}", addr.address, addr.netmask));
-            }
-            let default_ip = ipv4s.iter().find(|a| a.ifname == nic.ifname && a.is_default);
+            let nic_ips: Vec<&IpAddressRecord> = ips.iter().filter(|a| a.ifname == nic.ifname).collect();
+            let addresses = nic_ips.iter()
+                .filter(|a| a.mode == AddressMode::Static)
+                .map(|a| format!("{}/{}", a.address, a.prefix_len))
+                .collect();
+            let routes: Vec<RouteConfig> = nic_ips.iter()
+                .filter(|a| a.is_default)
+                .filter_map(|a| a.gateway.clone())
+                .map(|gateway| RouteConfig {
+                    to: "default".to_string(),
+                    via: gateway,
+                })
+                .collect();
+            let pool_nameservers = nic_ips.iter()
+                .filter(|a| a.is_default && a.family == IpFamily::Ipv4)
+                .filter_map(|a| a.address.parse().ok())
+                .find_map(|address| pools.iter().find(|pool| pool.contains(address) && !pool.nameservers.is_empty()))
+                .map(|pool| pool.nameservers.clone());
+            let has_ipv6 = nic_ips.iter().any(|a| a.family == IpFamily::Ipv6);
+            let slaac = nic_ips.iter().any(|a| a.mode == AddressMode::Slaac);
             interfaces.insert(nic.id.clone(), EthernetConfig {
                 match_interface: MatchInterface {
                     macaddress: nic.mac_address.clone(),
                 },
                 addresses,
-                routes: match default_ip {
-                    Some(default_ipv4) => match &default_ipv4.gateway {
-                        Some(gateway) => Some(vec![
-                            RouteConfig {
-                                to: "default".to_string(),
-                                via: gateway.clone(),
-                            },
-                        ]),
-                        None => None,
-                    },
-                    None => None,
-                },
-                nameservers: match default_ip {
-                    Some(_) => Some(Nameservers {
-                        addresses: self.context.config().network.nameservers.clone(),
+                routes: (!routes.is_empty()).then_some(routes),
+                nameservers: match nic_ips.iter().any(|a| a.is_default) {
+                    true => Some(Nameservers {
+                        addresses: pool_nameservers.unwrap_or_else(|| self.context.config().network.nameservers.clone()),
                     }),
-                    None => None,
+                    false => None,
                 },
+                accept_ra: has_ipv6.then_some(slaac),
+                ipv6_address_generation: slaac.then(|| "eui64".to_string()),
             });
         }
         PresetNetworkConfig {
@@ -95,7 +159,8 @@ impl<'ctx> CloudInitBuilder<'ctx> {
 
     pub async fn build(&self, vm_id: &str, root_password: &str) -> Result<CloudInit, crate::Error> {
         let registry = self.context.registry();
-        let (vm_record, _, nics, ipv4s) = registry.get_vm_full(vm_id).await?;
+        let (vm_record, _, nics, ips) = registry.get_vm_full(vm_id).await?;
+        let pools = registry.get_ip_pools().await?;
         let cloud_init = vm_types::cloudinit::UserDataCloudInit {
             hostname: vm_record.hostname,
             chpasswd: vm_types::cloudinit::Chpasswd {
@@ -117,10 +182,15 @@ impl<'ctx> CloudInitBuilder<'ctx> {
                 condition: "true".to_string(),
             },
             disable_root: false,
+            growpart: Growpart {
+                mode: "auto".to_string(),
+                devices: vec!["/".to_string()],
+            },
+            resize_rootfs: true,
         };
         Ok(CloudInit {
             user_data: cloud_init,
-            network_config: self.create_network_config(&nics, &ipv4s) 
+            network_config: self.create_network_config(&nics, &ips, &pools)
         })
     }
 }
@@ -0,0 +1,54 @@
+use crate::{builders::VmLaunchRequestBuilder, context::YaveContext, ipam::IpamManager, registry::{CloneVirtualMachine, DriveRecord, VirtualMachineRecord}};
+
+pub struct CloneManager<'ctx> {
+    context: &'ctx YaveContext,
+}
+
+impl<'ctx> CloneManager<'ctx> {
+    pub fn new(context: &'ctx YaveContext) -> Self {
+        Self { context }
+    }
+
+    /// Clones a stopped VM. The registry rows are written first, in one transaction, so a clone
+    /// the registry refuses leaves the source untouched; if copying or linking the drives fails
+    /// afterwards, both the images and the rows are reverted.
+    pub async fn clone_vm(&self, source_id: &str, clone: CloneVirtualMachine) -> Result<VirtualMachineRecord, crate::Error> {
+        let registry = self.context.registry();
+        let storage = self.context.storage();
+        if registry.get_vm_by_id(&clone.id).await.is_ok() {
+            return Err(crate::Error::VMExists(clone.id));
+        }
+        let launch_request = VmLaunchRequestBuilder::new(self.context).build(source_id).await?;
+        if self.context.runtime().is_running(&launch_request).await? {
+            return Err(crate::Error::VMRunning);
+        }
+
+        let drives = registry.get_drives_by_vm_id(source_id).await?;
+        let linked = clone.linked;
+        let vm = registry.clone_vm(source_id, clone).await?;
+        let bases = match storage.clone_vm(source_id, &vm.id, &drives, linked).await {
+            Ok(bases) => bases,
+            Err(err) => {
+                self.revert_registry(source_id, &vm.id, &drives).await?;
+                return Err(err);
+            },
+        };
+        if let Err(err) = IpamManager::new(self.context).allocate_vm(&vm.id).await {
+            storage.unclone_vm(source_id, &vm.id, &bases).await?;
+            self.revert_registry(source_id, &vm.id, &drives).await?;
+            return Err(err);
+        }
+        log::debug!("Cloned VM {} to {}", source_id, vm.id);
+        Ok(vm)
+    }
+
+    /// Deletes the clone's rows and restores the drive options of the source.
+    async fn revert_registry(&self, source_id: &str, clone_id: &str, drives: &[DriveRecord]) -> Result<(), crate::Error> {
+        let registry = self.context.registry();
+        registry.delete_vm(clone_id).await?;
+        for drive in drives {
+            registry.set_drive_options(source_id, &drive.id, &drive.options).await?;
+        }
+        Ok(())
+    }
+}
@@ -1,7 +1,7 @@
 use std::path::Path;
 
 use tokio::process::Command;
-use vm_types::{cloudinit::CloudInit, vm::{DriveBus, DriveConfig, VmLaunchRequest}};
+use vm_types::{cloudinit::CloudInit, vm::{DriveBus, DriveConfig, DriveFormat, DriveOptions, VmLaunchRequest}};
 
 use crate::context::YaveContext;
 
@@ -107,6 +107,10 @@ impl<'ctx> CloudInitInstaller<'ctx> {
     ) -> Result<(), crate::Error> {
         let iso = self.create_iso_image(cloud_config).await?;
         let mut launch_request = launch_request.clone();
+        // The drives have just been installed, a state saved before cannot be resumed on them.
+        if launch_request.state_file.take().is_some() {
+            self.yave_context.storage().discard_state(&launch_request.id)?;
+        }
         #[cfg(not(debug_assertions))]
         {
             launch_request.vnc = None;
@@ -117,10 +121,15 @@ impl<'ctx> CloudInitInstaller<'ctx> {
                 media_type: vm_types::vm::DiskMediaKind::Cdrom,
                 boot_index: Some(launch_request.drives.len() as u32 + 1),
             },
-            path: iso.output_iso_path().to_string_lossy().to_string(),
+            path: Some(iso.output_iso_path().to_string_lossy().to_string()),
+            options: DriveOptions {
+                format: Some(DriveFormat::Raw),
+                ..Default::default()
+            },
+            key_file: None,
         });
         let runtime = self.yave_context.runtime();
-        runtime.run_vm(&launch_request).await?;
+        runtime.run_vm(&launch_request, false).await?;
         log::debug!(
             "Launched VM with Cloud Init ISO with params {:?}",
             launch_request
@@ -133,6 +142,6 @@ impl<'ctx> CloudInitInstaller<'ctx> {
             qmp.invoke(InvokeCommand::set_vnc_password("12345678")).await?;
         }
         qmp.on_close().await?;
-        Ok(())
+        runtime.delete_interfaces(&launch_request).await
     }
 }
@@ -1,27 +1,23 @@
-use std::path::{Path, PathBuf};
+use std::{path::{Path, PathBuf}, sync::Arc};
 
 use vm_types::Config;
 
-use crate::{launch::VmRuntime, registry::VmRegistry, storage::VmStorage};
+use crate::{launch::VmRuntime, net::TapHook, registry::{MacGenerator, VmRegistry}, storage::VmStorage};
 
 #[derive(Debug, Clone)]
 pub struct YaveContext {
     config: Config,
     storage_path: PathBuf,
     run_path: PathBuf,
-    netdev_scripts: NetdevScripts,
     db_pool: sqlx::Pool<sqlx::Sqlite>,
 }
 
-#[derive(Debug, Clone)]
-pub struct NetdevScripts {
-    pub up: PathBuf,
-    pub down: PathBuf,
-}
-
 impl YaveContext {
-    pub async fn load(config_path: impl AsRef<Path>, storage_path: impl AsRef<Path>, run_path: impl AsRef<Path>, netdev_scripts: &NetdevScripts) -> Result<Self, crate::Error> {
-        let config = Config::load(config_path.as_ref())?;
+    pub async fn load(config_path: impl AsRef<Path>, storage_path: impl AsRef<Path>, run_path: impl AsRef<Path>) -> Result<Self, crate::Error> {
+        let mut config = Config::load(config_path.as_ref())?;
+        if config.network.mac_seed.is_none() {
+            config.network.mac_seed = machine_id();
+        }
         if !storage_path.as_ref().exists() {
             std::fs::create_dir_all(storage_path.as_ref())?;
         }
@@ -36,7 +32,6 @@ impl YaveContext {
             config,
             storage_path: storage_path.as_ref().to_path_buf(),
             run_path: run_path.as_ref().to_path_buf(),
-            netdev_scripts: netdev_scripts.clone(),
             db_pool: sqlx::SqlitePool::connect(&format!("sqlite://{}", db.to_string_lossy())).await?,
         })
     }
@@ -45,12 +40,11 @@ impl YaveContext {
         &self.config
     }
 
-    pub fn netdev_scripts(&self) -> &NetdevScripts {
-        &self.netdev_scripts
-    }
-
     pub fn registry(&self) -> VmRegistry {
-        VmRegistry::new(self.db_pool.clone())
+        VmRegistry::new(
+            self.db_pool.clone(),
+            MacGenerator::new(self.config.network.mac_oui.clone(), self.config.network.mac_seed.clone()),
+        )
     }
 
     pub fn storage(&self) -> VmStorage {
@@ -62,8 +56,14 @@ impl YaveContext {
             &self.config.cli.bin,
             &self.run_path,
             &self.config.ovmf.code, &self.config.ovmf.vars,
-            Some(self.netdev_scripts.up.clone()),
-            Some(self.netdev_scripts.down.clone()),
+            Some(Arc::new(TapHook::new(self.clone()))),
         )
     }
 }
+
+/// Identifier of this host, distinct between hosts installed separately.
+fn machine_id() -> Option<String> {
+    let id = std::fs::read_to_string("/etc/machine-id").ok()?;
+    let id = id.trim();
+    (!id.is_empty()).then(|| id.to_string())
+}
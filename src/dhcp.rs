@@ -0,0 +1,123 @@
+use std::path::{Path, PathBuf};
+
+use nix::{sys::signal::{Signal, kill}, unistd::Pid};
+use tokio::process::Command;
+
+use crate::registry::{DhcpHostRecord, NetworkRecord};
+
+/// dnsmasq instance serving DHCP and DNS on the bridge of a managed network.
+/// Leases are static, only interfaces with an address in the registry get one.
+pub struct Dnsmasq {
+    bin: PathBuf,
+    dir: PathBuf,
+}
+
+impl Dnsmasq {
+    pub fn new(bin: impl AsRef<Path>, dir: impl AsRef<Path>) -> Self {
+        Self {
+            bin: bin.as_ref().to_path_buf(),
+            dir: dir.as_ref().to_path_buf(),
+        }
+    }
+
+    fn config_path(&self) -> PathBuf {
+        self.dir.join("dnsmasq.conf")
+    }
+
+    fn pid_path(&self) -> PathBuf {
+        self.dir.join("dnsmasq.pid")
+    }
+
+    fn hosts_path(&self) -> PathBuf {
+        self.dir.join("dhcp-hosts")
+    }
+
+    fn names_path(&self) -> PathBuf {
+        self.dir.join("hosts")
+    }
+
+    fn pid(&self) -> Option<Pid> {
+        let pid = std::fs::read_to_string(self.pid_path()).ok()?;
+        let pid = Pid::from_raw(pid.trim().parse().ok()?);
+        kill(pid, None).ok().map(|_| pid)
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.pid().is_some()
+    }
+
+    pub fn write_config(&self, network: &NetworkRecord, nameservers: &[String]) -> Result<(), crate::Error> {
+        let (subnet, netmask) = network.subnet()
+            .ok_or(crate::Error::InvalidNetworkAddress(network.address.clone()))?;
+        std::fs::create_dir_all(&self.dir)?;
+        let mut config = vec![
+            format!("interface={}", network.bridge),
+            "bind-interfaces".to_string(),
+            "except-interface=lo".to_string(),
+            "no-resolv".to_string(),
+            "no-hosts".to_string(),
+            "domain-needed".to_string(),
+            format!("pid-file={}", self.pid_path().to_string_lossy()),
+            format!("dhcp-leasefile={}", self.dir.join("dnsmasq.leases").to_string_lossy()),
+            format!("dhcp-range={},static,{}", subnet, netmask),
+            format!("dhcp-option=option:router,{}", network.address),
+            format!("dhcp-option=option:dns-server,{}", network.address),
+            format!("dhcp-hostsfile={}", self.hosts_path().to_string_lossy()),
+            format!("addn-hosts={}", self.names_path().to_string_lossy()),
+        ];
+        if let Some(mtu) = network.mtu {
+            config.push(format!("dhcp-option=option:mtu,{}", mtu));
+        }
+        for nameserver in nameservers {
+            config.push(format!("server={}", nameserver));
+        }
+        config.push(String::new());
+        std::fs::write(self.config_path(), config.join("\n"))?;
+        Ok(())
+    }
+
+    /// Writes the static leases and host names, a running instance picks them up on reload.
+    pub fn write_hosts(&self, hosts: &[DhcpHostRecord]) -> Result<(), crate::Error> {
+        std::fs::create_dir_all(&self.dir)?;
+        let leases: String = hosts.iter()
+            .map(|host| format!("{},{},{}\n", host.mac_address, host.address, host.hostname))
+            .collect();
+        let names: String = hosts.iter()
+            .map(|host| format!("{} {}\n", host.address, host.hostname))
+            .collect();
+        std::fs::write(self.hosts_path(), leases)?;
+        std::fs::write(self.names_path(), names)?;
+        Ok(())
+    }
+
+    pub async fn start(&self) -> Result<(), crate::Error> {
+        if self.is_running() {
+            return Ok(());
+        }
+        let status = Command::new(&self.bin)
+            .arg(format!("--conf-file={}", self.config_path().to_string_lossy()))
+            .status()
+            .await?;
+        if !status.success() {
+            return Err(crate::Error::Command(format!("{} exited with {}", self.bin.to_string_lossy(), status)));
+        }
+        Ok(())
+    }
+
+    pub fn reload(&self) -> Result<(), crate::Error> {
+        if let Some(pid) = self.pid() {
+            kill(pid, Signal::SIGHUP)?;
+        }
+        Ok(())
+    }
+
+    pub fn stop(&self) -> Result<(), crate::Error> {
+        if let Some(pid) = self.pid() {
+            kill(pid, Signal::SIGTERM)?;
+        }
+        if self.pid_path().exists() {
+            std::fs::remove_file(self.pid_path())?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,115 @@
+use vm_types::vm::{AioMode, CacheMode, DiskMediaKind, DriveBus, DriveOptions, IoLimits};
+
+use crate::{builders::VmLaunchRequestBuilder, context::YaveContext};
+
+pub struct DriveManager<'ctx> {
+    context: &'ctx YaveContext,
+}
+
+impl <'ctx> DriveManager<'ctx> {
+    pub fn new(context: &'ctx YaveContext) -> Self {
+        Self { context }
+    }
+}
+
+impl <'ctx> DriveManager<'ctx> {
+    /// Resizes a drive to `size` megabytes. Running VMs are resized through QMP,
+    /// stopped ones with `qemu-img`. Shrinking is refused unless `force` is set.
+    pub async fn resize_drive(&self, vm_id: &str, drive_id: &str, size: u64, force: bool) -> Result<(), crate::Error> {
+        let registry = self.context.registry();
+        let storage = self.context.storage();
+        let drive = registry.get_drive(vm_id, drive_id).await?;
+        let current = storage.drive_size(vm_id, &drive.id).await?;
+        let shrink = size < current;
+        if shrink && !force {
+            return Err(crate::Error::DriveShrink(drive.id));
+        }
+        let launch_request = VmLaunchRequestBuilder::new(self.context).build(vm_id).await?;
+        let runtime = self.context.runtime();
+        if runtime.is_running(&launch_request).await? {
+            runtime.resize_drive(&launch_request, &drive.id, size).await?;
+        } else {
+            storage.resize_drive(vm_id, &drive.id, size, shrink).await?;
+        }
+        registry.set_drive_size(vm_id, &drive.id, size).await?;
+        Ok(())
+    }
+
+    /// Stores new I/O limits for a drive and applies them through QMP if the VM is running.
+    pub async fn set_io_limits(&self, vm_id: &str, drive_id: &str, limits: IoLimits) -> Result<(), crate::Error> {
+        let registry = self.context.registry();
+        let mut drive = registry.get_drive(vm_id, drive_id).await?;
+        drive.options.io_limits = limits;
+        validate_drive_options(&drive.options)?;
+        let launch_request = VmLaunchRequestBuilder::new(self.context).build(vm_id).await?;
+        let runtime = self.context.runtime();
+        if runtime.is_running(&launch_request).await? {
+            runtime.set_io_limits(&launch_request, &drive.id, &drive.options.io_limits).await?;
+        }
+        registry.set_drive_options(vm_id, &drive.id, &drive.options).await?;
+        Ok(())
+    }
+
+    /// Rewraps an encrypted drive with a new key. The VM must be stopped.
+    pub async fn rotate_key(&self, vm_id: &str, drive_id: &str) -> Result<(), crate::Error> {
+        let drive = self.context.registry().get_drive(vm_id, drive_id).await?;
+        if !drive.encrypted {
+            return Err(crate::Error::DriveNotEncrypted(drive.id));
+        }
+        let launch_request = VmLaunchRequestBuilder::new(self.context).build(vm_id).await?;
+        if self.context.runtime().is_running(&launch_request).await? {
+            return Err(crate::Error::VMRunning);
+        }
+        self.context.storage().rotate_drive_key(vm_id, &drive.id).await
+    }
+
+    /// Inserts an ISO from the image library into a CD-ROM drive, or ejects it when `iso` is `None`.
+    /// Without `drive_id` the first CD-ROM drive of the VM is used.
+    pub async fn change_media(&self, vm_id: &str, drive_id: Option<&str>, iso: Option<&str>) -> Result<(), crate::Error> {
+        let registry = self.context.registry();
+        let storage = self.context.storage();
+        let drive = match drive_id {
+            Some(drive_id) => registry.get_drive(vm_id, drive_id).await?,
+            None => registry.get_drives_by_vm_id(vm_id).await?
+                .into_iter()
+                .find(|drive| matches!(drive.drive_bus, DriveBus::Ide { media_type: DiskMediaKind::Cdrom, .. }))
+                .ok_or(crate::Error::DriveNotFound)?,
+        };
+        if !matches!(drive.drive_bus, DriveBus::Ide { media_type: DiskMediaKind::Cdrom, .. }) {
+            return Err(crate::Error::DriveNotCdrom(drive.id));
+        }
+        let iso_path = match iso {
+            Some(iso) => {
+                let path = storage.iso_path(iso);
+                if !path.exists() {
+                    return Err(crate::Error::ImageNotFound(iso.to_string()));
+                }
+                Some(path)
+            },
+            None => None,
+        };
+        let launch_request = VmLaunchRequestBuilder::new(self.context).build(vm_id).await?;
+        let runtime = self.context.runtime();
+        if runtime.is_running(&launch_request).await? {
+            runtime.change_media(&launch_request, &drive.id, iso_path.as_deref()).await?;
+        }
+        registry.set_drive_media(vm_id, &drive.id, iso).await?;
+        Ok(())
+    }
+}
+
+/// Rejects options QEMU refuses to start with: native AIO on images not opened with O_DIRECT,
+/// and a total I/O limit combined with a read or write one of the same kind.
+pub fn validate_drive_options(options: &DriveOptions) -> Result<(), crate::Error> {
+    if options.aio == Some(AioMode::Native) && !matches!(options.cache, Some(CacheMode::None | CacheMode::Directsync)) {
+        return Err(crate::Error::InvalidDriveOptions("aio=native requires cache=none or cache=directsync".to_string()));
+    }
+    let limits = &options.io_limits;
+    if limits.iops_total.is_some() && (limits.iops_read.is_some() || limits.iops_write.is_some()) {
+        return Err(crate::Error::InvalidDriveOptions("iops_total cannot be combined with iops_read or iops_write".to_string()));
+    }
+    if limits.bps_total.is_some() && (limits.bps_read.is_some() || limits.bps_write.is_some()) {
+        return Err(crate::Error::InvalidDriveOptions("bps_total cannot be combined with bps_read or bps_write".to_string()));
+    }
+    Ok(())
+}
@@ -1,10 +1,21 @@
 use futures_util::TryStreamExt;
-use rtnetlink::{Handle, LinkUnspec, new_connection, packet_route::link::LinkMessage};
+use std::net::IpAddr;
 
+use rtnetlink::{Handle, LinkBridge, LinkBridgeVlan, LinkMessageBuilder, LinkUnspec, new_connection, packet_route::{AddressFamily, link::{BridgeVlanInfoFlags, LinkMessage}, neighbour::{NeighbourAttribute, NeighbourMessage, NeighbourState}}};
+
+const TUN_DEVICE: &str = "/dev/net/tun";
+
+nix::ioctl_write_int!(tunsetiff, b'T', 202);
+nix::ioctl_write_int!(tunsetpersist, b'T', 203);
+
+/// Looks up a link by name, `None` if the kernel reports no such device.
 async fn get_interface(handle: &Handle, interface: &str) -> Result<Option<LinkMessage>, rtnetlink::Error> {
     let mut links = handle.link().get().match_name(interface.to_string()).execute();
 
-    links.try_next().await
+    match links.try_next().await {
+        Err(rtnetlink::Error::NetlinkError(err)) if err.raw_code() == -(nix::errno::Errno::ENODEV as i32) => Ok(None),
+        result => result,
+    }
 }
 
 pub async fn set_master(interface: &str, master: &str) -> Result<(), rtnetlink::Error> {
@@ -38,3 +49,233 @@ pub async fn set_link_up(interface: &str) -> Result<(), rtnetlink::Error> {
 
     Ok(())
 }
+
+pub async fn set_link_down(interface: &str) -> Result<(), rtnetlink::Error> {
+    let (connection, handle, _) = new_connection().unwrap();
+    tokio::spawn(connection);
+
+    let interface_id = match get_interface(&handle, interface).await? {
+        Some(interface) => interface.header.index,
+        None => return Ok(()),
+    };
+
+    handle.link().set(LinkUnspec::new_with_index(interface_id).down().build()).execute().await?;
+
+    Ok(())
+}
+
+/// Releases the interface from the bridge it is a port of.
+pub async fn set_nomaster(interface: &str) -> Result<(), rtnetlink::Error> {
+    let (connection, handle, _) = new_connection().unwrap();
+    tokio::spawn(connection);
+
+    let interface_id = match get_interface(&handle, interface).await? {
+        Some(interface) => interface.header.index,
+        None => return Ok(()),
+    };
+
+    handle.link().set(LinkUnspec::new_with_index(interface_id).nocontroller().build()).execute().await?;
+
+    Ok(())
+}
+
+fn is_static(neighbour: &NeighbourMessage) -> bool {
+    matches!(neighbour.header.state, NeighbourState::Noarp | NeighbourState::Permanent)
+}
+
+/// Removes static bridge FDB entries pointing at the port `interface`.
+pub async fn delete_static_fdb(interface: &str) -> Result<(), rtnetlink::Error> {
+    let (connection, handle, _) = new_connection().unwrap();
+    tokio::spawn(connection);
+
+    let interface_id = match get_interface(&handle, interface).await? {
+        Some(interface) => interface.header.index,
+        None => return Ok(()),
+    };
+
+    let mut request = handle.neighbours().get();
+    request.message_mut().header.family = AddressFamily::Bridge;
+    let entries: Vec<NeighbourMessage> = request.execute().try_collect().await?;
+    for entry in entries {
+        if entry.header.ifindex == interface_id && entry.header.state == NeighbourState::Noarp {
+            handle.neighbours().del(entry).execute().await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Removes static ARP/NDP entries on `bridge` resolving to `mac`.
+pub async fn delete_static_neighbours(bridge: &str, mac: &[u8]) -> Result<(), rtnetlink::Error> {
+    let (connection, handle, _) = new_connection().unwrap();
+    tokio::spawn(connection);
+
+    let bridge_id = match get_interface(&handle, bridge).await? {
+        Some(interface) => interface.header.index,
+        None => return Ok(()),
+    };
+
+    let entries: Vec<NeighbourMessage> = handle.neighbours().get().execute().try_collect().await?;
+    for entry in entries {
+        let matches_mac = entry.attributes.iter()
+            .any(|attr| matches!(attr, NeighbourAttribute::LinkLocalAddress(lla) if lla == mac));
+        if entry.header.ifindex == bridge_id && is_static(&entry) && matches_mac {
+            handle.neighbours().del(entry).execute().await?;
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn set_mtu(interface: &str, mtu: u32) -> Result<(), rtnetlink::Error> {
+    let (connection, handle, _) = new_connection().unwrap();
+    tokio::spawn(connection);
+
+    let interface_id = match get_interface(&handle, interface).await? {
+        Some(interface) => interface.header.index,
+        None => return Ok(()),
+    };
+
+    handle.link().set(LinkUnspec::new_with_index(interface_id).mtu(mtu).build()).execute().await?;
+
+    Ok(())
+}
+
+/// Makes a bridge port an untagged member of `vid` only, replacing the default VLAN 1.
+pub async fn set_port_vlan(interface: &str, vid: u16) -> Result<(), rtnetlink::Error> {
+    let (connection, handle, _) = new_connection().unwrap();
+    tokio::spawn(connection);
+
+    let interface_id = match get_interface(&handle, interface).await? {
+        Some(interface) => interface.header.index,
+        None => return Ok(()),
+    };
+
+    handle.link().set_port(
+        LinkBridgeVlan::new(interface_id)
+            .vlan(vid, BridgeVlanInfoFlags::Pvid | BridgeVlanInfoFlags::Untagged)
+            .build()
+    ).execute().await?;
+    if vid != 1 {
+        handle.link().del_with_message(
+            LinkBridgeVlan::new(interface_id)
+                .vlan(1, BridgeVlanInfoFlags::empty())
+                .build()
+        ).execute().await?;
+    }
+
+    Ok(())
+}
+
+/// Adds `vid` as a tagged VLAN to a bridge port, or with `bridge_self` to the bridge device
+/// itself, leaving its other VLANs alone.
+pub async fn add_tagged_vlan(interface: &str, vid: u16, bridge_self: bool) -> Result<(), rtnetlink::Error> {
+    let (connection, handle, _) = new_connection().unwrap();
+    tokio::spawn(connection);
+
+    let interface_id = match get_interface(&handle, interface).await? {
+        Some(interface) => interface.header.index,
+        None => return Ok(()),
+    };
+
+    let message = LinkBridgeVlan::new(interface_id).vlan(vid, BridgeVlanInfoFlags::empty());
+    let message = if bridge_self { message.bridge_self() } else { message };
+    handle.link().set_port(message.build()).execute().await?;
+
+    Ok(())
+}
+
+/// Turns on VLAN filtering on the bridge so the VLAN membership of its ports is enforced.
+pub async fn set_vlan_filtering(bridge: &str) -> Result<(), rtnetlink::Error> {
+    let (connection, handle, _) = new_connection().unwrap();
+    tokio::spawn(connection);
+
+    let bridge_id = match get_interface(&handle, bridge).await? {
+        Some(interface) => interface.header.index,
+        None => return Ok(()),
+    };
+
+    handle.link().set(LinkMessageBuilder::<LinkBridge>::new(bridge).index(bridge_id).vlan_filtering(true).build()).execute().await?;
+
+    Ok(())
+}
+
+/// Creates the persistent TAP device `name`, which outlives the file descriptor it is created
+/// with until deleted. QEMU can open it later by name when its multiqueue flag matches.
+pub fn create_tap(name: &str, multi_queue: bool) -> Result<(), std::io::Error> {
+    use nix::libc;
+    use std::os::fd::AsRawFd;
+
+    if name.len() >= libc::IFNAMSIZ {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, format!("interface name too long: {}", name)));
+    }
+    let tun = std::fs::OpenOptions::new().read(true).write(true).open(TUN_DEVICE)?;
+    // SAFETY: ifreq is plain old data, all zeroes is a valid value.
+    let mut ifr: libc::ifreq = unsafe { std::mem::zeroed() };
+    for (dst, src) in ifr.ifr_name.iter_mut().zip(name.bytes()) {
+        *dst = src as libc::c_char;
+    }
+    let mut flags = libc::IFF_TAP | libc::IFF_NO_PI;
+    if multi_queue {
+        flags |= libc::IFF_MULTI_QUEUE;
+    }
+    ifr.ifr_ifru.ifru_flags = flags as libc::c_short;
+    // SAFETY: both requests take an int sized argument, TUNSETIFF a pointer to the ifreq that
+    // outlives the call.
+    unsafe {
+        tunsetiff(tun.as_raw_fd(), &mut ifr as *mut libc::ifreq as libc::c_ulong)?;
+        tunsetpersist(tun.as_raw_fd(), 1)?;
+    }
+
+    Ok(())
+}
+
+pub async fn interface_exists(interface: &str) -> Result<bool, rtnetlink::Error> {
+    let (connection, handle, _) = new_connection().unwrap();
+    tokio::spawn(connection);
+
+    Ok(get_interface(&handle, interface).await?.is_some())
+}
+
+/// Creates the bridge `name` unless an interface of that name already exists.
+pub async fn create_bridge(name: &str) -> Result<(), rtnetlink::Error> {
+    let (connection, handle, _) = new_connection().unwrap();
+    tokio::spawn(connection);
+
+    if get_interface(&handle, name).await?.is_some() {
+        return Ok(());
+    }
+
+    handle.link().add(LinkBridge::new(name).build()).execute().await?;
+
+    Ok(())
+}
+
+pub async fn delete_link(interface: &str) -> Result<(), rtnetlink::Error> {
+    let (connection, handle, _) = new_connection().unwrap();
+    tokio::spawn(connection);
+
+    let interface_id = match get_interface(&handle, interface).await? {
+        Some(interface) => interface.header.index,
+        None => return Ok(()),
+    };
+
+    handle.link().del(interface_id).execute().await?;
+
+    Ok(())
+}
+
+/// Assigns `address` to the interface, replacing it if it is already present.
+pub async fn add_address(interface: &str, address: IpAddr, prefix_len: u8) -> Result<(), rtnetlink::Error> {
+    let (connection, handle, _) = new_connection().unwrap();
+    tokio::spawn(connection);
+
+    let interface_id = match get_interface(&handle, interface).await? {
+        Some(interface) => interface.header.index,
+        None => return Ok(()),
+    };
+
+    handle.address().add(interface_id, address, prefix_len).replace().execute().await?;
+
+    Ok(())
+}
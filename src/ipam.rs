@@ -0,0 +1,137 @@
+use std::{collections::HashSet, net::{IpAddr, Ipv4Addr}};
+
+use crate::{context::YaveContext, net::NetworkManager, registry::{AddressMode, CreateIpPool, IpAddressRecord, IpPoolRecord}};
+
+/// Utilisation of a pool. Addresses inside the subnet count as used whether they were
+/// allocated from the pool or assigned by hand.
+#[derive(Debug, Clone)]
+pub struct PoolUsage {
+    pub total: usize,
+    pub used: usize,
+    pub addresses: Vec<IpAddressRecord>,
+}
+
+pub struct IpamManager<'ctx> {
+    context: &'ctx YaveContext,
+}
+
+impl<'ctx> IpamManager<'ctx> {
+    pub fn new(context: &'ctx YaveContext) -> Self {
+        Self { context }
+    }
+
+    pub async fn create_pool(&self, pool: CreateIpPool) -> Result<IpPoolRecord, crate::Error> {
+        let record = IpPoolRecord {
+            name: pool.name.clone(),
+            cidr: pool.cidr.clone(),
+            gateway: pool.gateway.clone(),
+            reserved: pool.reserved.clone(),
+            nameservers: pool.nameservers.clone(),
+            network: pool.network.clone(),
+        };
+        validate_pool(&record)?;
+        if let Some(network) = &pool.network
+            && !NetworkManager::new(self.context).network_exists(network).await?
+        {
+            return Err(crate::Error::NetworkNotFound(network.clone()));
+        }
+        self.context.registry().create_ip_pool(pool).await
+    }
+
+    pub async fn get_pool(&self, name: &str) -> Result<(IpPoolRecord, PoolUsage), crate::Error> {
+        let pool = self.context.registry().get_ip_pool(name).await?;
+        let usage = self.usage(&pool).await?;
+        Ok((pool, usage))
+    }
+
+    pub async fn get_pools(&self) -> Result<Vec<(IpPoolRecord, PoolUsage)>, crate::Error> {
+        let mut pools = vec![];
+        for pool in self.context.registry().get_ip_pools().await? {
+            let usage = self.usage(&pool).await?;
+            pools.push((pool, usage));
+        }
+        Ok(pools)
+    }
+
+    /// Deletes a pool. Addresses allocated from it stay assigned.
+    pub async fn delete_pool(&self, name: &str) -> Result<(), crate::Error> {
+        self.context.registry().delete_ip_pool(name).await
+    }
+
+    async fn usage(&self, pool: &IpPoolRecord) -> Result<PoolUsage, crate::Error> {
+        let addresses: Vec<IpAddressRecord> = self.context.registry().get_ipv4_addresses().await?
+            .into_iter()
+            .filter(|record| record.address.parse().is_ok_and(|address| pool.contains(address)))
+            .collect();
+        let used = addresses.iter()
+            .filter(|record| record.address.parse().is_ok_and(|address| pool.is_host(address)))
+            .count();
+        Ok(PoolUsage { total: pool.host_count(), used, addresses })
+    }
+
+    /// Assigns the next free address of the pool of the interface's network. Interfaces
+    /// that already have an IPv4 address or whose network has no pool are left alone.
+    pub async fn allocate(&self, ifname: &str) -> Result<Option<IpAddressRecord>, crate::Error> {
+        let registry = self.context.registry();
+        let nic = registry.get_network_interface_by_ifname(ifname).await?;
+        let Some(pool) = registry.find_ip_pool_by_network(nic.network.as_deref()).await? else {
+            return Ok(None);
+        };
+        if !registry.get_ipv4_by_ifname(&nic.ifname).await?.is_empty() {
+            return Ok(None);
+        }
+        let (_, prefix_len) = pool.subnet()
+            .ok_or(crate::Error::InvalidIpPool(pool.cidr.clone()))?;
+        let assigned: HashSet<String> = registry.get_ipv4_addresses().await?
+            .into_iter()
+            .map(|record| record.address)
+            .collect();
+        let address = pool.hosts()
+            .find(|address| !assigned.contains(&address.to_string()))
+            .ok_or(crate::Error::IpPoolExhausted(pool.name.clone()))?;
+        let record = NetworkManager::new(self.context)
+            .add_address(&nic.ifname, &address.to_string(), prefix_len, pool.gateway.clone(), AddressMode::Static)
+            .await?;
+        log::info!("Allocated {} from pool {} to {}", record.address, pool.name, nic.ifname);
+        Ok(Some(record))
+    }
+
+    /// Allocates addresses for all interfaces of a newly created VM.
+    pub async fn allocate_vm(&self, vm_id: &str) -> Result<(), crate::Error> {
+        for nic in self.context.registry().get_network_interfaces_by_vm_id(vm_id).await? {
+            self.allocate(&nic.ifname).await?;
+        }
+        Ok(())
+    }
+}
+
+fn validate_pool(pool: &IpPoolRecord) -> Result<(), crate::Error> {
+    let invalid = |reason: String| crate::Error::InvalidIpPool(format!("{}: {}", pool.name, reason));
+    let (network, prefix_len) = pool.subnet()
+        .ok_or_else(|| invalid(format!("{} is not an IPv4 subnet", pool.cidr)))?;
+    if prefix_len == 0 || prefix_len > 30 {
+        return Err(invalid(format!("prefix length {} out of range", prefix_len)));
+    }
+    if pool.cidr != format!("{}/{}", network, prefix_len) {
+        return Err(invalid(format!("{} has host bits set", pool.cidr)));
+    }
+    let in_subnet = |address: &str| address.parse::<Ipv4Addr>().is_ok_and(|address| pool.contains(address));
+    if let Some(gateway) = &pool.gateway
+        && !in_subnet(gateway)
+    {
+        return Err(invalid(format!("gateway {} is outside {}", gateway, pool.cidr)));
+    }
+    for range in &pool.reserved {
+        let ordered = matches!(
+            (range.start.parse::<Ipv4Addr>(), range.end.parse::<Ipv4Addr>()),
+            (Ok(start), Ok(end)) if start <= end
+        );
+        if !ordered || !in_subnet(&range.start) || !in_subnet(&range.end) {
+            return Err(invalid(format!("reserved range {}-{} is not within {}", range.start, range.end, pool.cidr)));
+        }
+    }
+    if let Some(nameserver) = pool.nameservers.iter().find(|nameserver| nameserver.parse::<IpAddr>().is_err()) {
+        return Err(invalid(format!("invalid nameserver {}", nameserver)));
+    }
+    Ok(())
+}
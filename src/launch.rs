@@ -1,25 +1,95 @@
-use std::path::PathBuf;
+use std::{collections::HashMap, fs::{File, TryLockError}, path::{Path, PathBuf}, sync::Arc};
 
+use async_trait::async_trait;
 use qemu::{KVM};
-use vm_types::vm::{DriveBus, VmLaunchRequest};
+use qmp::types::{InvokeCommand, IoThrottle};
+use vm_types::vm::{DriveBus, IoLimits, NetdevBackend, NetworkConfig, NicModel, VmLaunchRequest};
 
 use crate::Error;
 
+const SCSI_CONTROLLER: &str = "scsi0";
+const USB_CONTROLLER: &str = "usb0";
+
+/// Id of the `-object secret` holding the key of an encrypted drive.
+pub fn key_secret_id(drive_id: &str) -> String {
+    format!("{}-key", drive_id)
+}
+
+/// Persistent dirty bitmap tracking the writes to a drive since its last backup.
+pub const BACKUP_BITMAP: &str = "yave-backup";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BitmapState {
+    Missing,
+    Consistent,
+    /// The VM was not shut down cleanly, the bitmap can no longer be trusted.
+    Inconsistent,
+}
+
+/// State of a drive of a running VM as far as backups are concerned, from `query-block`.
+#[derive(Debug, Clone)]
+pub struct DriveBackupState {
+    pub qcow2: bool,
+    /// Virtual size in bytes.
+    pub size: u64,
+    pub bitmap: BitmapState,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum DriveBackupMode {
+    /// Full copy of a drive that cannot hold a persistent bitmap.
+    Full,
+    /// Full copy starting a new chain, the bitmap is reset together with the copy.
+    Tracked(BitmapState),
+    /// Copy of the clusters dirtied since the previous backup into a prepared overlay.
+    Incremental,
+}
+
+#[derive(Debug, Clone)]
+pub struct DriveBackupTarget {
+    pub drive_id: String,
+    pub path: PathBuf,
+    pub mode: DriveBackupMode,
+}
+
+/// Progress of a migration, from `query-migrate`. Sizes are in bytes.
+#[derive(Debug, Clone, Default)]
+pub struct MigrationStatus {
+    pub status: String,
+    pub transferred: u64,
+    pub remaining: u64,
+    pub total: u64,
+    pub error: Option<String>,
+}
+
+/// Host side setup of the TAP interfaces of a VM. `up` runs once the TAP has been created and
+/// before QEMU opens it, `down` after QEMU has quit and before the TAP is deleted.
+#[async_trait]
+pub trait InterfaceHook: Send + Sync {
+    async fn up(&self, ifname: &str) -> Result<(), Error>;
+    async fn down(&self, ifname: &str) -> Result<(), Error>;
+}
+
 pub struct VmRuntime {
     kvm: PathBuf,
     run_dir: PathBuf,
     ovmf_code: PathBuf,
     ovmf_vars: PathBuf,
-    netdev_up_script: Option<PathBuf>,
-    netdev_down_script: Option<PathBuf>,
+    interface_hook: Option<Arc<dyn InterfaceHook>>,
 }
 
 impl VmRuntime {
-    pub fn new(kvm: impl Into<PathBuf>, run_dir: impl Into<PathBuf>, ovmf_code: impl Into<PathBuf>, ovmf_vars: impl Into<PathBuf>, netdev_up_script: Option<PathBuf>, netdev_down_script: Option<PathBuf>) -> Self {
-        Self { kvm: kvm.into(), run_dir: run_dir.into(), ovmf_code: ovmf_code.into(), ovmf_vars: ovmf_vars.into(), netdev_up_script, netdev_down_script }
+    pub fn new(kvm: impl Into<PathBuf>, run_dir: impl Into<PathBuf>, ovmf_code: impl Into<PathBuf>, ovmf_vars: impl Into<PathBuf>, interface_hook: Option<Arc<dyn InterfaceHook>>) -> Self {
+        Self { kvm: kvm.into(), run_dir: run_dir.into(), ovmf_code: ovmf_code.into(), ovmf_vars: ovmf_vars.into(), interface_hook }
     }
 
-    fn args(&self, vm_request: &VmLaunchRequest) -> Vec<String> {
+    /// Queue pairs of a multiqueue virtio interface, one per vCPU.
+    fn queues(vm_request: &VmLaunchRequest, network: &NetworkConfig) -> Option<u32> {
+        let options = &network.options;
+        (options.model == NicModel::Virtio && options.multiqueue && vm_request.vcpu > 1).then_some(vm_request.vcpu)
+    }
+
+    fn kvm(&self, vm_request: &VmLaunchRequest) -> KVM {
         let mut qemu = KVM::new(&self.kvm.to_string_lossy())
             .enable_kvm()
             .nodefaults()
@@ -33,8 +103,20 @@ impl VmRuntime {
         if vm_request.ovmf {
             qemu = qemu.ovmf(&self.ovmf_code, &self.ovmf_vars);
         }
+        if vm_request.drives.iter().any(|drive| matches!(drive.drive_media, DriveBus::VirtioScsi { .. })) {
+            qemu = qemu.virtio_scsi_controller(SCSI_CONTROLLER);
+        }
+        if vm_request.drives.iter().any(|drive| matches!(drive.drive_media, DriveBus::UsbStorage { .. })) {
+            qemu = qemu.xhci_controller(USB_CONTROLLER);
+        }
         for drive in &vm_request.drives {
-            qemu = qemu.drive(&drive.id, &drive.path);
+            let mut key_secret = None;
+            if let Some(key_file) = &drive.key_file {
+                let secret_id = key_secret_id(&drive.id);
+                qemu = qemu.object_secret(&secret_id, key_file);
+                key_secret = Some(secret_id);
+            }
+            qemu = qemu.drive(&drive.id, drive.path.as_ref(), &drive.options, key_secret.as_deref());
             match &drive.drive_media {
                 DriveBus::Ide { media_type, boot_index } => {
                     qemu = qemu.ide_device(&drive.id, *boot_index, &media_type.clone().into());
@@ -42,34 +124,186 @@ impl VmRuntime {
                 DriveBus::VirtioBlk { boot_index } => {
                     qemu = qemu.virtio_blk(&drive.id, *boot_index);
                 },
+                DriveBus::VirtioScsi { lun, boot_index } => {
+                    qemu = qemu.scsi_hd(&drive.id, SCSI_CONTROLLER, *lun, *boot_index);
+                },
+                DriveBus::Nvme { serial, boot_index } => {
+                    qemu = qemu.nvme(&drive.id, serial, *boot_index);
+                },
+                DriveBus::UsbStorage { boot_index } => {
+                    qemu = qemu.usb_storage(&drive.id, USB_CONTROLLER, *boot_index);
+                },
             }
         }
         if let Some(vnc_display) = &vm_request.vnc {
             qemu = qemu.vnc(vnc_display, true);
         }
         for network in &vm_request.networks {
-            qemu = qemu.netdev_tap(&network.id, self.netdev_up_script.as_ref(), self.netdev_down_script.as_ref(), &network.ifname);
-            qemu = qemu.network_device(&network.id, &network.mac);
+            let options = &network.options;
+            let queues = Self::queues(vm_request, network);
+            qemu = match &network.backend {
+                NetdevBackend::Tap => qemu.netdev_tap(&network.id, &network.ifname, options.model == NicModel::Virtio && options.vhost, queues),
+                NetdevBackend::Mcast { group, local } => qemu.netdev_socket_mcast(&network.id, group, local),
+                NetdevBackend::Udp { local, remote } => qemu.netdev_socket_udp(&network.id, local, remote),
+            };
+            qemu = match options.model {
+                NicModel::Virtio => qemu.virtio_net_device(&network.id, &network.mac, queues, options.mtu),
+                model => qemu.network_device(&network.id, &network.mac, model),
+            };
         }
-        qemu.build()
+        qemu
+    }
+
+    fn args(&self, vm_request: &VmLaunchRequest) -> Vec<String> {
+        self.kvm(vm_request).build()
+    }
+
+    /// Replaces the TAP interfaces of the VM with fresh persistent ones and sets them up.
+    async fn create_interfaces(&self, vm_request: &VmLaunchRequest) -> Result<(), Error> {
+        for network in vm_request.networks.iter().filter(|network| network.backend == NetdevBackend::Tap) {
+            crate::interface::delete_link(&network.ifname).await?;
+            crate::interface::create_tap(&network.ifname, Self::queues(vm_request, network).is_some())?;
+            if let Some(hook) = &self.interface_hook {
+                hook.up(&network.ifname).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Tears down and deletes the TAP interfaces of a VM that is no longer running.
+    pub async fn delete_interfaces(&self, vm_request: &VmLaunchRequest) -> Result<(), Error> {
+        for network in vm_request.networks.iter().filter(|network| network.backend == NetdevBackend::Tap) {
+            if let Some(hook) = &self.interface_hook {
+                hook.down(&network.ifname).await?;
+            }
+            crate::interface::delete_link(&network.ifname).await?;
+        }
+        Ok(())
     }
 
-    pub async fn run_vm(&self, vm_request: &VmLaunchRequest) -> Result<(), Error> {
-        let args = self.args(vm_request);
-        let mut command = tokio::process::Command::new(&args[0]);
-        command.args(&args[1..]);
-        command.status().await?;
+    /// Tears down the TAP interfaces left behind by a QEMU that exited on its own, e.g. on a
+    /// guest poweroff or a crash. A VM being launched is left alone.
+    async fn reap(&self, vm_request: &VmLaunchRequest) -> Result<(), Error> {
+        let mut stale = false;
+        for network in vm_request.networks.iter().filter(|network| network.backend == NetdevBackend::Tap) {
+            stale |= crate::interface::interface_exists(&network.ifname).await?;
+        }
+        if !stale {
+            return Ok(());
+        }
+        let Some(_lock) = self.launch_lock(vm_request)? else {
+            return Ok(());
+        };
+        if self.qmp_connect(vm_request).await.is_ok() {
+            return Ok(());
+        }
+        log::info!("QEMU of VM {} has exited, removing its interfaces", vm_request.id);
+        self.delete_interfaces(vm_request).await
+    }
+
+    /// Lock held from creating the TAP interfaces of a VM until QEMU is up, so `reap` does not
+    /// mistake them for leftovers. `None` while another launch holds it.
+    fn launch_lock(&self, vm_request: &VmLaunchRequest) -> Result<Option<File>, Error> {
+        let file = File::create(self.run_dir.join(&vm_request.id).with_added_extension("lock"))?;
+        match file.try_lock() {
+            Ok(()) => Ok(Some(file)),
+            Err(TryLockError::WouldBlock) => Ok(None),
+            Err(TryLockError::Error(err)) => Err(err.into()),
+        }
+    }
+
+    /// Starts QEMU, which daemonizes once the VM is set up. If it fails to, the TAP interfaces
+    /// are removed again.
+    async fn launch(&self, vm_request: &VmLaunchRequest, args: Vec<String>) -> Result<(), Error> {
+        if self.is_running(vm_request).await? {
+            return Err(Error::VMRunning);
+        }
+        let Some(_lock) = self.launch_lock(vm_request)? else {
+            return Err(Error::VMRunning);
+        };
+        let launched = async {
+            self.create_interfaces(vm_request).await?;
+            let mut command = tokio::process::Command::new(&args[0]);
+            command.args(&args[1..]);
+            let status = command.status().await?;
+            if !status.success() {
+                return Err(Error::Command(format!("{} exited with {}", args[0], status)));
+            }
+            Ok(())
+        }.await;
+        if let Err(err) = launched {
+            if let Err(cleanup) = self.delete_interfaces(vm_request).await {
+                log::warn!("Error removing the interfaces of VM {}: {}", vm_request.id, cleanup);
+            }
+            return Err(err);
+        }
         log::debug!("Launched VM with params {:?} (args: {:?})", vm_request, args);
         Ok(())
     }
 
+    /// Boots the VM, or with `resume` continues it from its saved state if there is one.
+    /// A VM with a saved state is only booted afresh once the state has been discarded,
+    /// see [`crate::storage::VmStorage::discard_state`].
+    pub async fn run_vm(&self, vm_request: &VmLaunchRequest, resume: bool) -> Result<(), Error> {
+        let Some(state_file) = &vm_request.state_file else {
+            return self.launch(vm_request, self.args(vm_request)).await;
+        };
+        if !resume {
+            return Err(Error::SavedState(vm_request.id.clone()));
+        }
+        self.run_incoming(vm_request, &format!("file:{}", state_file)).await?;
+        self.wait_for_migration(vm_request).await?;
+        let qmp = self.qmp_connect(vm_request).await?;
+        qmp.invoke(InvokeCommand::cont()).await?.into_result()?;
+        std::fs::remove_file(state_file)?;
+        log::debug!("Resumed VM {} from {}", vm_request.id, state_file);
+        Ok(())
+    }
+
+    /// Stops the guest, writes its memory and device state to `path` and quits QEMU.
+    /// If saving fails the guest keeps running.
+    pub async fn save_state(&self, vm_request: &VmLaunchRequest, path: &Path) -> Result<(), Error> {
+        let qmp = self.qmp_connect(vm_request).await?;
+        qmp.invoke(InvokeCommand::stop()).await?.into_result()?;
+        let saved = async {
+            qmp.invoke(InvokeCommand::migrate(&format!("file:{}", path.to_string_lossy()))).await?.into_result()?;
+            self.wait_for_migration(vm_request).await
+        }.await;
+        if let Err(err) = saved {
+            qmp.invoke(InvokeCommand::cont()).await?.into_result()?;
+            if path.exists() {
+                std::fs::remove_file(path)?;
+            }
+            return Err(err);
+        }
+        log::debug!("Saved state of VM {} to {:?}", vm_request.id, path);
+        self.shutdown_vm(vm_request).await
+    }
+
+    async fn wait_for_migration(&self, vm_request: &VmLaunchRequest) -> Result<(), Error> {
+        loop {
+            let status = self.query_migrate(vm_request).await?;
+            match status.status.as_str() {
+                "completed" => return Ok(()),
+                "failed" | "cancelled" => return Err(Error::Migration(status.error.unwrap_or(status.status))),
+                _ => tokio::time::sleep(std::time::Duration::from_millis(500)).await,
+            }
+        }
+    }
+
+    /// Launches the VM waiting for an incoming migration on `uri` (e.g. `tcp:0.0.0.0:4444`).
+    pub async fn run_incoming(&self, vm_request: &VmLaunchRequest, uri: &str) -> Result<(), Error> {
+        self.launch(vm_request, self.kvm(vm_request).incoming(uri).build()).await
+    }
+
+
     pub async fn shutdown_vm(&self, vm_request: &VmLaunchRequest) -> Result<(), Error> {
         log::debug!("Shutting down VM {}", vm_request.id);
         let mut qmp = self.qmp_connect(vm_request).await?;
         qmp.invoke(qmp::types::InvokeCommand::quit()).await?;
         qmp.on_close().await?;
         log::debug!("Shutdown command sent to VM {}", vm_request.id);
-        Ok(())
+        self.delete_interfaces(vm_request).await
     }
 
     pub async fn reboot_vm(&self, vm_request: &VmLaunchRequest) -> Result<(), Error> {
@@ -79,6 +313,266 @@ impl VmRuntime {
         Ok(())
     }
 
+    pub async fn resize_drive(&self, vm_request: &VmLaunchRequest, drive_id: &str, size: u64) -> Result<(), Error> {
+        let qmp = self.qmp_connect(vm_request).await?;
+        qmp.invoke(qmp::types::InvokeCommand::block_resize(drive_id, size * 1024 * 1024)).await?
+            .into_result()?;
+        log::debug!("Resized drive {} of VM {} to {}", drive_id, vm_request.id, size);
+        Ok(())
+    }
+
+    pub async fn set_io_limits(&self, vm_request: &VmLaunchRequest, drive_id: &str, limits: &IoLimits) -> Result<(), Error> {
+        let qmp = self.qmp_connect(vm_request).await?;
+        let throttle = IoThrottle {
+            bps: limits.bps_total.unwrap_or(0),
+            bps_rd: limits.bps_read.unwrap_or(0),
+            bps_wr: limits.bps_write.unwrap_or(0),
+            iops: limits.iops_total.unwrap_or(0),
+            iops_rd: limits.iops_read.unwrap_or(0),
+            iops_wr: limits.iops_write.unwrap_or(0),
+        };
+        qmp.invoke(qmp::types::InvokeCommand::block_set_io_throttle(drive_id, &throttle)).await?
+            .into_result()?;
+        log::debug!("Set I/O limits of drive {} of VM {} to {:?}", drive_id, vm_request.id, limits);
+        Ok(())
+    }
+
+    /// Sets the link of a network interface up or down as seen by the guest.
+    pub async fn set_link(&self, vm_request: &VmLaunchRequest, netdev_id: &str, up: bool) -> Result<(), Error> {
+        let qmp = self.qmp_connect(vm_request).await?;
+        qmp.invoke(qmp::types::InvokeCommand::set_link(netdev_id, up)).await?
+            .into_result()?;
+        log::debug!("Set link of {} of VM {} {}", netdev_id, vm_request.id, if up { "up" } else { "down" });
+        Ok(())
+    }
+
+    /// Inserts `media` into a CD-ROM drive, or ejects it when `None`.
+    pub async fn change_media(&self, vm_request: &VmLaunchRequest, drive_id: &str, media: Option<&Path>) -> Result<(), Error> {
+        let qmp = self.qmp_connect(vm_request).await?;
+        let command = match media {
+            Some(path) => qmp::types::InvokeCommand::blockdev_change_medium(drive_id, &path.to_string_lossy()),
+            None => qmp::types::InvokeCommand::eject(drive_id),
+        };
+        qmp.invoke(command).await?.into_result()?;
+        log::debug!("Changed media of drive {} of VM {} to {:?}", drive_id, vm_request.id, media);
+        Ok(())
+    }
+
+    /// Point-in-time backup of several drives at once, `targets` maps drive ids to new qcow2 images.
+    pub async fn backup_drives(&self, vm_request: &VmLaunchRequest, targets: &[(String, PathBuf)]) -> Result<(), Error> {
+        let qmp = self.qmp_connect(vm_request).await?;
+        let mut actions = vec![];
+        let mut jobs = vec![];
+        for (drive_id, target) in targets {
+            let job_id = format!("backup-{}", drive_id);
+            actions.push(InvokeCommand::drive_backup(&job_id, drive_id, &target.to_string_lossy()));
+            jobs.push(job_id);
+        }
+        qmp.invoke(InvokeCommand::transaction(actions)).await?.into_result()?;
+        log::debug!("Started backup jobs {:?} for VM {}", jobs, vm_request.id);
+        self.wait_for_jobs(&qmp, &jobs).await
+    }
+
+    pub async fn drive_backup_states(&self, vm_request: &VmLaunchRequest) -> Result<HashMap<String, DriveBackupState>, Error> {
+        let qmp = self.qmp_connect(vm_request).await?;
+        let result = qmp.invoke(InvokeCommand::query_block()).await?.into_result()?;
+        let states = result.as_array().into_iter().flatten().filter_map(|block| {
+            let device = block["device"].as_str()?;
+            let inserted = block.get("inserted")?;
+            let bitmap = inserted["dirty-bitmaps"].as_array().into_iter().flatten()
+                .chain(block["dirty-bitmaps"].as_array().into_iter().flatten())
+                .find(|bitmap| bitmap["name"] == BACKUP_BITMAP);
+            let bitmap = match bitmap {
+                None => BitmapState::Missing,
+                Some(bitmap) if bitmap["inconsistent"] == true => BitmapState::Inconsistent,
+                Some(_) => BitmapState::Consistent,
+            };
+            Some((device.to_string(), DriveBackupState {
+                qcow2: inserted["drv"] == "qcow2",
+                size: inserted["image"]["virtual-size"].as_u64().unwrap_or(0),
+                bitmap,
+            }))
+        }).collect();
+        Ok(states)
+    }
+
+    /// Point-in-time backup of several drives at once, full or incremental per drive.
+    /// If any job fails the backup bitmaps are dropped, so the next backup starts a new chain.
+    pub async fn backup_drives_incremental(&self, vm_request: &VmLaunchRequest, targets: &[DriveBackupTarget]) -> Result<(), Error> {
+        let qmp = self.qmp_connect(vm_request).await?;
+        let mut actions = vec![];
+        let mut jobs = vec![];
+        for target in targets {
+            let job_id = format!("backup-{}", target.drive_id);
+            let path = target.path.to_string_lossy();
+            match target.mode {
+                DriveBackupMode::Full => {
+                    actions.push(InvokeCommand::drive_backup(&job_id, &target.drive_id, &path));
+                }
+                DriveBackupMode::Tracked(bitmap) => {
+                    match bitmap {
+                        BitmapState::Missing => {
+                            actions.push(InvokeCommand::block_dirty_bitmap_add(&target.drive_id, BACKUP_BITMAP, true));
+                        }
+                        BitmapState::Consistent => {
+                            actions.push(InvokeCommand::block_dirty_bitmap_clear(&target.drive_id, BACKUP_BITMAP));
+                        }
+                        BitmapState::Inconsistent => {
+                            qmp.invoke(InvokeCommand::block_dirty_bitmap_remove(&target.drive_id, BACKUP_BITMAP)).await?.into_result()?;
+                            actions.push(InvokeCommand::block_dirty_bitmap_add(&target.drive_id, BACKUP_BITMAP, true));
+                        }
+                    }
+                    actions.push(InvokeCommand::drive_backup(&job_id, &target.drive_id, &path));
+                }
+                DriveBackupMode::Incremental => {
+                    actions.push(InvokeCommand::drive_backup_incremental(&job_id, &target.drive_id, &path, BACKUP_BITMAP));
+                }
+            }
+            jobs.push(job_id);
+        }
+        qmp.invoke(InvokeCommand::grouped_transaction(actions)).await?.into_result()?;
+        log::debug!("Started backup jobs {:?} for VM {}", jobs, vm_request.id);
+        if let Err(err) = self.wait_for_jobs(&qmp, &jobs).await {
+            for target in targets.iter().filter(|target| !matches!(target.mode, DriveBackupMode::Full)) {
+                let removed = qmp.invoke(InvokeCommand::block_dirty_bitmap_remove(&target.drive_id, BACKUP_BITMAP)).await
+                    .map_err(Error::from)
+                    .and_then(|response| response.into_result().map_err(Error::from));
+                if let Err(err) = removed {
+                    log::warn!("Failed to remove backup bitmap of drive {} of VM {}: {}", target.drive_id, vm_request.id, err);
+                }
+            }
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    /// Exports the drives of a VM waiting for a block migration over NBD.
+    pub async fn start_nbd_server(&self, vm_request: &VmLaunchRequest, host: &str, port: u16, drives: &[String]) -> Result<(), Error> {
+        let qmp = self.qmp_connect(vm_request).await?;
+        qmp.invoke(InvokeCommand::nbd_server_start(host, port)).await?.into_result()?;
+        for drive_id in drives {
+            qmp.invoke(InvokeCommand::nbd_server_add(drive_id, true)).await?.into_result()?;
+        }
+        log::debug!("Exported drives {:?} of VM {} over NBD on {}:{}", drives, vm_request.id, host, port);
+        Ok(())
+    }
+
+    pub async fn stop_nbd_server(&self, vm_request: &VmLaunchRequest) -> Result<(), Error> {
+        let qmp = self.qmp_connect(vm_request).await?;
+        qmp.invoke(InvokeCommand::nbd_server_stop()).await?.into_result()?;
+        Ok(())
+    }
+
+    /// Starts mirroring drives into NBD exports, `targets` maps drive ids to NBD URIs.
+    /// Returns once every mirror is in sync, the mirrors keep copying guest writes afterwards.
+    pub async fn mirror_drives(&self, vm_request: &VmLaunchRequest, targets: &[(String, String)]) -> Result<(), Error> {
+        let qmp = self.qmp_connect(vm_request).await?;
+        let mut jobs = vec![];
+        for (drive_id, target) in targets {
+            let job_id = format!("mirror-{}", drive_id);
+            qmp.invoke(InvokeCommand::drive_mirror(&job_id, drive_id, target)).await?.into_result()?;
+            jobs.push(job_id);
+        }
+        log::debug!("Started mirror jobs {:?} for VM {}", jobs, vm_request.id);
+        loop {
+            let result = qmp.invoke(InvokeCommand::query_jobs()).await?.into_result()?;
+            let states = result.as_array().into_iter().flatten()
+                .filter(|job| jobs.iter().any(|id| job["id"] == id.as_str()))
+                .collect::<Vec<_>>();
+            if let Some(job) = states.iter().find(|job| job["status"] == "concluded" || job["status"] == "aborting") {
+                let message = job["error"].as_str().unwrap_or("mirror job aborted");
+                return Err(Error::Migration(format!("{}: {}", job["id"], message)));
+            }
+            if states.len() == jobs.len() && states.iter().all(|job| job["status"] == "ready") {
+                return Ok(());
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+        }
+    }
+
+    /// Ends the mirrors started by [`VmRuntime::mirror_drives`] without switching the VM to their
+    /// targets. Unless `abort` is set, the targets are brought in sync first.
+    pub async fn finish_mirrors(&self, vm_request: &VmLaunchRequest, drives: &[String], abort: bool) -> Result<(), Error> {
+        let qmp = self.qmp_connect(vm_request).await?;
+        let jobs = drives.iter().map(|drive_id| format!("mirror-{}", drive_id)).collect::<Vec<_>>();
+        for job_id in &jobs {
+            let result = qmp.invoke(InvokeCommand::block_job_cancel(job_id, abort)).await?.into_result();
+            if !abort {
+                result?;
+            }
+        }
+        self.wait_for_jobs(&qmp, &jobs).await
+    }
+
+    /// Sets the migration capabilities and starts migrating the VM to `uri`.
+    pub async fn migrate(&self, vm_request: &VmLaunchRequest, uri: &str, capabilities: &[(&str, bool)]) -> Result<(), Error> {
+        let qmp = self.qmp_connect(vm_request).await?;
+        qmp.invoke(InvokeCommand::migrate_set_capabilities(capabilities)).await?.into_result()?;
+        qmp.invoke(InvokeCommand::migrate(uri)).await?.into_result()?;
+        log::debug!("Started migration of VM {} to {}", vm_request.id, uri);
+        Ok(())
+    }
+
+    pub async fn query_migrate(&self, vm_request: &VmLaunchRequest) -> Result<MigrationStatus, Error> {
+        let qmp = self.qmp_connect(vm_request).await?;
+        let result = qmp.invoke(InvokeCommand::query_migrate()).await?.into_result()?;
+        Ok(MigrationStatus {
+            status: result["status"].as_str().unwrap_or("none").to_string(),
+            transferred: result["ram"]["transferred"].as_u64().unwrap_or(0),
+            remaining: result["ram"]["remaining"].as_u64().unwrap_or(0),
+            total: result["ram"]["total"].as_u64().unwrap_or(0),
+            error: result["error-desc"].as_str().map(|error| error.to_string()),
+        })
+    }
+
+    pub async fn continue_migration(&self, vm_request: &VmLaunchRequest, state: &str) -> Result<(), Error> {
+        let qmp = self.qmp_connect(vm_request).await?;
+        qmp.invoke(InvokeCommand::migrate_continue(state)).await?.into_result()?;
+        Ok(())
+    }
+
+    /// Cancels a running migration together with its drive mirrors.
+    pub async fn cancel_migration(&self, vm_request: &VmLaunchRequest) -> Result<(), Error> {
+        let qmp = self.qmp_connect(vm_request).await?;
+        qmp.invoke(InvokeCommand::migrate_cancel()).await?.into_result()?;
+        for drive in &vm_request.drives {
+            let job_id = format!("mirror-{}", drive.id);
+            if qmp.invoke(InvokeCommand::block_job_cancel(&job_id, true)).await?.into_result().is_ok() {
+                log::debug!("Cancelled mirror job {} of VM {}", job_id, vm_request.id);
+            }
+        }
+        log::debug!("Cancelled migration of VM {}", vm_request.id);
+        Ok(())
+    }
+
+    /// Polls `query-jobs` until all `jobs` have concluded, then dismisses them.
+    pub(crate) async fn wait_for_jobs(&self, qmp: &qmp::client::Client, jobs: &[String]) -> Result<(), Error> {
+        loop {
+            let result = qmp.invoke(InvokeCommand::query_jobs()).await?.into_result()?;
+            let running = result.as_array()
+                .map(|all| all.iter()
+                    .filter(|job| jobs.iter().any(|id| job["id"] == id.as_str()))
+                    .any(|job| job["status"] != "concluded"))
+                .unwrap_or(false);
+            if !running {
+                let mut error = None;
+                for job in result.as_array().into_iter().flatten() {
+                    if let Some(id) = job["id"].as_str().filter(|id| jobs.iter().any(|job_id| job_id == id)) {
+                        if let Some(message) = job["error"].as_str() {
+                            error = Some(format!("{}: {}", id, message));
+                        }
+                        qmp.invoke(InvokeCommand::job_dismiss(id)).await?.into_result()?;
+                    }
+                }
+                return match error {
+                    Some(error) => Err(Error::Command(error)),
+                    None => Ok(()),
+                };
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+        }
+    }
+
     pub async fn qmp_connect(&self, vm_request: &VmLaunchRequest) -> Result<qmp::client::Client, Error> {
         let socket_path = self.run_dir.join(&vm_request.id).with_added_extension("sock");
         if !socket_path.exists() {
@@ -88,11 +582,12 @@ impl VmRuntime {
         Ok(qmp)
     }
 
+    /// Whether QEMU of the VM is up. If it is not, interfaces it left behind are reaped.
     pub async fn is_running(&self, vm_request: &VmLaunchRequest) -> Result<bool, Error> {
-        match self.qmp_connect(vm_request).await {
-            Ok(client) => client,
-            Err(_) => return Ok(false),
-        };
-        Ok(true)
+        if self.qmp_connect(vm_request).await.is_ok() {
+            return Ok(true);
+        }
+        self.reap(vm_request).await?;
+        Ok(false)
     }
 }
@@ -1,11 +1,21 @@
-use crate::{constants::{get_config_path, get_net_script, get_run_path, get_vm_config_path}, context::YaveContext};
+use crate::{constants::{get_config_path, get_run_path, get_vm_config_path}, context::YaveContext};
 
 mod constants;
 mod interface;
+pub mod backup;
+pub mod clone;
 pub mod context;
+pub mod dhcp;
+pub mod drive;
+pub mod ipam;
 pub mod launch;
+pub mod migration;
+pub mod port_forward;
 pub mod registry;
+pub mod security_group;
+pub mod secrets;
 pub mod storage;
+pub mod switch;
 
 pub mod cloudinit;
 pub mod builders;
@@ -23,20 +33,96 @@ pub enum Error {
     QMP(#[from] qmp::Error),
     #[error("rtnetlink Error: {0}")]
     Rnetlink(#[from] rtnetlink::Error),
+    #[error("nftables Error: {0}")]
+    Nft(#[from] nft::Error),
     #[error("Signal Error: {0}")]
     Signal(#[from] nix::Error),
     #[error("Database Error: {0}")]
     Database(#[from] sqlx::Error),
     #[error("No free interface names available")]
     NoFreeIfname,
+    #[error("Command failed: {0}")]
+    Command(String),
 
     // Errors with logic
     #[error("VM Instance is not running: {0}")]
     VMNotRunning(String),
     #[error("VM Instance is already running")]
     VMRunning,
+    #[error("VM {0} has a saved state, resume or discard it")]
+    SavedState(String),
+    #[error("VM already exists: {0}")]
+    VMExists(String),
+    #[error("Invalid backup archive: {0}")]
+    InvalidBackup(String),
     #[error("VM not found")]
-    VMNotFound
+    VMNotFound,
+    #[error("Drive not found")]
+    DriveNotFound,
+    #[error("Refusing to shrink drive {0} without force")]
+    DriveShrink(String),
+    #[error("Drive {0} is not encrypted")]
+    DriveNotEncrypted(String),
+    #[error("Drive {0} is not a CD-ROM")]
+    DriveNotCdrom(String),
+    #[error("Image not found: {0}")]
+    ImageNotFound(String),
+    #[error("Backup not found")]
+    BackupNotFound,
+    #[error("Network not found: {0}")]
+    NetworkNotFound(String),
+    #[error("Network already exists: {0}")]
+    NetworkExists(String),
+    #[error("Network {0} still has interfaces attached")]
+    NetworkInUse(String),
+    #[error("Invalid network address: {0}")]
+    InvalidNetworkAddress(String),
+    #[error("Invalid network: {0}")]
+    InvalidNetwork(String),
+    #[error("Network {0} already links two interfaces")]
+    NetworkFull(String),
+    #[error("Filtering unsupported: {0}")]
+    FilteringUnsupported(String),
+    #[error("Invalid VLAN id: {0}")]
+    InvalidVlan(u16),
+    #[error("Invalid drive options: {0}")]
+    InvalidDriveOptions(String),
+    #[error("Invalid network interface options: {0}")]
+    InvalidNicOptions(String),
+    #[error("Invalid MAC address: {0}")]
+    InvalidMacAddress(String),
+    #[error("MAC address already in use: {0}")]
+    MacAddressInUse(String),
+    #[error("Invalid IP address: {0}")]
+    InvalidIpAddress(String),
+    #[error("IP address not found: {0}")]
+    IpAddressNotFound(String),
+    #[error("IP pool not found: {0}")]
+    IpPoolNotFound(String),
+    #[error("IP pool already exists: {0}")]
+    IpPoolExists(String),
+    #[error("IP pool exhausted: {0}")]
+    IpPoolExhausted(String),
+    #[error("Invalid IP pool: {0}")]
+    InvalidIpPool(String),
+    #[error("Port forward not found")]
+    PortForwardNotFound,
+    #[error("Port forward conflict: {0}")]
+    PortForwardConflict(String),
+    #[error("Invalid port forward: {0}")]
+    InvalidPortForward(String),
+    #[error("Security group not found: {0}")]
+    SecurityGroupNotFound(String),
+    #[error("Security group already exists: {0}")]
+    SecurityGroupExists(String),
+    #[error("Security group {0} is still attached to interfaces")]
+    SecurityGroupInUse(String),
+    #[error("Invalid security group rule: {0}")]
+    InvalidSecurityGroupRule(String),
+    #[error("Migration failed: {0}")]
+    Migration(String),
+    #[error("Linked clones of encrypted drive {0} are not supported")]
+    LinkedCloneEncrypted(String),
 }
 
 pub struct DefaultYaveContext;
@@ -46,11 +132,7 @@ impl DefaultYaveContext {
         let config_path = get_config_path();
         let storage_path = get_vm_config_path();
         let run_path = get_run_path();
-        let netdev_scripts = context::NetdevScripts {
-            up: get_net_script(true),
-            down: get_net_script(false),
-        };
-        let context = YaveContext::load(config_path, storage_path, run_path, &netdev_scripts).await?;
+        let context = YaveContext::load(config_path, storage_path, run_path).await?;
         Ok(context)
     }
 }
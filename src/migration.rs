@@ -0,0 +1,156 @@
+use std::time::Duration;
+
+use vm_types::vm::VmLaunchRequest;
+
+use crate::{builders::VmLaunchRequestBuilder, context::YaveContext, launch::MigrationStatus};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Destination of an outgoing migration.
+#[derive(Debug, Clone)]
+pub struct MigrationTarget {
+    pub host: String,
+    pub port: u16,
+    /// Port of the destination's NBD server for block migration, `None` with shared storage.
+    pub nbd_port: Option<u16>,
+    pub auto_converge: bool,
+}
+
+/// Moves a running VM to another yave host. Both hosts must know the VM under the same id,
+/// either through shared storage (registry and drive images) or, for block migration, an
+/// identical definition whose drives are copied over NBD while the guest keeps running.
+pub struct MigrationManager<'ctx> {
+    context: &'ctx YaveContext,
+}
+
+impl<'ctx> MigrationManager<'ctx> {
+    pub fn new(context: &'ctx YaveContext) -> Self {
+        Self { context }
+    }
+
+    /// Destination side: launches the VM waiting for the migration on `host:port`. With
+    /// `nbd_port`, missing drive images are created and all disks are exported over NBD.
+    pub async fn incoming(&self, vm_id: &str, host: &str, port: u16, nbd_port: Option<u16>) -> Result<(), crate::Error> {
+        let registry = self.context.registry();
+        let storage = self.context.storage();
+        let runtime = self.context.runtime();
+        let launch_request = VmLaunchRequestBuilder::new(self.context).build(vm_id).await?;
+        if runtime.is_running(&launch_request).await? {
+            return Err(crate::Error::VMRunning);
+        }
+        let drives = registry.get_drives_by_vm_id(vm_id).await?
+            .into_iter()
+            .filter(|drive| !drive.is_cdrom())
+            .collect::<Vec<_>>();
+        if nbd_port.is_some() {
+            for drive in &drives {
+                if storage.drive_path(vm_id, &drive.id).exists() {
+                    continue;
+                }
+                if drive.size == 0 {
+                    return Err(crate::Error::Migration(format!("size of drive {} is unknown", drive.id)));
+                }
+                storage.create_empty_drive(vm_id, &drive.id, drive.size, drive.options.format, drive.encrypted).await?;
+            }
+        }
+        runtime.run_incoming(&launch_request, &format!("tcp:{}:{}", host, port)).await?;
+        if let Some(nbd_port) = nbd_port {
+            let drive_ids = drives.iter().map(|drive| drive.id.clone()).collect::<Vec<_>>();
+            runtime.start_nbd_server(&launch_request, host, nbd_port, &drive_ids).await?;
+        }
+        log::info!("VM {} is waiting for incoming migration on {}:{}", vm_id, host, port);
+        Ok(())
+    }
+
+    /// Destination side: waits until the incoming migration has completed, then stops the NBD server.
+    pub async fn wait_incoming(&self, vm_id: &str, nbd: bool, on_progress: impl Fn(&MigrationStatus)) -> Result<(), crate::Error> {
+        let runtime = self.context.runtime();
+        let launch_request = VmLaunchRequestBuilder::new(self.context).build(vm_id).await?;
+        loop {
+            let status = runtime.query_migrate(&launch_request).await?;
+            on_progress(&status);
+            match status.status.as_str() {
+                "completed" => break,
+                "failed" | "cancelled" => {
+                    return Err(crate::Error::Migration(status.error.unwrap_or(status.status)));
+                }
+                _ => tokio::time::sleep(POLL_INTERVAL).await,
+            }
+        }
+        if nbd {
+            runtime.stop_nbd_server(&launch_request).await?;
+        }
+        log::info!("Incoming migration of VM {} completed", vm_id);
+        Ok(())
+    }
+
+    /// Source side: migrates the VM to a destination prepared by [`MigrationManager::incoming`]
+    /// and stops it here once the destination has taken over. With an NBD port the disks are
+    /// mirrored to the destination first (block migration), otherwise storage must be shared.
+    pub async fn migrate(&self, vm_id: &str, target: &MigrationTarget, on_progress: impl Fn(&MigrationStatus)) -> Result<(), crate::Error> {
+        let registry = self.context.registry();
+        let runtime = self.context.runtime();
+        let launch_request = VmLaunchRequestBuilder::new(self.context).build(vm_id).await?;
+        if !runtime.is_running(&launch_request).await? {
+            return Err(crate::Error::VMNotRunning(vm_id.to_string()));
+        }
+        let drive_ids = registry.get_drives_by_vm_id(vm_id).await?
+            .into_iter()
+            .filter(|drive| !drive.is_cdrom())
+            .map(|drive| drive.id)
+            .collect::<Vec<_>>();
+
+        let result = self.run_migration(&launch_request, &drive_ids, target, on_progress).await;
+        if let Err(err) = result {
+            if target.nbd_port.is_some()
+                && let Err(err) = runtime.finish_mirrors(&launch_request, &drive_ids, true).await
+            {
+                log::warn!("Failed to abort drive mirrors of VM {}: {}", vm_id, err);
+            }
+            return Err(err);
+        }
+        runtime.shutdown_vm(&launch_request).await?;
+        log::info!("Migrated VM {} to {}:{}", vm_id, target.host, target.port);
+        Ok(())
+    }
+
+    async fn run_migration(&self, launch_request: &VmLaunchRequest, drive_ids: &[String], target: &MigrationTarget, on_progress: impl Fn(&MigrationStatus)) -> Result<(), crate::Error> {
+        let runtime = self.context.runtime();
+        if let Some(nbd_port) = target.nbd_port {
+            let targets = drive_ids.iter()
+                .map(|drive_id| (drive_id.clone(), format!("nbd://{}:{}/{}", target.host, nbd_port, drive_id)))
+                .collect::<Vec<_>>();
+            runtime.mirror_drives(launch_request, &targets).await?;
+        }
+        // Pausing before switchover lets the mirrors finish while the guest is stopped,
+        // so the destination disks are complete before it resumes there.
+        let capabilities = [
+            ("auto-converge", target.auto_converge),
+            ("pause-before-switchover", target.nbd_port.is_some()),
+        ];
+        runtime.migrate(launch_request, &format!("tcp:{}:{}", target.host, target.port), &capabilities).await?;
+        let mut switched = false;
+        loop {
+            let status = runtime.query_migrate(launch_request).await?;
+            on_progress(&status);
+            match status.status.as_str() {
+                "completed" => return Ok(()),
+                "pre-switchover" if !switched => {
+                    runtime.finish_mirrors(launch_request, drive_ids, false).await?;
+                    runtime.continue_migration(launch_request, "pre-switchover").await?;
+                    switched = true;
+                }
+                "failed" | "cancelled" => {
+                    return Err(crate::Error::Migration(status.error.unwrap_or(status.status)));
+                }
+                _ => tokio::time::sleep(POLL_INTERVAL).await,
+            }
+        }
+    }
+
+    /// Cancels an outgoing migration; the VM keeps running on this host.
+    pub async fn cancel(&self, vm_id: &str) -> Result<(), crate::Error> {
+        let launch_request = VmLaunchRequestBuilder::new(self.context).build(vm_id).await?;
+        self.context.runtime().cancel_migration(&launch_request).await
+    }
+}
@@ -1,4 +1,35 @@
-use crate::context::YaveContext;
+use std::net::{IpAddr, Ipv6Addr};
+
+use async_trait::async_trait;
+use nft::{batch::Batch, builders::Family, executor::Executor, types::{ChainType, Field, Hook, Policy, Statement, Table, Value}};
+use vm_types::{NetworkDefinition, SwitchBackend, vm::{NicModel, NicOptions}};
+
+use crate::{builders::VmLaunchRequestBuilder, context::YaveContext, launch::InterfaceHook, dhcp::Dnsmasq, switch::Switch, registry::{AddIpAddress, AddressMode, CreateNetwork, CreateSocketNetwork, SocketMode, SocketNetworkRecord, IpAddressRecord, IpFamily, NetworkInterfaceRecord, NetworkRecord}};
+
+/// Longest accepted network name.
+const MAX_NETWORK_NAME_LEN: usize = 32;
+
+/// Brings the TAPs created by `VmRuntime` up and down through `NetworkManager`.
+pub struct TapHook {
+    context: YaveContext,
+}
+
+impl TapHook {
+    pub fn new(context: YaveContext) -> Self {
+        Self { context }
+    }
+}
+
+#[async_trait]
+impl InterfaceHook for TapHook {
+    async fn up(&self, ifname: &str) -> Result<(), crate::Error> {
+        NetworkManager::new(&self.context).up_interface(ifname).await
+    }
+
+    async fn down(&self, ifname: &str) -> Result<(), crate::Error> {
+        NetworkManager::new(&self.context).down_interface(ifname).await
+    }
+}
 
 pub struct NetworkManager<'ctx> {
     context: &'ctx YaveContext,
@@ -11,13 +42,607 @@ impl <'ctx> NetworkManager<'ctx> {
 }
 
 impl <'ctx> NetworkManager<'ctx> {
+    /// Brings a TAP interface up and attaches it to the bridge of its network.
     pub async fn up_interface(&self, ifname: &str) -> Result<(), crate::Error> {
-        let vm = self.context.registry().get_vm_by_ifname(ifname).await?;
+        let registry = self.context.registry();
+        let vm = registry.get_vm_by_ifname(ifname).await?;
+        let nic = registry.get_network_interface_by_ifname(ifname).await?;
         println!("Bringing up interface {} for VM {}", ifname, vm.hostname);
+        let network = self.network_definition(nic.network.as_deref()).await?;
+        if let (Some(name), None) = (&nic.network, &network) {
+            return Err(crate::Error::NetworkNotFound(name.clone()));
+        }
+        if let Some(name) = &nic.network
+            && let Some(managed) = registry.find_network(name).await?
+            && !crate::interface::interface_exists(&managed.bridge).await?
+        {
+            self.apply_network(&managed).await?;
+        }
+        if let Some(mtu) = nic.options.mtu.or(network.as_ref().and_then(|network| network.mtu)) {
+            crate::interface::set_mtu(ifname, mtu).await?;
+        }
         crate::interface::set_link_up(ifname).await?;
-        if let Some(bridge) = &self.context.config().network.default_bridge {
-            crate::interface::set_master(ifname, bridge).await?;
+        if let Some(network) = &network {
+            self.switch(network).attach(ifname, &network.bridge, nic.vlan.or(network.vlan)).await?;
+        }
+        self.apply_interface_rules(ifname).await?;
+        registry.set_network_interface_attached(ifname, true).await?;
+        Ok(())
+    }
+
+    /// Undoes `up_interface`: drops the static FDB and neighbour entries of the TAP, releases it
+    /// from its bridge, removes its nftables rules and marks the interface detached.
+    pub async fn down_interface(&self, ifname: &str) -> Result<(), crate::Error> {
+        let registry = self.context.registry();
+        let nic = registry.get_network_interface_by_ifname(ifname).await?;
+        log::info!("Bringing down interface {} of VM {}", ifname, nic.vm_id);
+        crate::interface::delete_static_fdb(ifname).await?;
+        match self.network_definition(nic.network.as_deref()).await? {
+            Some(network) => {
+                let mac = nic.mac_address.split(':')
+                    .map(|octet| u8::from_str_radix(octet, 16))
+                    .collect::<Result<Vec<u8>, _>>()
+                    .unwrap_or_default();
+                crate::interface::delete_static_neighbours(&network.bridge, &mac).await?;
+                self.switch(&network).detach(ifname, &network.bridge).await?;
+            },
+            None => crate::interface::set_nomaster(ifname).await?,
+        }
+        crate::interface::set_link_down(ifname).await?;
+
+        let table = Self::interface_table(ifname);
+        self.nft().apply(Batch::new().add(table.clone()).delete(table)).await?;
+
+        registry.set_network_interface_attached(ifname, false).await?;
+        Ok(())
+    }
+
+    /// Sets the VLAN of an interface, `None` falls back to the VLAN of its network. An attached
+    /// interface is detached and re-attached so its previous membership is dropped.
+    pub async fn set_vlan(&self, ifname: &str, vlan: Option<u16>) -> Result<(), crate::Error> {
+        if let Some(vlan) = vlan {
+            validate_vlan(vlan)?;
+        }
+        let registry = self.context.registry();
+        registry.set_network_interface_vlan(ifname, vlan).await?;
+        let nic = registry.get_network_interface_by_ifname(ifname).await?;
+        if nic.attached
+            && let Some(network) = self.network_definition(nic.network.as_deref()).await?
+        {
+            let switch = self.switch(&network);
+            switch.detach(ifname, &network.bridge).await?;
+            switch.attach(ifname, &network.bridge, vlan.or(network.vlan)).await?;
+        }
+        Ok(())
+    }
+
+    /// Stores the device options of an interface, they take effect the next time the VM starts.
+    pub async fn set_options(&self, ifname: &str, options: &NicOptions) -> Result<(), crate::Error> {
+        validate_nic_options(options)?;
+        let registry = self.context.registry();
+        registry.get_network_interface_by_ifname(ifname).await?;
+        registry.set_network_interface_options(ifname, options).await
+    }
+
+    /// Sets the link of an interface of a running VM up or down as seen by the guest.
+    pub async fn set_link(&self, ifname: &str, up: bool) -> Result<(), crate::Error> {
+        let nic = self.context.registry().get_network_interface_by_ifname(ifname).await?;
+        let launch_request = VmLaunchRequestBuilder::new(self.context).build(&nic.vm_id).await?;
+        let runtime = self.context.runtime();
+        if !runtime.is_running(&launch_request).await? {
+            return Err(crate::Error::VMNotRunning(nic.vm_id));
+        }
+        runtime.set_link(&launch_request, &nic.id, up).await
+    }
+
+    fn switch(&self, network: &NetworkDefinition) -> Box<dyn Switch> {
+        crate::switch::switch(self.context.config(), network)
+    }
+
+    /// Resolves a network name against the managed networks first, then the configuration.
+    /// `None` stands for the default bridge.
+    pub async fn network_definition(&self, name: Option<&str>) -> Result<Option<NetworkDefinition>, crate::Error> {
+        if let Some(name) = name
+            && let Some(network) = self.context.registry().find_network(name).await?
+        {
+            return Ok(Some(NetworkDefinition {
+                bridge: network.bridge,
+                vlan: None,
+                mtu: network.mtu,
+                backend: SwitchBackend::Bridge,
+                uplink: None,
+                unfiltered: false,
+            }));
+        }
+        Ok(self.context.config().network.resolve(name))
+    }
+
+    fn dnsmasq(&self, network: &str) -> Dnsmasq {
+        Dnsmasq::new(&self.context.config().cli.dnsmasq, self.context.storage().network_path(network))
+    }
+
+    fn nat_table(network: &str) -> Table {
+        Table::new(Family::Ip, &format!("yave_{}", network))
+    }
+
+    /// Bridge family table holding the per-interface rules of a TAP.
+    fn interface_table(ifname: &str) -> Table {
+        Table::new(Family::Bridge, &format!("yave_if_{}", ifname))
+    }
+
+    pub(crate) fn nft(&self) -> Executor {
+        Executor::new(&self.context.config().cli.nft)
+    }
+
+    /// Rules confining the traffic a guest sends through its TAP to its assigned MAC and
+    /// registered IP addresses. Only ARP, IPv4 and IPv6 frames pass, so VLAN-tagged frames
+    /// and other ethertypes are dropped. DHCP and DHCPv6 server replies and router
+    /// advertisements from the guest are dropped; DHCP requests from `0.0.0.0` are let
+    /// through. IPv6 senders may also use the EUI-64 link-local address of the MAC, and `::`
+    /// for duplicate address detection. Frames that pass are not accepted here so the
+    /// security group rules appended to the chain still see them.
+    fn antispoof_batch(ifname: &str, mac: &str, addresses: &[String], ipv6_addresses: &[String]) -> Batch {
+        let table = Self::interface_table(ifname);
+        let prerouting = table.base_chain("prerouting", ChainType::Filter, Hook::Prerouting, -200)
+            .policy(Policy::Accept);
+        let guest = table.chain("guest");
+        let mut arp_addresses: Vec<Value> = addresses.iter().map(|address| Value::from(address.as_str())).collect();
+        arp_addresses.push(Value::from("0.0.0.0"));
+
+        let mut batch = Batch::new();
+        batch
+            .add(table.clone())
+            .flush(table.clone())
+            .add(prerouting.clone())
+            .add(guest.clone())
+            .add(prerouting.rule().matches(Field::meta("iifname"), ifname).jump(&guest.name))
+            .add(guest.rule().not_matches(Field::payload("ether", "saddr"), mac).drop())
+            .add(guest.rule().not_matches(Field::payload("ether", "type"), Value::set(["arp", "ip", "ip6"])).drop())
+            .add(guest.rule().matches(Field::payload("udp", "sport"), Value::set([67u16, 547])).drop())
+            .add(guest.rule().matches(Field::payload("icmpv6", "type"), "nd-router-advert").drop())
+            .add(guest.rule()
+                .matches(Field::payload("ip", "saddr"), "0.0.0.0")
+                .matches(Field::payload("udp", "dport"), 67u16)
+                .accept())
+            .add(guest.rule().not_matches(Field::payload("arp", "saddr ether"), mac).drop())
+            .add(guest.rule().not_matches(Field::payload("arp", "saddr ip"), Value::Set(arp_addresses)).drop());
+        match addresses.is_empty() {
+            true => batch.add(guest.rule().matches(Field::payload("ether", "type"), "ip").drop()),
+            false => batch.add(guest.rule()
+                .not_matches(Field::payload("ip", "saddr"), Value::set(addresses.iter().map(String::as_str)))
+                .drop()),
+        };
+        let mut ipv6_targets: Vec<Value> = ipv6_addresses.iter().map(|address| Value::from(address.as_str())).collect();
+        if let Some(link_local) = eui64(Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 0), mac) {
+            ipv6_targets.push(Value::from(IpAddr::V6(link_local)));
         }
+        let mut ipv6_senders = ipv6_targets.clone();
+        ipv6_senders.push(Value::from("::"));
+        batch
+            .add(guest.rule().not_matches(Field::payload("ip6", "saddr"), Value::Set(ipv6_senders)).drop())
+            .add(guest.rule()
+                .matches(Field::payload("icmpv6", "type"), "nd-neighbor-advert")
+                .not_matches(Field::payload("icmpv6", "taddr"), Value::Set(ipv6_targets))
+                .drop());
+        batch
+    }
+
+    /// Whether the anti-spoofing and security group rules apply to interfaces on `network`,
+    /// `false` for networks marked unfiltered. Networks on a switch nftables cannot filter
+    /// are refused unless marked unfiltered, so they are not mistaken for protected ones.
+    pub async fn filtered(&self, network: Option<&str>) -> Result<bool, crate::Error> {
+        let Some(definition) = self.network_definition(network).await? else {
+            return Ok(true);
+        };
+        if definition.unfiltered {
+            return Ok(false);
+        }
+        if !self.switch(&definition).filterable() {
+            return Err(crate::Error::FilteringUnsupported(format!(
+                "nftables cannot filter the ports of network {}, mark it unfiltered",
+                network.unwrap_or(&definition.bridge),
+            )));
+        }
+        Ok(true)
+    }
+
+    /// Installs the anti-spoofing and security group rules of a TAP, replacing the previous
+    /// ones atomically. Interfaces on unfiltered networks get none.
+    pub async fn apply_interface_rules(&self, ifname: &str) -> Result<(), crate::Error> {
+        let registry = self.context.registry();
+        let nic = registry.get_network_interface_by_ifname(ifname).await?;
+        if !self.filtered(nic.network.as_deref()).await? {
+            if !registry.get_security_groups_by_ifname(ifname).await?.is_empty() {
+                return Err(crate::Error::FilteringUnsupported(format!("{} has security groups but its network is unfiltered", ifname)));
+            }
+            let table = Self::interface_table(ifname);
+            self.nft().apply(Batch::new().add(table.clone()).delete(table)).await?;
+            return Ok(());
+        }
+        let (ipv4, ipv6): (Vec<IpAddressRecord>, Vec<IpAddressRecord>) = registry.get_ip_addresses_by_ifname(ifname).await?
+            .into_iter()
+            .partition(|address| address.family == IpFamily::Ipv4);
+        let ipv4: Vec<String> = ipv4.into_iter().map(|address| address.address).collect();
+        let ipv6: Vec<String> = ipv6.into_iter().map(|address| address.address).collect();
+        let mut batch = Self::antispoof_batch(ifname, &nic.mac_address, &ipv4, &ipv6);
+        let groups = registry.get_security_groups_by_ifname(ifname).await?;
+        let mut rules = vec![];
+        for group in &groups {
+            rules.append(&mut registry.get_security_group_rules(&group.name).await?);
+        }
+        if !groups.is_empty() {
+            let table = Self::interface_table(ifname);
+            batch.extend(crate::security_group::compile(&table, ifname, &table.chain("guest"), &rules));
+        }
+        self.nft().apply(&batch).await?;
         Ok(())
     }
+
+    /// Masquerades traffic leaving the subnet of a network, or removes the rules if NAT is off.
+    /// Adding the table first makes the flush and delete valid when it does not exist yet.
+    fn nat_batch(network: &NetworkRecord, subnet: std::net::Ipv4Addr) -> Batch {
+        let table = Self::nat_table(&network.name);
+        let mut batch = Batch::new();
+        batch.add(table.clone());
+        if !network.nat {
+            batch.delete(table);
+            return batch;
+        }
+        let cidr = Value::prefix(&subnet.to_string(), network.prefix_len as u8);
+        let postrouting = table.base_chain("postrouting", ChainType::Nat, Hook::Postrouting, 100);
+        batch
+            .flush(table.clone())
+            .add(postrouting.clone())
+            .add(postrouting.rule()
+                .matches(Field::payload("ip", "saddr"), cidr.clone())
+                .not_matches(Field::payload("ip", "daddr"), cidr)
+                .statement(Statement::Masquerade));
+        batch
+    }
+
+    /// Registers a managed network and brings it up. If it cannot be brought up, the
+    /// registration and whatever was set up are removed again.
+    pub async fn create_network(&self, network: CreateNetwork) -> Result<NetworkRecord, crate::Error> {
+        validate_network_name(&network.name)?;
+        if self.context.config().network.networks.contains_key(&network.name) {
+            return Err(crate::Error::NetworkExists(network.name));
+        }
+        let address: Result<std::net::Ipv4Addr, _> = network.address.parse();
+        if address.is_err() || network.prefix_len == 0 || network.prefix_len > 30 {
+            return Err(crate::Error::InvalidNetworkAddress(format!("{}/{}", network.address, network.prefix_len)));
+        }
+        if network.isolated && (network.nat || network.dhcp) {
+            return Err(crate::Error::InvalidNetwork(format!("isolated network {} cannot have NAT or DHCP", network.name)));
+        }
+        let registry = self.context.registry();
+        let bridge_existed = crate::interface::interface_exists(&network.bridge).await?;
+        let record = registry.create_network(network).await?;
+        if let Err(err) = self.apply_network(&record).await {
+            if let Err(err) = self.teardown_network(&record, !bridge_existed).await {
+                log::warn!("Failed to tear down network {}: {}", record.name, err);
+            }
+            let dir = self.context.storage().network_path(&record.name);
+            if dir.exists() {
+                std::fs::remove_dir_all(dir)?;
+            }
+            registry.delete_network(&record.name).await?;
+            return Err(err);
+        }
+        Ok(record)
+    }
+
+    /// Creates the bridge of a managed network with its gateway address, then sets up NAT
+    /// and the DHCP server as configured. The bridge of an isolated network gets no address.
+    /// Safe to run again on an already running network.
+    pub async fn apply_network(&self, network: &NetworkRecord) -> Result<(), crate::Error> {
+        let (subnet, _) = network.subnet()
+            .ok_or(crate::Error::InvalidNetworkAddress(network.address.clone()))?;
+        let address: IpAddr = network.address.parse()
+            .map_err(|_| crate::Error::InvalidNetworkAddress(network.address.clone()))?;
+        crate::interface::create_bridge(&network.bridge).await?;
+        if let Some(mtu) = network.mtu {
+            crate::interface::set_mtu(&network.bridge, mtu).await?;
+        }
+        if !network.isolated {
+            crate::interface::add_address(&network.bridge, address, network.prefix_len as u8).await?;
+        }
+        crate::interface::set_link_up(&network.bridge).await?;
+
+        if network.nat {
+            std::fs::write("/proc/sys/net/ipv4/ip_forward", "1")?;
+        }
+        self.nft().apply(&Self::nat_batch(network, subnet)).await?;
+
+        let dnsmasq = self.dnsmasq(&network.name);
+        if network.dhcp {
+            dnsmasq.write_config(network, &self.context.config().network.nameservers)?;
+            dnsmasq.write_hosts(&self.context.registry().get_dhcp_hosts(&network.name).await?)?;
+            if dnsmasq.is_running() {
+                dnsmasq.stop()?;
+            }
+            dnsmasq.start().await?;
+        } else {
+            dnsmasq.stop()?;
+        }
+        log::info!("Network {} is up on {}", network.name, network.bridge);
+        Ok(())
+    }
+
+    pub async fn start_network(&self, name: &str) -> Result<(), crate::Error> {
+        let network = self.context.registry().get_network(name).await?;
+        self.apply_network(&network).await
+    }
+
+    /// Stops the DHCP server, removes the NAT rules and deletes the bridge of a managed network.
+    pub async fn stop_network(&self, name: &str) -> Result<(), crate::Error> {
+        let network = self.context.registry().get_network(name).await?;
+        self.teardown_network(&network, true).await?;
+        log::info!("Network {} is down", network.name);
+        Ok(())
+    }
+
+    /// Tears down as much as possible before reporting the first failure.
+    async fn teardown_network(&self, network: &NetworkRecord, delete_bridge: bool) -> Result<(), crate::Error> {
+        let stopped = self.dnsmasq(&network.name).stop();
+        let table = Self::nat_table(&network.name);
+        let flushed = self.nft().apply(Batch::new().add(table.clone()).delete(table)).await;
+        if delete_bridge {
+            crate::interface::delete_link(&network.bridge).await?;
+        }
+        stopped?;
+        flushed?;
+        Ok(())
+    }
+
+    /// Changes the NAT, DHCP and MTU settings of a managed network and applies them.
+    pub async fn update_network(&self, name: &str, nat: Option<bool>, dhcp: Option<bool>, mtu: Option<u32>) -> Result<NetworkRecord, crate::Error> {
+        let registry = self.context.registry();
+        let mut network = registry.get_network(name).await?;
+        network.nat = nat.unwrap_or(network.nat);
+        network.dhcp = dhcp.unwrap_or(network.dhcp);
+        network.mtu = mtu.or(network.mtu);
+        if network.isolated && (network.nat || network.dhcp) {
+            return Err(crate::Error::InvalidNetwork(format!("isolated network {} cannot have NAT or DHCP", network.name)));
+        }
+        registry.update_network(&network).await?;
+        self.apply_network(&network).await?;
+        Ok(network)
+    }
+
+    /// Registers a network connecting its interfaces through QEMU sockets: a multicast group
+    /// shared by any number of interfaces, or a UDP link between two of them. Multicast
+    /// networks stay on loopback unless given the local address of another interface.
+    pub async fn create_socket_network(&self, network: CreateSocketNetwork) -> Result<SocketNetworkRecord, crate::Error> {
+        validate_network_name(&network.name)?;
+        if self.context.config().network.networks.contains_key(&network.name) {
+            return Err(crate::Error::NetworkExists(network.name));
+        }
+        let address: IpAddr = network.address.parse()
+            .map_err(|_| crate::Error::InvalidNetworkAddress(network.address.clone()))?;
+        let valid = match network.mode {
+            SocketMode::Mcast => matches!(address, IpAddr::V4(group) if group.is_multicast()) && network.port > 0,
+            SocketMode::Udp => !address.is_multicast() && !address.is_unspecified() && network.port > 0 && network.port < u16::MAX,
+        };
+        if !valid {
+            return Err(crate::Error::InvalidNetworkAddress(format!("{}:{}", network.address, network.port)));
+        }
+        if let Some(local_address) = &network.local_address {
+            let valid = network.mode == SocketMode::Mcast
+                && local_address.parse::<std::net::Ipv4Addr>().is_ok_and(|local| !local.is_multicast() && !local.is_unspecified());
+            if !valid {
+                return Err(crate::Error::InvalidNetworkAddress(local_address.clone()));
+            }
+        }
+        self.context.registry().create_socket_network(network).await
+    }
+
+    /// Whether `name` is a managed, socket or configured network.
+    pub async fn network_exists(&self, name: &str) -> Result<bool, crate::Error> {
+        Ok(self.network_definition(Some(name)).await?.is_some()
+            || self.context.registry().find_socket_network(name).await?.is_some())
+    }
+
+    /// Deletes a managed or socket network, refusing while interfaces are on it.
+    pub async fn delete_network(&self, name: &str) -> Result<(), crate::Error> {
+        let registry = self.context.registry();
+        if registry.find_socket_network(name).await?.is_some() {
+            if !registry.get_network_interfaces_by_network(name).await?.is_empty() {
+                return Err(crate::Error::NetworkInUse(name.to_string()));
+            }
+            return registry.delete_socket_network(name).await;
+        }
+        let network = registry.get_network(name).await?;
+        if !registry.get_network_interfaces_by_network(&network.name).await?.is_empty() {
+            return Err(crate::Error::NetworkInUse(network.name));
+        }
+        self.stop_network(&network.name).await?;
+        let dir = self.context.storage().network_path(&network.name);
+        if dir.exists() {
+            std::fs::remove_dir_all(dir)?;
+        }
+        registry.delete_network(&network.name).await
+    }
+
+    /// Checks that `address`/`prefix_len` is a host address, that the gateway is a neighbour
+    /// in the same family and, on managed networks, that the address lies in the network's
+    /// subnet.
+    async fn validate_address(&self, nic: &NetworkInterfaceRecord, address: &str, prefix_len: u32, gateway: Option<&str>) -> Result<IpAddr, crate::Error> {
+        let parsed: IpAddr = address.parse()
+            .map_err(|_| crate::Error::InvalidIpAddress(address.to_string()))?;
+        if parsed.is_unspecified() || parsed.is_loopback() || parsed.is_multicast() {
+            return Err(crate::Error::InvalidIpAddress(address.to_string()));
+        }
+        let max_len = if parsed.is_ipv4() { 32 } else { 128 };
+        if prefix_len == 0 || prefix_len > max_len {
+            return Err(crate::Error::InvalidIpAddress(format!("{}/{}", address, prefix_len)));
+        }
+        if let Some(gateway) = gateway {
+            let gateway: IpAddr = gateway.parse()
+                .map_err(|_| crate::Error::InvalidIpAddress(gateway.to_string()))?;
+            if gateway.is_ipv4() != parsed.is_ipv4() {
+                return Err(crate::Error::InvalidIpAddress(format!("gateway {} is not in the family of {}", gateway, address)));
+            }
+            let link_local = matches!(gateway, IpAddr::V6(gateway) if gateway.is_unicast_link_local());
+            if gateway == parsed || !(link_local || same_subnet(parsed, gateway, prefix_len)) {
+                return Err(crate::Error::InvalidIpAddress(format!("gateway {} is not a neighbour of {}/{}", gateway, address, prefix_len)));
+            }
+        }
+        if let IpAddr::V4(v4) = parsed
+            && prefix_len <= 30
+        {
+            let host_bits = u32::MAX >> prefix_len;
+            if v4.to_bits() & host_bits == 0 || v4.to_bits() & host_bits == host_bits {
+                return Err(crate::Error::InvalidIpAddress(format!("{}/{} is not a host address", address, prefix_len)));
+            }
+        }
+        if let (IpAddr::V4(_), Some(name)) = (parsed, &nic.network)
+            && let Some(network) = self.context.registry().find_network(name).await?
+            && let Ok(gateway) = network.address.parse::<IpAddr>()
+            && (prefix_len != network.prefix_len || gateway == parsed || !same_subnet(parsed, gateway, prefix_len))
+        {
+            return Err(crate::Error::InvalidIpAddress(format!("{}/{} is not a host of network {} ({}/{})", address, prefix_len, network.name, network.address, network.prefix_len)));
+        }
+        Ok(parsed)
+    }
+
+    /// Validates and registers an address of a VM interface, then refreshes the rules and
+    /// leases depending on it. For SLAAC, `address` is any address of the advertised /64
+    /// prefix; the interface address is derived from it and the MAC.
+    pub async fn add_address(&self, ifname: &str, address: &str, prefix_len: u32, gateway: Option<String>, mode: AddressMode) -> Result<IpAddressRecord, crate::Error> {
+        let registry = self.context.registry();
+        let nic = registry.get_network_interface_by_ifname(ifname).await?;
+        let parsed = self.validate_address(&nic, address, prefix_len, gateway.as_deref()).await?;
+        let address = match (mode, parsed) {
+            (AddressMode::Static, _) => parsed,
+            (AddressMode::Slaac, IpAddr::V6(prefix)) if prefix_len == 64 => eui64(prefix, &nic.mac_address)
+                .map(IpAddr::V6)
+                .ok_or(crate::Error::InvalidIpAddress(format!("no EUI-64 address for MAC {}", nic.mac_address)))?,
+            (AddressMode::Slaac, _) => return Err(crate::Error::InvalidIpAddress("SLAAC needs an IPv6 /64 prefix".to_string())),
+        };
+        if registry.get_ip_by_address(&address.to_string()).await?.is_some() {
+            return Err(crate::Error::InvalidIpAddress(format!("{} is already assigned", address)));
+        }
+        let record = registry.add_ip_address(AddIpAddress {
+            ifname: nic.ifname.clone(),
+            address: address.to_string(),
+            family: if address.is_ipv4() { IpFamily::Ipv4 } else { IpFamily::Ipv6 },
+            prefix_len,
+            gateway,
+            mode,
+        }).await?;
+        self.addresses_changed(&nic.ifname).await?;
+        Ok(record)
+    }
+
+    /// Removes an IPv4 address of an interface, promoting another one to default if needed.
+    pub async fn remove_address(&self, ifname: &str, address: &str) -> Result<(), crate::Error> {
+        self.context.registry().remove_ipv4_address(ifname, address).await?;
+        self.addresses_changed(ifname).await
+    }
+
+    pub async fn set_default_address(&self, ifname: &str, address: &str) -> Result<(), crate::Error> {
+        self.context.registry().set_default_address(ifname, address).await?;
+        self.addresses_changed(ifname).await
+    }
+
+    /// Changes the prefix length and gateway of an IPv4 address of an interface.
+    pub async fn update_address(&self, ifname: &str, address: &str, prefix_len: u32, gateway: Option<String>) -> Result<IpAddressRecord, crate::Error> {
+        let registry = self.context.registry();
+        let nic = registry.get_network_interface_by_ifname(ifname).await?;
+        let mut record = registry.get_ip_by_address(address).await?
+            .filter(|record| record.ifname == nic.ifname && record.family == IpFamily::Ipv4)
+            .ok_or(crate::Error::IpAddressNotFound(address.to_string()))?;
+        self.validate_address(&nic, address, prefix_len, gateway.as_deref()).await?;
+        record.prefix_len = prefix_len;
+        record.gateway = gateway;
+        registry.update_ipv4_address(&record).await?;
+        self.addresses_changed(&nic.ifname).await?;
+        Ok(record)
+    }
+
+    /// Refreshes the anti-spoofing rules of an attached interface, the port forwards
+    /// targeting it and the DHCP leases of its network after its addresses changed.
+    pub async fn addresses_changed(&self, ifname: &str) -> Result<(), crate::Error> {
+        let registry = self.context.registry();
+        let nic = registry.get_network_interface_by_ifname(ifname).await?;
+        if nic.attached {
+            self.apply_interface_rules(ifname).await?;
+        }
+        let forwards = registry.get_port_forwards_by_vm_id(&nic.vm_id).await?;
+        if forwards.iter().any(|forward| forward.ifname == nic.ifname) {
+            crate::port_forward::PortForwardManager::new(self.context).apply().await?;
+        }
+        let Some(name) = nic.network else {
+            return Ok(());
+        };
+        let Some(network) = registry.find_network(&name).await? else {
+            return Ok(());
+        };
+        if network.dhcp {
+            let dnsmasq = self.dnsmasq(&network.name);
+            dnsmasq.write_hosts(&registry.get_dhcp_hosts(&network.name).await?)?;
+            dnsmasq.reload()?;
+        }
+        Ok(())
+    }
+}
+
+/// Address of the /64 `prefix` with the modified EUI-64 interface identifier of `mac`.
+pub fn eui64(prefix: Ipv6Addr, mac: &str) -> Option<Ipv6Addr> {
+    let mac = mac.split(':')
+        .map(|octet| u8::from_str_radix(octet, 16))
+        .collect::<Result<Vec<u8>, _>>()
+        .ok()?;
+    let [a, b, c, d, e, f] = mac[..] else {
+        return None;
+    };
+    let interface_id = u64::from_be_bytes([a ^ 0x02, b, c, 0xff, 0xfe, d, e, f]);
+    let network = prefix.to_bits() & !(u64::MAX as u128);
+    Some(Ipv6Addr::from_bits(network | interface_id as u128))
+}
+
+/// VLAN ids 0 and 4095 are reserved by 802.1Q.
+pub fn validate_vlan(vlan: u16) -> Result<(), crate::Error> {
+    match (1..=4094).contains(&vlan) {
+        true => Ok(()),
+        false => Err(crate::Error::InvalidVlan(vlan)),
+    }
+}
+
+/// Network names end up in paths and nftables table names, so only a safe subset is allowed.
+pub fn validate_network_name(name: &str) -> Result<(), crate::Error> {
+    let valid = (1..=MAX_NETWORK_NAME_LEN).contains(&name.len())
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    match valid {
+        true => Ok(()),
+        false => Err(crate::Error::InvalidNetwork(format!("invalid name {:?}, use up to {} letters, digits, '_' or '-'", name, MAX_NETWORK_NAME_LEN))),
+    }
+}
+
+/// vhost-net and multiqueue are virtio features, the MTU must fit an Ethernet frame.
+pub fn validate_nic_options(options: &NicOptions) -> Result<(), crate::Error> {
+    if options.model != NicModel::Virtio && (options.vhost || options.multiqueue) {
+        return Err(crate::Error::InvalidNicOptions("vhost and multiqueue require the virtio model".to_string()));
+    }
+    if let Some(mtu) = options.mtu
+        && !(68..=65535).contains(&mtu)
+    {
+        return Err(crate::Error::InvalidNicOptions(format!("MTU out of range: {}", mtu)));
+    }
+    Ok(())
+}
+
+/// Whether both addresses are of the same family and share the first `prefix_len` bits.
+fn same_subnet(a: IpAddr, b: IpAddr, prefix_len: u32) -> bool {
+    match (a, b) {
+        (IpAddr::V4(a), IpAddr::V4(b)) => {
+            let mask = u32::MAX.checked_shl(32 - prefix_len).unwrap_or(0);
+            a.to_bits() & mask == b.to_bits() & mask
+        },
+        (IpAddr::V6(a), IpAddr::V6(b)) => {
+            let mask = u128::MAX.checked_shl(128 - prefix_len).unwrap_or(0);
+            a.to_bits() & mask == b.to_bits() & mask
+        },
+        _ => false,
+    }
 }
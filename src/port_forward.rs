@@ -0,0 +1,126 @@
+use std::net::{IpAddr, Ipv4Addr};
+
+use nft::{batch::Batch, builders::Family, types::{ChainType, Field, Hook, Op, Policy, Statement, Table}};
+
+use crate::{context::YaveContext, net::NetworkManager, registry::{AddPortForward, ForwardProtocol, PortForwardRecord}};
+
+pub struct PortForwardManager<'ctx> {
+    context: &'ctx YaveContext,
+}
+
+impl<'ctx> PortForwardManager<'ctx> {
+    pub fn new(context: &'ctx YaveContext) -> Self {
+        Self { context }
+    }
+
+    fn table() -> Table {
+        Table::new(Family::Ip, "yave_port_forwards")
+    }
+
+    pub async fn get_forwards(&self, vm_id: &str) -> Result<Vec<PortForwardRecord>, crate::Error> {
+        self.context.registry().get_port_forwards_by_vm_id(vm_id).await
+    }
+
+    /// Exposes a port of a VM interface on the host. The interface needs a default IPv4
+    /// address, which becomes the DNAT target.
+    pub async fn add_forward(&self, vm_id: &str, forward: AddPortForward) -> Result<PortForwardRecord, crate::Error> {
+        let registry = self.context.registry();
+        let nic = registry.get_network_interface_by_ifname(&forward.ifname).await?;
+        if nic.vm_id != vm_id {
+            return Err(crate::Error::InvalidPortForward(format!("interface {} does not belong to VM {}", nic.ifname, vm_id)));
+        }
+        if let Some(host_ip) = &forward.host_ip
+            && host_ip.parse::<Ipv4Addr>().is_err()
+        {
+            return Err(crate::Error::InvalidPortForward(format!("invalid host address {}", host_ip)));
+        }
+        if forward.host_port == 0 || forward.guest_port == 0 {
+            return Err(crate::Error::InvalidPortForward("ports must be between 1 and 65535".to_string()));
+        }
+        if self.guest_address(&nic.ifname).await?.is_none() {
+            return Err(crate::Error::InvalidPortForward(format!("interface {} has no default IPv4 address", nic.ifname)));
+        }
+        let record = registry.add_port_forward(vm_id, forward).await?;
+        self.apply().await?;
+        Ok(record)
+    }
+
+    pub async fn delete_forward(&self, vm_id: &str, forward_id: i64) -> Result<(), crate::Error> {
+        self.context.registry().delete_port_forward(vm_id, forward_id).await?;
+        self.apply().await
+    }
+
+    async fn guest_address(&self, ifname: &str) -> Result<Option<Ipv4Addr>, crate::Error> {
+        let addresses = self.context.registry().get_ipv4_by_ifname(ifname).await?;
+        Ok(addresses.iter()
+            .find(|address| address.is_default)
+            .or(addresses.first())
+            .and_then(|address| address.address.parse().ok()))
+    }
+
+    /// Rebuilds the port forwarding table from the registry in one transaction. Forwards
+    /// whose interface lost its address are skipped.
+    pub async fn apply(&self) -> Result<(), crate::Error> {
+        let mut forwards = vec![];
+        for forward in self.context.registry().get_port_forwards().await? {
+            match self.guest_address(&forward.ifname).await? {
+                Some(address) => forwards.push((forward, address)),
+                None => log::warn!("Skipping port forward {}: {} has no IPv4 address", forward.id, forward.ifname),
+            }
+        }
+        if !forwards.is_empty() {
+            std::fs::write("/proc/sys/net/ipv4/ip_forward", "1")?;
+        }
+        NetworkManager::new(self.context).nft().apply(&compile(&forwards)).await?;
+        Ok(())
+    }
+}
+
+/// DNATs connections to a host port onto the guest address and accepts them in the forward
+/// hook. Without a host address, only destinations local to the host are translated so
+/// traffic routed through the host keeps its destination.
+fn compile(forwards: &[(PortForwardRecord, Ipv4Addr)]) -> Batch {
+    let table = PortForwardManager::table();
+    let mut batch = Batch::new();
+    batch.add(table.clone());
+    if forwards.is_empty() {
+        batch.delete(table);
+        return batch;
+    }
+    let prerouting = table.base_chain("prerouting", ChainType::Nat, Hook::Prerouting, -100);
+    let output = table.base_chain("output", ChainType::Nat, Hook::Output, -100);
+    let forward_chain = table.base_chain("forward", ChainType::Filter, Hook::Forward, 0)
+        .policy(Policy::Accept);
+    batch
+        .flush(table.clone())
+        .add(prerouting.clone())
+        .add(output.clone())
+        .add(forward_chain.clone());
+
+    for (forward, guest) in forwards {
+        let protocol = match forward.protocol {
+            ForwardProtocol::Tcp => "tcp",
+            ForwardProtocol::Udp => "udp",
+        };
+        let comment = format!("{}:{}", forward.vm_id, forward.id);
+        for chain in [&prerouting, &output] {
+            let rule = chain.rule().comment(&comment);
+            let rule = match &forward.host_ip {
+                Some(host_ip) => rule.matches(Field::payload("ip", "daddr"), host_ip.as_str()),
+                None => rule.matches(Field::fib(&["daddr"], "type"), "local"),
+            };
+            batch.add(rule
+                .matches(Field::meta("l4proto"), protocol)
+                .matches(Field::payload(protocol, "dport"), forward.host_port)
+                .statement(Statement::Dnat { addr: IpAddr::V4(*guest), port: Some(forward.guest_port) }));
+        }
+        batch.add(forward_chain.rule()
+            .comment(&comment)
+            .matches(Field::payload("ip", "daddr"), IpAddr::V4(*guest))
+            .matches(Field::meta("l4proto"), protocol)
+            .matches(Field::payload(protocol, "dport"), forward.guest_port)
+            .matches_op(Field::ct("status"), Op::In, "dnat")
+            .accept());
+    }
+    batch
+}
@@ -1,10 +1,11 @@
-use std::collections::HashSet;
+use std::{collections::HashSet, net::Ipv4Addr};
 
 use serde::{Deserialize, Serialize};
-use vm_types::vm::DriveBus;
+use vm_types::vm::{DiskMediaKind, DriveBus, DriveFormat, DriveOptions, NicOptions};
 
 pub struct VmRegistry {
     pool: sqlx::Pool<sqlx::Sqlite>,
+    macs: MacGenerator,
 }
 
 #[derive(Debug, sqlx::FromRow, Serialize, Deserialize)]
@@ -17,31 +18,216 @@ pub struct VirtualMachineRecord {
     pub vnc_display: String,
 }
 
-#[derive(Debug, sqlx::FromRow)]
+#[derive(Debug, sqlx::FromRow, Serialize, Deserialize)]
 pub struct DriveRecord {
     pub vm_id: String,
     pub id: String,
     #[sqlx(json)]
     pub drive_bus: DriveBus,
+    pub size: u64,
+    #[sqlx(json)]
+    pub options: DriveOptions,
+    pub encrypted: bool,
+    pub media: Option<String>,
 }
 
-#[derive(Debug, sqlx::FromRow)]
+#[derive(Debug, sqlx::FromRow, Serialize, Deserialize)]
 pub struct NetworkInterfaceRecord {
     pub ifname: String,
     pub vm_id: String,
     pub id: String,
     pub mac_address: String,
+    pub network: Option<String>,
+    /// Set while the TAP is up and attached to its bridge.
+    #[serde(default)]
+    pub attached: bool,
+    /// VLAN id of the interface, overriding the one of its network.
+    #[serde(default)]
+    pub vlan: Option<u16>,
+    #[sqlx(json)]
+    #[serde(default)]
+    pub options: NicOptions,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, sqlx::Type, Serialize, Deserialize)]
+#[sqlx(rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum IpFamily {
+    #[default]
+    Ipv4,
+    Ipv6,
+}
+
+/// How the guest obtains an address. SLAAC addresses are derived from the router advertised
+/// prefix and the interface MAC (EUI-64), the registry holds the resulting address.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, sqlx::Type, Serialize, Deserialize)]
+#[sqlx(rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum AddressMode {
+    #[default]
+    Static,
+    Slaac,
 }
 
-#[derive(Debug, sqlx::FromRow)]
-pub struct IPv4AddressRecord {
+#[derive(Debug, Clone, sqlx::FromRow, Serialize, Deserialize)]
+pub struct IpAddressRecord {
     pub address: String,
     pub ifname: String,
-    pub netmask: u32,
+    #[serde(default)]
+    pub family: IpFamily,
+    #[serde(alias = "netmask")]
+    pub prefix_len: u32,
     pub gateway: Option<String>,
+    #[serde(default)]
+    pub mode: AddressMode,
+    /// Set on the first address of each family of an interface, its gateway is the default route.
     pub is_default: bool,
 }
 
+/// Inclusive range of IPv4 addresses.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AddressRange {
+    pub start: String,
+    pub end: String,
+}
+
+#[derive(Debug, Clone, sqlx::FromRow, Serialize, Deserialize)]
+pub struct IpPoolRecord {
+    pub name: String,
+    /// IPv4 subnet the addresses are allocated from, e.g. `10.0.0.0/24`.
+    pub cidr: String,
+    pub gateway: Option<String>,
+    /// Ranges never handed out, e.g. for hosts outside yave.
+    #[sqlx(json)]
+    pub reserved: Vec<AddressRange>,
+    /// Resolvers configured in guests instead of the global nameservers.
+    #[sqlx(json)]
+    pub nameservers: Vec<String>,
+    /// Network whose interfaces draw addresses from the pool, the default bridge if unset.
+    pub network: Option<String>,
+}
+
+#[derive(Debug, Clone, sqlx::FromRow, Serialize, Deserialize)]
+pub struct NetworkRecord {
+    pub name: String,
+    pub bridge: String,
+    /// Host address on the bridge, handed to guests as their gateway.
+    pub address: String,
+    pub prefix_len: u32,
+    pub nat: bool,
+    pub dhcp: bool,
+    pub mtu: Option<u32>,
+    /// The host takes no address on the bridge, guests only reach each other.
+    #[serde(default)]
+    pub isolated: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type, Serialize, Deserialize)]
+#[sqlx(rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum SocketMode {
+    /// All interfaces join the multicast group.
+    Mcast,
+    /// Point-to-point link between two interfaces.
+    Udp,
+}
+
+/// Network without a host bridge, connecting its interfaces through QEMU sockets.
+#[derive(Debug, Clone, sqlx::FromRow, Serialize, Deserialize)]
+pub struct SocketNetworkRecord {
+    pub name: String,
+    pub mode: SocketMode,
+    /// Multicast group, or the local address both ends of a UDP link bind to.
+    pub address: String,
+    /// Port of the group, or the port of the first end of a UDP link, the second uses the next one.
+    pub port: u16,
+    /// Host address multicast datagrams are sent from, [`MCAST_LOCAL_ADDRESS`] if unset.
+    pub local_address: Option<String>,
+}
+
+/// Local address of multicast networks without one, keeping their traffic on this host.
+pub const MCAST_LOCAL_ADDRESS: &str = "127.0.0.1";
+
+#[derive(Debug, sqlx::FromRow, Serialize, Deserialize)]
+pub struct DhcpHostRecord {
+    pub mac_address: String,
+    pub address: String,
+    pub hostname: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type, Serialize, Deserialize)]
+#[sqlx(rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum RuleDirection {
+    /// Traffic towards the VM.
+    Ingress,
+    /// Traffic sent by the VM.
+    Egress,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type, Serialize, Deserialize)]
+#[sqlx(rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum RuleProtocol {
+    Any,
+    Tcp,
+    Udp,
+    Icmp,
+}
+
+#[derive(Debug, Clone, sqlx::FromRow, Serialize, Deserialize)]
+pub struct SecurityGroupRecord {
+    pub name: String,
+    pub description: Option<String>,
+}
+
+#[derive(Debug, Clone, sqlx::FromRow, Serialize, Deserialize)]
+pub struct SecurityGroupRuleRecord {
+    pub id: i64,
+    pub group_name: String,
+    pub direction: RuleDirection,
+    pub protocol: RuleProtocol,
+    pub port_from: Option<u16>,
+    pub port_to: Option<u16>,
+    /// Remote address or subnet, any if unset.
+    pub cidr: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, sqlx::Type, Serialize, Deserialize)]
+#[sqlx(rename_all = "lowercase")]
+#[serde(rename_all = "lowercase")]
+pub enum ForwardProtocol {
+    Tcp,
+    Udp,
+}
+
+#[derive(Debug, Clone, sqlx::FromRow, Serialize, Deserialize)]
+pub struct PortForwardRecord {
+    pub id: i64,
+    pub vm_id: String,
+    pub ifname: String,
+    /// Host address the port is exposed on, all local addresses if unset.
+    pub host_ip: Option<String>,
+    pub host_port: u16,
+    pub protocol: ForwardProtocol,
+    pub guest_port: u16,
+}
+
+#[derive(Debug, sqlx::FromRow, Serialize, Deserialize)]
+pub struct BackupRecord {
+    pub id: i64,
+    pub vm_id: String,
+    pub created_at: i64,
+}
+
+#[derive(Debug, sqlx::FromRow, Serialize, Deserialize)]
+pub struct BackupImageRecord {
+    pub backup_id: i64,
+    pub drive_id: String,
+    pub incremental: bool,
+    pub path: String,
+}
+
 #[derive(Debug, Clone)]
 pub struct CreateVirtualMachine {
     pub id: String,
@@ -53,42 +239,355 @@ pub struct CreateVirtualMachine {
     pub drives: Vec<CreateDrive>,
 }
 
+#[derive(Debug, Clone)]
+pub struct CloneVirtualMachine {
+    pub id: String,
+    pub hostname: Option<String>,
+    pub linked: bool,
+}
+
 #[derive(Debug, Clone)]
 pub struct CreateNetworkInterface {
     pub id: String,
+    /// Named network from the configuration, `None` for the default bridge.
+    pub network: Option<String>,
+    pub vlan: Option<u16>,
+    pub options: NicOptions,
+    /// MAC address chosen by the user, generated if unset.
+    pub mac_address: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct CreateNetwork {
+    pub name: String,
+    pub bridge: String,
+    pub address: String,
+    pub prefix_len: u32,
+    pub nat: bool,
+    pub dhcp: bool,
+    pub mtu: Option<u32>,
+    pub isolated: bool,
+}
+
+#[derive(Debug, Clone)]
+pub struct CreateSocketNetwork {
+    pub name: String,
+    pub mode: SocketMode,
+    pub address: String,
+    pub port: u16,
+    pub local_address: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct CreateIpPool {
+    pub name: String,
+    pub cidr: String,
+    pub gateway: Option<String>,
+    pub reserved: Vec<AddressRange>,
+    pub nameservers: Vec<String>,
+    pub network: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct AddSecurityGroupRule {
+    pub direction: RuleDirection,
+    pub protocol: RuleProtocol,
+    pub port_from: Option<u16>,
+    pub port_to: Option<u16>,
+    pub cidr: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct AddPortForward {
+    pub ifname: String,
+    pub host_ip: Option<String>,
+    pub host_port: u16,
+    pub protocol: ForwardProtocol,
+    pub guest_port: u16,
 }
 
 #[derive(Debug, Clone)]
-pub struct AddIPv4Address {
+pub struct AddIpAddress {
     pub ifname: String,
     pub address: String,
-    pub netmask: u32,
+    pub family: IpFamily,
+    pub prefix_len: u32,
     pub gateway: Option<String>,
+    pub mode: AddressMode,
 }
 
 #[derive(Debug, Clone, sqlx::FromRow)]
 pub struct CreateDrive {
     pub id: String,
     pub drive_bus: DriveBus,
+    pub size: u64,
+    pub options: DriveOptions,
+    pub encrypted: bool,
+    pub media: Option<String>,
+}
+
+impl CreateDrive {
+    /// CD-ROM drive holding `iso` from the image library. With `boot` it is booted ahead of the
+    /// disks, otherwise it is not a boot device at all.
+    pub fn cdrom(id: &str, iso: &str, boot: bool) -> Self {
+        Self {
+            id: id.to_string(),
+            drive_bus: DriveBus::Ide {
+                media_type: DiskMediaKind::Cdrom,
+                boot_index: boot.then_some(0),
+            },
+            size: 0,
+            options: DriveOptions {
+                format: Some(DriveFormat::Raw),
+                ..Default::default()
+            },
+            encrypted: false,
+            media: Some(iso.to_string()),
+        }
+    }
+}
+
+impl NetworkRecord {
+    /// Network address and netmask of the subnet, `None` if the record holds no valid IPv4 address.
+    pub fn subnet(&self) -> Option<(Ipv4Addr, Ipv4Addr)> {
+        let address: Ipv4Addr = self.address.parse().ok()?;
+        if self.prefix_len > 32 {
+            return None;
+        }
+        let mask = u32::MAX.checked_shl(32 - self.prefix_len).unwrap_or(0);
+        Some((Ipv4Addr::from(address.to_bits() & mask), Ipv4Addr::from(mask)))
+    }
+}
+
+impl DriveRecord {
+    pub fn is_cdrom(&self) -> bool {
+        matches!(self.drive_bus, DriveBus::Ide { media_type: DiskMediaKind::Cdrom, .. })
+    }
+}
+
+/// Generates the MAC addresses of new interfaces from the md5 of a per-host seed and the
+/// interface name, following the OUI. Without an OUI the QEMU prefix `52:54` is used.
+#[derive(Debug, Clone, Default)]
+pub struct MacGenerator {
+    oui: Option<String>,
+    seed: Option<String>,
+}
+
+impl MacGenerator {
+    pub fn new(oui: Option<String>, seed: Option<String>) -> Self {
+        Self { oui, seed }
+    }
+
+    /// Candidate `attempt` for the interface `name`, later attempts are tried on collisions.
+    fn generate(&self, name: &str, attempt: u32) -> Result<String, crate::Error> {
+        use md5::{Digest, Md5};
+
+        let prefix = match &self.oui {
+            Some(oui) => parse_mac_octets(oui)
+                .filter(|octets| octets.len() == 3 && octets[0] & 0x01 == 0)
+                .ok_or_else(|| crate::Error::InvalidMacAddress(oui.clone()))?,
+            None => vec![0x52, 0x54],
+        };
+        let mut hasher = Md5::new();
+        if let Some(seed) = &self.seed {
+            hasher.update(seed.as_bytes());
+        }
+        hasher.update(name.as_bytes());
+        if attempt > 0 {
+            hasher.update(attempt.to_be_bytes());
+        }
+        let hash = hasher.finalize();
+        let octets: Vec<String> = prefix.iter()
+            .chain(hash.iter().take(6 - prefix.len()))
+            .map(|octet| format!("{:02x}", octet))
+            .collect();
+        Ok(octets.join(":"))
+    }
+}
+
+fn parse_mac_octets(mac: &str) -> Option<Vec<u8>> {
+    mac.split(':')
+        .map(|octet| match octet.len() {
+            2 => u8::from_str_radix(octet, 16).ok(),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Lowercase form of a user supplied MAC address, which must be a non-zero unicast address.
+pub fn normalize_mac(mac: &str) -> Result<String, crate::Error> {
+    match parse_mac_octets(mac) {
+        Some(octets) if octets.len() == 6 && octets[0] & 0x01 == 0 && octets.iter().any(|octet| *octet != 0) =>
+            Ok(mac.to_lowercase()),
+        _ => Err(crate::Error::InvalidMacAddress(mac.to_string())),
+    }
+}
+
+type VmInfo = (VirtualMachineRecord, Vec<DriveRecord>, Vec<NetworkInterfaceRecord>, Vec<IpAddressRecord>);
+
+impl AddressRange {
+    pub fn contains(&self, address: Ipv4Addr) -> bool {
+        match (self.start.parse::<Ipv4Addr>(), self.end.parse::<Ipv4Addr>()) {
+            (Ok(start), Ok(end)) => start <= address && address <= end,
+            _ => false,
+        }
+    }
 }
 
-pub fn get_mac(name: &str) -> String {
-    use md5::{Digest, Md5};
+impl IpPoolRecord {
+    /// Network address and prefix length of the pool, `None` if `cidr` is not an IPv4 subnet.
+    pub fn subnet(&self) -> Option<(Ipv4Addr, u32)> {
+        let (address, prefix_len) = self.cidr.split_once('/')?;
+        let address: Ipv4Addr = address.parse().ok()?;
+        let prefix_len: u32 = prefix_len.parse().ok()?;
+        if prefix_len > 32 {
+            return None;
+        }
+        let mask = u32::MAX.checked_shl(32 - prefix_len).unwrap_or(0);
+        Some((Ipv4Addr::from(address.to_bits() & mask), prefix_len))
+    }
+
+    pub fn contains(&self, address: Ipv4Addr) -> bool {
+        self.subnet().is_some_and(|(network, prefix_len)| {
+            let mask = u32::MAX.checked_shl(32 - prefix_len).unwrap_or(0);
+            address.to_bits() & mask == network.to_bits()
+        })
+    }
+
+    /// First and last host address of the subnet, `None` for subnets without hosts.
+    fn host_range(&self) -> Option<(u32, u32)> {
+        match self.subnet() {
+            Some((network, prefix_len)) if prefix_len <= 30 => {
+                let size = 1u32 << (32 - prefix_len);
+                Some((network.to_bits() + 1, network.to_bits() + size - 2))
+            },
+            _ => None,
+        }
+    }
+
+    /// Whether the pool hands out `address`: a host of the subnet that is neither the
+    /// gateway nor reserved.
+    pub fn is_host(&self, address: Ipv4Addr) -> bool {
+        self.host_range().is_some_and(|(first, last)| (first..=last).contains(&address.to_bits()))
+            && self.gateway.as_deref().and_then(|gateway| gateway.parse().ok()) != Some(address)
+            && !self.reserved.iter().any(|range| range.contains(address))
+    }
+
+    /// Addresses the pool hands out: the hosts of the subnet except the gateway and the
+    /// reserved ranges.
+    pub fn hosts(&self) -> impl Iterator<Item = Ipv4Addr> + '_ {
+        let (first, last) = self.host_range().unwrap_or((1, 0));
+        (first..=last)
+            .map(Ipv4Addr::from)
+            .filter(|address| self.is_host(*address))
+    }
+
+    /// Number of addresses `hosts` yields, computed from the ranges so large subnets are not
+    /// enumerated.
+    pub fn host_count(&self) -> usize {
+        let Some((first, last)) = self.host_range() else {
+            return 0;
+        };
+        let mut reserved: Vec<(u32, u32)> = self.reserved.iter()
+            .filter_map(|range| Some((range.start.parse::<Ipv4Addr>().ok()?.to_bits(), range.end.parse::<Ipv4Addr>().ok()?.to_bits())))
+            .map(|(start, end)| (start.max(first), end.min(last)))
+            .filter(|(start, end)| start <= end)
+            .collect();
+        reserved.sort_unstable();
+        let mut excluded = 0u64;
+        let mut covered_to: Option<u32> = None;
+        for (start, end) in reserved {
+            let start = match covered_to {
+                Some(covered) if covered >= end => continue,
+                Some(covered) if covered >= start => covered + 1,
+                _ => start,
+            };
+            excluded += (end - start) as u64 + 1;
+            covered_to = Some(end);
+        }
+        let gateway: Option<Ipv4Addr> = self.gateway.as_deref().and_then(|gateway| gateway.parse().ok());
+        if let Some(gateway) = gateway
+            && (first..=last).contains(&gateway.to_bits())
+            && !self.reserved.iter().any(|range| range.contains(gateway))
+        {
+            excluded += 1;
+        }
+        ((last - first) as u64 + 1 - excluded) as usize
+    }
+}
 
-    let mut hasher = Md5::new();
-    hasher.update(name.as_bytes());
-    let hash = hasher.finalize();
-    format!(
-        "52:54:{:02x}:{:02x}:{:02x}:{:02x}",
-        hash[0], hash[1], hash[2], hash[3]
-    )
+/// Schema change bringing a database created by an earlier version up to date.
+enum Migration {
+    /// Adds a column to a table created before the column existed.
+    AddColumn { table: &'static str, column: &'static str, definition: &'static str },
+    /// Copies the rows of a table that was replaced by another one with `insert`, an
+    /// `INSERT ... SELECT` from it, and drops it.
+    MoveRows { from: &'static str, insert: &'static str },
+    /// Runs a statement that is a no-op when already applied, e.g. `CREATE INDEX IF NOT EXISTS`.
+    Statement(&'static str),
 }
 
-type VmInfo = (VirtualMachineRecord, Vec<DriveRecord>, Vec<NetworkInterfaceRecord>, Vec<IPv4AddressRecord>);
+/// Applied in order, a database at `PRAGMA user_version` N has the first N applied. Steps are
+/// no-ops on tables already created with the current schema.
+const MIGRATIONS: &[Migration] = &[
+    Migration::AddColumn { table: "drives", column: "size", definition: "INTEGER NOT NULL DEFAULT 0" },
+    Migration::AddColumn { table: "drives", column: "options", definition: "TEXT NOT NULL DEFAULT '{}'" },
+    Migration::AddColumn { table: "drives", column: "encrypted", definition: "BOOLEAN NOT NULL DEFAULT FALSE" },
+    Migration::AddColumn { table: "drives", column: "media", definition: "TEXT" },
+    Migration::AddColumn { table: "network_interfaces", column: "network", definition: "TEXT" },
+    Migration::AddColumn { table: "network_interfaces", column: "attached", definition: "BOOLEAN NOT NULL DEFAULT FALSE" },
+    Migration::AddColumn { table: "network_interfaces", column: "vlan", definition: "INTEGER" },
+    Migration::AddColumn { table: "network_interfaces", column: "options", definition: "TEXT NOT NULL DEFAULT '{}'" },
+    Migration::AddColumn { table: "networks", column: "isolated", definition: "BOOLEAN NOT NULL DEFAULT FALSE" },
+    Migration::MoveRows {
+        from: "ipv4_addresses",
+        insert: r#"
+            INSERT OR IGNORE INTO ip_addresses (address, ifname, family, prefix_len, gateway, mode, is_default)
+            SELECT address, ifname, 'ipv4', netmask, gateway, 'static', is_default FROM ipv4_addresses;
+        "#,
+    },
+    Migration::Statement("CREATE UNIQUE INDEX IF NOT EXISTS network_interfaces_mac_address ON network_interfaces (mac_address);"),
+    Migration::AddColumn { table: "socket_networks", column: "local_address", definition: "TEXT" },
+];
+
+impl Migration {
+    async fn apply(&self, conn: &mut sqlx::SqliteConnection) -> Result<(), crate::Error> {
+        match self {
+            Migration::AddColumn { table, column, definition } => {
+                let exists = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM pragma_table_info(?) WHERE name = ?;")
+                    .bind(table)
+                    .bind(column)
+                    .fetch_one(&mut *conn)
+                    .await?;
+                if exists == 0 {
+                    sqlx::query(&format!("ALTER TABLE {} ADD COLUMN {} {};", table, column, definition))
+                        .execute(&mut *conn)
+                        .await?;
+                }
+            },
+            Migration::MoveRows { from, insert } => {
+                let exists = sqlx::query_scalar::<_, i64>("SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = ?;")
+                    .bind(from)
+                    .fetch_one(&mut *conn)
+                    .await?;
+                if exists != 0 {
+                    sqlx::query(insert).execute(&mut *conn).await?;
+                    sqlx::query(&format!("DROP TABLE {};", from))
+                        .execute(&mut *conn)
+                        .await?;
+                }
+            },
+            Migration::Statement(sql) => {
+                sqlx::query(sql).execute(&mut *conn).await?;
+            },
+        }
+        Ok(())
+    }
+}
 
 impl VmRegistry {
-    pub fn new(pool: sqlx::Pool<sqlx::Sqlite>) -> Self {
-        Self { pool }
+    pub fn new(pool: sqlx::Pool<sqlx::Sqlite>, macs: MacGenerator) -> Self {
+        Self { pool, macs }
     }
 
     pub async fn create_tables(&self) -> Result<(), crate::Error> {
@@ -106,44 +605,144 @@ impl VmRegistry {
                 ifname TEXT PRIMARY KEY,
                 vm_id TEXT NOT NULL,
                 id TEXT NOT NULL,
-                mac_address TEXT NOT NULL,
+                mac_address TEXT NOT NULL UNIQUE,
+                network TEXT,
+                attached BOOLEAN NOT NULL DEFAULT FALSE,
+                vlan INTEGER,
+                options TEXT NOT NULL DEFAULT '{}',
                 FOREIGN KEY(vm_id) REFERENCES virtual_machines(id) ON DELETE CASCADE
             );
             CREATE TABLE IF NOT EXISTS drives (
                 vm_id TEXT NOT NULL,
                 id TEXT NOT NULL,
                 drive_bus TEXT NOT NULL,
+                size INTEGER NOT NULL DEFAULT 0,
+                options TEXT NOT NULL DEFAULT '{}',
+                encrypted BOOLEAN NOT NULL DEFAULT FALSE,
+                media TEXT,
                 FOREIGN KEY(vm_id) REFERENCES virtual_machines(id) ON DELETE CASCADE
             );
-            CREATE TABLE IF NOT EXISTS ipv4_addresses (
+            CREATE TABLE IF NOT EXISTS ip_addresses (
                 address TEXT PRIMARY KEY,
                 ifname TEXT NOT NULL,
-                netmask INTEGER NOT NULL,
+                family TEXT NOT NULL DEFAULT 'ipv4',
+                prefix_len INTEGER NOT NULL,
                 gateway TEXT,
+                mode TEXT NOT NULL DEFAULT 'static',
                 is_default BOOLEAN DEFAULT FALSE,
                 FOREIGN KEY(ifname) REFERENCES network_interfaces(ifname) ON DELETE CASCADE
             );
+            CREATE TABLE IF NOT EXISTS ip_pools (
+                name TEXT PRIMARY KEY,
+                cidr TEXT NOT NULL,
+                gateway TEXT,
+                reserved TEXT NOT NULL DEFAULT '[]',
+                nameservers TEXT NOT NULL DEFAULT '[]',
+                network TEXT UNIQUE
+            );
+            CREATE TABLE IF NOT EXISTS backups (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                vm_id TEXT NOT NULL,
+                created_at INTEGER NOT NULL
+            );
+            CREATE TABLE IF NOT EXISTS backup_images (
+                backup_id INTEGER NOT NULL,
+                drive_id TEXT NOT NULL,
+                incremental BOOLEAN NOT NULL,
+                path TEXT NOT NULL,
+                PRIMARY KEY(backup_id, drive_id),
+                FOREIGN KEY(backup_id) REFERENCES backups(id) ON DELETE CASCADE
+            );
+            CREATE TABLE IF NOT EXISTS networks (
+                name TEXT PRIMARY KEY,
+                bridge TEXT NOT NULL UNIQUE,
+                address TEXT NOT NULL,
+                prefix_len INTEGER NOT NULL,
+                nat BOOLEAN NOT NULL DEFAULT FALSE,
+                dhcp BOOLEAN NOT NULL DEFAULT FALSE,
+                mtu INTEGER,
+                isolated BOOLEAN NOT NULL DEFAULT FALSE
+            );
+            CREATE TABLE IF NOT EXISTS socket_networks (
+                name TEXT PRIMARY KEY,
+                mode TEXT NOT NULL,
+                address TEXT NOT NULL,
+                port INTEGER NOT NULL,
+                local_address TEXT,
+                UNIQUE(address, port)
+            );
+            CREATE TABLE IF NOT EXISTS port_forwards (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                vm_id TEXT NOT NULL,
+                ifname TEXT NOT NULL,
+                host_ip TEXT,
+                host_port INTEGER NOT NULL,
+                protocol TEXT NOT NULL,
+                guest_port INTEGER NOT NULL,
+                FOREIGN KEY(vm_id) REFERENCES virtual_machines(id) ON DELETE CASCADE,
+                FOREIGN KEY(ifname) REFERENCES network_interfaces(ifname) ON DELETE CASCADE
+            );
+            CREATE TABLE IF NOT EXISTS security_groups (
+                name TEXT PRIMARY KEY,
+                description TEXT
+            );
+            CREATE TABLE IF NOT EXISTS security_group_rules (
+                id INTEGER PRIMARY KEY AUTOINCREMENT,
+                group_name TEXT NOT NULL,
+                direction TEXT NOT NULL,
+                protocol TEXT NOT NULL,
+                port_from INTEGER,
+                port_to INTEGER,
+                cidr TEXT,
+                FOREIGN KEY(group_name) REFERENCES security_groups(name) ON DELETE CASCADE
+            );
+            CREATE TABLE IF NOT EXISTS network_interface_security_groups (
+                ifname TEXT NOT NULL,
+                group_name TEXT NOT NULL,
+                PRIMARY KEY(ifname, group_name),
+                FOREIGN KEY(ifname) REFERENCES network_interfaces(ifname) ON DELETE CASCADE,
+                FOREIGN KEY(group_name) REFERENCES security_groups(name) ON DELETE CASCADE
+            );
             "#,
         )
         .execute(&self.pool)
         .await?;
+        self.migrate().await
+    }
+
+    /// Applies the migrations the database has not seen yet, each in its own transaction.
+    async fn migrate(&self) -> Result<(), crate::Error> {
+        let version = sqlx::query_scalar::<_, i64>("PRAGMA user_version;")
+            .fetch_one(&self.pool)
+            .await?;
+        for (index, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+            let mut tx = self.pool.begin().await?;
+            migration.apply(&mut tx).await?;
+            sqlx::query(&format!("PRAGMA user_version = {};", index + 1))
+                .execute(&mut *tx)
+                .await?;
+            tx.commit().await?;
+        }
         Ok(())
     }
 
-    pub async fn add_ipv4_address(&self, addr: AddIPv4Address) -> Result<IPv4AddressRecord, crate::Error> {
-        let ipv4 = self.get_ipv4_by_ifname(&addr.ifname).await?;
-        let record = sqlx::query_as::<_, IPv4AddressRecord>(
+    /// Adds an address, the first one of its family on the interface becomes the default.
+    pub async fn add_ip_address(&self, addr: AddIpAddress) -> Result<IpAddressRecord, crate::Error> {
+        let existing = self.get_ip_addresses_by_ifname(&addr.ifname).await?;
+        let record = sqlx::query_as::<_, IpAddressRecord>(
             r#"
-            INSERT INTO ipv4_addresses (address, ifname, netmask, gateway, is_default)
-            VALUES (?, ?, ?, ?, ?)
-            RETURNING address, ifname, netmask, gateway, is_default;
+            INSERT INTO ip_addresses (address, ifname, family, prefix_len, gateway, mode, is_default)
+            VALUES (?, ?, ?, ?, ?, ?, ?)
+            RETURNING address, ifname, family, prefix_len, gateway, mode, is_default;
             "#,
         )
             .bind(&addr.address)
             .bind(&addr.ifname)
-            .bind(addr.netmask as i64)
+            .bind(addr.family)
+            .bind(addr.prefix_len as i64)
             .bind(&addr.gateway)
-            .bind(ipv4.is_empty())
+            .bind(addr.mode)
+            .bind(!existing.iter().any(|record| record.family == addr.family))
             .fetch_one(&self.pool)
             .await?;
         Ok(record)
@@ -160,13 +759,13 @@ impl VmRegistry {
         Ok(vms)
     }
 
-    async fn find_free_vnc_display(&self) -> Result<Option<String>, crate::Error> {
+    async fn find_free_vnc_display(conn: &mut sqlx::SqliteConnection) -> Result<Option<String>, crate::Error> {
         let used_displays = sqlx::query_scalar::<_, String>(
             r#"
             SELECT vnc_display FROM virtual_machines;
             "#,
         )
-            .fetch_all(&self.pool)
+            .fetch_all(conn)
             .await?
             .into_iter()
             .collect::<HashSet<_>>();
@@ -179,13 +778,52 @@ impl VmRegistry {
         Ok(None)
     }
 
-    async fn find_free_ifname(&self) -> Result<String, crate::Error> {
+    /// Checks the user supplied MAC addresses of the interfaces of a new VM against the ones in
+    /// use, returns them in order together with all the addresses now taken.
+    async fn reserve_macs(conn: &mut sqlx::SqliteConnection, interfaces: &[CreateNetworkInterface]) -> Result<(Vec<Option<String>>, HashSet<String>), crate::Error> {
+        let mut used_macs = sqlx::query_scalar::<_, String>(
+            r#"
+            SELECT mac_address FROM network_interfaces;
+            "#,
+        )
+            .fetch_all(conn)
+            .await?
+            .into_iter()
+            .map(|mac| mac.to_lowercase())
+            .collect::<HashSet<_>>();
+        let mut macs = vec![];
+        for net in interfaces {
+            let mac = net.mac_address.as_deref().map(normalize_mac).transpose()?;
+            if let Some(mac) = &mac
+                && !used_macs.insert(mac.clone())
+            {
+                return Err(crate::Error::MacAddressInUse(mac.clone()));
+            }
+            macs.push(mac);
+        }
+        Ok((macs, used_macs))
+    }
+
+    /// Generated MAC address of the interface `ifname` that is not in `used_macs`.
+    fn generate_mac(&self, ifname: &str, used_macs: &mut HashSet<String>) -> Result<String, crate::Error> {
+        let mut attempt = 0;
+        loop {
+            let mac = self.macs.generate(ifname, attempt)?;
+            if used_macs.insert(mac.clone()) {
+                return Ok(mac);
+            }
+            log::warn!("Generated MAC address {} of {} is already in use", mac, ifname);
+            attempt += 1;
+        }
+    }
+
+    async fn find_free_ifname(conn: &mut sqlx::SqliteConnection) -> Result<String, crate::Error> {
         let used_ifnames = sqlx::query_scalar::<_, String>(
             r#"
             SELECT ifname FROM network_interfaces;
             "#,
         )
-            .fetch_all(&self.pool)
+            .fetch_all(conn)
             .await?
             .into_iter()
             .collect::<HashSet<_>>();
@@ -198,22 +836,54 @@ impl VmRegistry {
         Err(crate::Error::NoFreeIfname)
     }
 
-    async fn insert_drive(&self, vm_id: &str, drive: &CreateDrive) -> Result<(), crate::Error> {
+    async fn insert_drive(conn: &mut sqlx::SqliteConnection, vm_id: &str, drive: &CreateDrive) -> Result<(), crate::Error> {
         sqlx::query(
             r#"
-            INSERT INTO drives (vm_id, id, drive_bus)
-            VALUES (?, ?, ?);
+            INSERT INTO drives (vm_id, id, drive_bus, size, options, encrypted, media)
+            VALUES (?, ?, ?, ?, ?, ?, ?);
             "#,
         )
             .bind(vm_id)
             .bind(&drive.id)
             .bind(serde_json::to_string(&drive.drive_bus)?)
-            .execute(&self.pool)
+            .bind(drive.size as i64)
+            .bind(serde_json::to_string(&drive.options)?)
+            .bind(drive.encrypted)
+            .bind(&drive.media)
+            .execute(conn)
             .await?;
         Ok(())
     }
 
     pub async fn create_vm(&self, vm: CreateVirtualMachine) -> Result<VirtualMachineRecord, crate::Error> {
+        let mut tx = self.pool.begin().await?;
+        let vm_record = self.insert_vm(&mut tx, vm).await?;
+        tx.commit().await?;
+        Ok(vm_record)
+    }
+
+    /// Fails with `NetworkFull` if `network` is a UDP socket network already linking two
+    /// interfaces.
+    async fn check_socket_capacity(conn: &mut sqlx::SqliteConnection, network: &str) -> Result<(), crate::Error> {
+        let full = sqlx::query_scalar::<_, bool>(
+            r#"
+            SELECT EXISTS (SELECT 1 FROM socket_networks WHERE name = ? AND mode = 'udp')
+                AND (SELECT COUNT(*) FROM network_interfaces WHERE network = ?) >= 2;
+            "#,
+        )
+            .bind(network)
+            .bind(network)
+            .fetch_one(conn)
+            .await?;
+        if full {
+            return Err(crate::Error::NetworkFull(network.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Inserts a VM with its interfaces and drives, allocating a VNC display, ifnames and MACs.
+    async fn insert_vm(&self, conn: &mut sqlx::SqliteConnection, vm: CreateVirtualMachine) -> Result<VirtualMachineRecord, crate::Error> {
+        let (macs, mut used_macs) = Self::reserve_macs(&mut *conn, &vm.network_interfaces).await?;
         let vm_record = sqlx::query_as::<_, VirtualMachineRecord>(
             r#"
             INSERT INTO virtual_machines (id, hostname, vcpu, memory, ovmf, vnc_display)
@@ -225,33 +895,106 @@ impl VmRegistry {
             .bind(vm.vcpu as i64)
             .bind(vm.memory as i64)
             .bind(vm.ovmf)
-            .bind(self.find_free_vnc_display().await?.unwrap())
-            .fetch_one(&self.pool)
+            .bind(Self::find_free_vnc_display(&mut *conn).await?.unwrap())
+            .fetch_one(&mut *conn)
             .await?;
         log::debug!("Created VM record: {:?}", vm_record);
-        for net in &vm.network_interfaces {
-            let ifname = self.find_free_ifname().await?;
+        for (net, mac) in vm.network_interfaces.iter().zip(macs) {
+            if let Some(network) = &net.network {
+                Self::check_socket_capacity(&mut *conn, network).await?;
+            }
+            let ifname = Self::find_free_ifname(&mut *conn).await?;
+            let mac = match mac {
+                Some(mac) => mac,
+                None => self.generate_mac(&ifname, &mut used_macs)?,
+            };
             sqlx::query(
                 r#"
-                INSERT INTO network_interfaces (ifname, vm_id, id, mac_address)
-                VALUES (?, ?, ?, ?);
+                INSERT INTO network_interfaces (ifname, vm_id, id, mac_address, network, vlan, options)
+                VALUES (?, ?, ?, ?, ?, ?, ?);
                 "#,
             )
                 .bind(&ifname)
                 .bind(&vm.id)
                 .bind(&net.id)
-                .bind(get_mac(&ifname))
-                .execute(&self.pool)
+                .bind(&mac)
+                .bind(&net.network)
+                .bind(net.vlan)
+                .bind(serde_json::to_string(&net.options)?)
+                .execute(&mut *conn)
                 .await?;
         }
         log::debug!("Added network interfaces for VM {}", vm.id);
         for drive in &vm.drives {
-            self.insert_drive(&vm.id, drive).await?;
+            Self::insert_drive(&mut *conn, &vm.id, drive).await?;
             log::debug!("Added drive {} for VM {}", drive.id, vm.id);
         }
         Ok(vm_record)
     }
 
+    /// Copies a VM's record, drives and network interfaces under a new id. Interfaces get new
+    /// ifnames and MACs, IP addresses are not copied. Disk drives of a linked clone and of
+    /// its source are qcow2 overlays afterwards, so raw drives are switched to qcow2 in both.
+    /// Nothing is changed unless all of it succeeds.
+    pub async fn clone_vm(&self, source_id: &str, clone: CloneVirtualMachine) -> Result<VirtualMachineRecord, crate::Error> {
+        if self.get_vm_by_id(&clone.id).await.is_ok() {
+            return Err(crate::Error::VMExists(clone.id));
+        }
+        let (vm, drives, network_interfaces, _) = self.get_vm_full(source_id).await?;
+        let linked_options = |drive: &DriveRecord| {
+            let mut options = drive.options.clone();
+            if clone.linked && !drive.is_cdrom() && options.format == Some(DriveFormat::Raw) {
+                options.format = Some(DriveFormat::Qcow2);
+            }
+            options
+        };
+        let mut tx = self.pool.begin().await?;
+        let vm_record = self.insert_vm(&mut tx, CreateVirtualMachine {
+            id: clone.id.clone(),
+            hostname: clone.hostname.clone().unwrap_or(vm.hostname),
+            vcpu: vm.vcpu,
+            memory: vm.memory,
+            ovmf: vm.ovmf,
+            network_interfaces: network_interfaces.iter()
+                .map(|nic| CreateNetworkInterface {
+                    id: nic.id.clone(),
+                    network: nic.network.clone(),
+                    vlan: nic.vlan,
+                    options: nic.options.clone(),
+                    mac_address: None,
+                })
+                .collect(),
+            drives: drives.iter()
+                .map(|drive| CreateDrive {
+                    id: drive.id.clone(),
+                    drive_bus: drive.drive_bus.clone(),
+                    size: drive.size,
+                    options: linked_options(drive),
+                    encrypted: drive.encrypted,
+                    media: drive.media.clone(),
+                })
+                .collect(),
+        }).await?;
+        for drive in &drives {
+            let options = linked_options(drive);
+            if options.format != drive.options.format {
+                sqlx::query(
+                    r#"
+                    UPDATE drives SET options = ? WHERE vm_id = ? AND id = ?;
+                    "#,
+                )
+                    .bind(serde_json::to_string(&options)?)
+                    .bind(source_id)
+                    .bind(&drive.id)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+        }
+        tx.commit().await?;
+        log::debug!("Cloned VM {} to {}", source_id, clone.id);
+        Ok(vm_record)
+    }
+
     pub async fn get_vm_by_id(&self, vm_id: &str) -> Result<VirtualMachineRecord, crate::Error> {
         let vm_record = sqlx::query_as::<_, VirtualMachineRecord>(
             r#"
@@ -267,7 +1010,7 @@ impl VmRegistry {
     pub async fn get_drives_by_vm_id(&self, vm_id: &str) -> Result<Vec<DriveRecord>, crate::Error> {
         let drives = sqlx::query_as::<_, DriveRecord>(
             r#"
-            SELECT vm_id, id, drive_bus FROM drives WHERE vm_id = ?;
+            SELECT vm_id, id, drive_bus, size, options, encrypted, media FROM drives WHERE vm_id = ?;
             "#,
         )
             .bind(vm_id)
@@ -276,85 +1019,1070 @@ impl VmRegistry {
         Ok(drives)
     }
 
-    pub async fn get_ipv4_by_ifname(&self, ifname: &str) -> Result<Vec<IPv4AddressRecord>, crate::Error> {
-        let addrs = sqlx::query_as::<_, IPv4AddressRecord>(
+    pub async fn get_drive(&self, vm_id: &str, drive_id: &str) -> Result<DriveRecord, crate::Error> {
+        let drive = sqlx::query_as::<_, DriveRecord>(
             r#"
-            SELECT address, ifname, netmask, gateway, is_default FROM ipv4_addresses WHERE ifname = ?;
+            SELECT vm_id, id, drive_bus, size, options, encrypted, media FROM drives WHERE vm_id = ? AND id = ?;
             "#,
         )
-            .bind(ifname)
-            .fetch_all(&self.pool)
+            .bind(vm_id)
+            .bind(drive_id)
+            .fetch_optional(&self.pool)
             .await?;
-        Ok(addrs)
+        drive.ok_or(crate::Error::DriveNotFound)
     }
 
-    pub async fn get_network_interfaces_by_vm_id(&self, vm_id: &str) -> Result<Vec<NetworkInterfaceRecord>, crate::Error> {
-        let nics = sqlx::query_as::<_, NetworkInterfaceRecord>(
+    pub async fn set_drive_size(&self, vm_id: &str, drive_id: &str, size: u64) -> Result<(), crate::Error> {
+        sqlx::query(
             r#"
-            SELECT ifname, vm_id, id, mac_address FROM network_interfaces WHERE vm_id = ?;
+            UPDATE drives SET size = ? WHERE vm_id = ? AND id = ?;
             "#,
         )
+            .bind(size as i64)
             .bind(vm_id)
-            .fetch_all(&self.pool)
+            .bind(drive_id)
+            .execute(&self.pool)
             .await?;
-        Ok(nics)
-    }
-
-    pub async fn get_vm_full(&self, vm_id: &str) -> Result<VmInfo, crate::Error> {
-        let vm_record = self.get_vm_by_id(vm_id).await?;
-        let drives = self.get_drives_by_vm_id(vm_id).await?;
-        let nics = self.get_network_interfaces_by_vm_id(vm_id).await?;
-        let mut addrs = vec![];
-        for nic in &nics {
-            let mut nic_addrs = self.get_ipv4_by_ifname(&nic.ifname).await?;
-            addrs.append(&mut nic_addrs);
-        }
-        Ok((vm_record, drives, nics, addrs))
+        log::debug!("Set size of drive {} for VM {} to {}", drive_id, vm_id, size);
+        Ok(())
     }
 
-    pub async fn get_vm_by_ifname(&self, ifname: &str) -> Result<VirtualMachineRecord, crate::Error> {
-        let vm_record = sqlx::query_as::<_, VirtualMachineRecord>(
+    pub async fn set_drive_media(&self, vm_id: &str, drive_id: &str, media: Option<&str>) -> Result<(), crate::Error> {
+        sqlx::query(
             r#"
-            SELECT vm.id, vm.hostname, vm.vcpu, vm.memory, vm.ovmf, vm.vnc_display
-            FROM virtual_machines vm
-            JOIN network_interfaces ni ON vm.id = ni.vm_id
-            WHERE ni.ifname = ?;
+            UPDATE drives SET media = ? WHERE vm_id = ? AND id = ?;
             "#,
         )
-            .bind(ifname)
-            .fetch_optional(&self.pool)
+            .bind(media)
+            .bind(vm_id)
+            .bind(drive_id)
+            .execute(&self.pool)
             .await?;
-        let vm_record = vm_record.ok_or(crate::Error::VMNotFound)?;
-        Ok(vm_record)
+        log::debug!("Set media of drive {} for VM {} to {:?}", drive_id, vm_id, media);
+        Ok(())
     }
 
-    pub async fn delete_vm(&self, vm_id: &str) -> Result<(), crate::Error> {
+    pub async fn set_drive_options(&self, vm_id: &str, drive_id: &str, options: &DriveOptions) -> Result<(), crate::Error> {
         sqlx::query(
             r#"
-            DELETE FROM virtual_machines WHERE id = ?;
+            UPDATE drives SET options = ? WHERE vm_id = ? AND id = ?;
             "#,
         )
+            .bind(serde_json::to_string(options)?)
             .bind(vm_id)
+            .bind(drive_id)
             .execute(&self.pool)
             .await?;
-        log::debug!("Deleted VM {}", vm_id);
+        log::debug!("Set options of drive {} for VM {} to {:?}", drive_id, vm_id, options);
         Ok(())
     }
 
-    pub async fn replace_drives(&self, vm_id: &str, drives: Vec<CreateDrive>) -> Result<(), crate::Error> {
-        sqlx::query(
+    pub async fn get_ip_addresses_by_ifname(&self, ifname: &str) -> Result<Vec<IpAddressRecord>, crate::Error> {
+        let addrs = sqlx::query_as::<_, IpAddressRecord>(
             r#"
-            DELETE FROM drives WHERE vm_id = ?;
+            SELECT address, ifname, family, prefix_len, gateway, mode, is_default FROM ip_addresses WHERE ifname = ?;
             "#,
         )
-            .bind(vm_id)
-            .execute(&self.pool)
+            .bind(ifname)
+            .fetch_all(&self.pool)
             .await?;
-        for drive in &drives {
-            self.insert_drive(vm_id, drive).await?;
-        }
-        log::debug!("Replaced drives for VM {}", vm_id);
-        Ok(())
+        Ok(addrs)
+    }
+
+    pub async fn get_ipv4_by_ifname(&self, ifname: &str) -> Result<Vec<IpAddressRecord>, crate::Error> {
+        let addrs = sqlx::query_as::<_, IpAddressRecord>(
+            r#"
+            SELECT address, ifname, family, prefix_len, gateway, mode, is_default FROM ip_addresses
+            WHERE ifname = ? AND family = 'ipv4';
+            "#,
+        )
+            .bind(ifname)
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(addrs)
+    }
+
+    pub async fn get_ipv4_addresses(&self) -> Result<Vec<IpAddressRecord>, crate::Error> {
+        let addrs = sqlx::query_as::<_, IpAddressRecord>(
+            r#"
+            SELECT address, ifname, family, prefix_len, gateway, mode, is_default FROM ip_addresses
+            WHERE family = 'ipv4';
+            "#,
+        )
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(addrs)
     }
-}
 
+    pub async fn get_ip_by_address(&self, address: &str) -> Result<Option<IpAddressRecord>, crate::Error> {
+        let addr = sqlx::query_as::<_, IpAddressRecord>(
+            r#"
+            SELECT address, ifname, family, prefix_len, gateway, mode, is_default FROM ip_addresses WHERE address = ?;
+            "#,
+        )
+            .bind(address)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(addr)
+    }
+
+    /// Removes an IPv4 address of an interface. If it was the default, the oldest remaining
+    /// IPv4 address of the interface becomes the default.
+    pub async fn remove_ipv4_address(&self, ifname: &str, address: &str) -> Result<(), crate::Error> {
+        let mut tx = self.pool.begin().await?;
+        let removed = sqlx::query_scalar::<_, bool>(
+            r#"
+            DELETE FROM ip_addresses WHERE ifname = ? AND address = ? AND family = 'ipv4'
+            RETURNING is_default;
+            "#,
+        )
+            .bind(ifname)
+            .bind(address)
+            .fetch_optional(&mut *tx)
+            .await?;
+        let Some(was_default) = removed else {
+            return Err(crate::Error::IpAddressNotFound(address.to_string()));
+        };
+        if was_default {
+            sqlx::query(
+                r#"
+                UPDATE ip_addresses SET is_default = TRUE
+                WHERE rowid = (SELECT rowid FROM ip_addresses WHERE ifname = ? AND family = 'ipv4' ORDER BY rowid LIMIT 1);
+                "#,
+            )
+                .bind(ifname)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+        log::debug!("Removed address {} from {}", address, ifname);
+        Ok(())
+    }
+
+    /// Makes an address the default of its family on the interface.
+    pub async fn set_default_address(&self, ifname: &str, address: &str) -> Result<(), crate::Error> {
+        let record = self.get_ip_by_address(address).await?
+            .filter(|record| record.ifname == ifname)
+            .ok_or(crate::Error::IpAddressNotFound(address.to_string()))?;
+        sqlx::query(
+            r#"
+            UPDATE ip_addresses SET is_default = (address = ?) WHERE ifname = ? AND family = ?;
+            "#,
+        )
+            .bind(&record.address)
+            .bind(&record.ifname)
+            .bind(record.family)
+            .execute(&self.pool)
+            .await?;
+        log::debug!("Set default address of {} to {}", ifname, address);
+        Ok(())
+    }
+
+    /// Changes the prefix length and gateway of an IPv4 address.
+    pub async fn update_ipv4_address(&self, addr: &IpAddressRecord) -> Result<(), crate::Error> {
+        let result = sqlx::query(
+            r#"
+            UPDATE ip_addresses SET prefix_len = ?, gateway = ?
+            WHERE ifname = ? AND address = ? AND family = 'ipv4';
+            "#,
+        )
+            .bind(addr.prefix_len as i64)
+            .bind(&addr.gateway)
+            .bind(&addr.ifname)
+            .bind(&addr.address)
+            .execute(&self.pool)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Err(crate::Error::IpAddressNotFound(addr.address.clone()));
+        }
+        log::debug!("Updated address {:?}", addr);
+        Ok(())
+    }
+
+    pub async fn get_network_interfaces_by_vm_id(&self, vm_id: &str) -> Result<Vec<NetworkInterfaceRecord>, crate::Error> {
+        let nics = sqlx::query_as::<_, NetworkInterfaceRecord>(
+            r#"
+            SELECT ifname, vm_id, id, mac_address, network, attached, vlan, options FROM network_interfaces WHERE vm_id = ?;
+            "#,
+        )
+            .bind(vm_id)
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(nics)
+    }
+
+    pub async fn get_network_interface_by_ifname(&self, ifname: &str) -> Result<NetworkInterfaceRecord, crate::Error> {
+        let nic = sqlx::query_as::<_, NetworkInterfaceRecord>(
+            r#"
+            SELECT ifname, vm_id, id, mac_address, network, attached, vlan, options FROM network_interfaces WHERE ifname = ?;
+            "#,
+        )
+            .bind(ifname)
+            .fetch_optional(&self.pool)
+            .await?;
+        nic.ok_or(crate::Error::VMNotFound)
+    }
+
+    /// Whether any interface has the MAC address `mac`, compared case-insensitively.
+    pub async fn mac_address_in_use(&self, mac: &str) -> Result<bool, crate::Error> {
+        let count = sqlx::query_scalar::<_, i64>(
+            r#"
+            SELECT COUNT(*) FROM network_interfaces WHERE lower(mac_address) = lower(?);
+            "#,
+        )
+            .bind(mac)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(count > 0)
+    }
+
+    pub async fn set_network_interface_attached(&self, ifname: &str, attached: bool) -> Result<(), crate::Error> {
+        sqlx::query(
+            r#"
+            UPDATE network_interfaces SET attached = ? WHERE ifname = ?;
+            "#,
+        )
+            .bind(attached)
+            .bind(ifname)
+            .execute(&self.pool)
+            .await?;
+        log::debug!("Set interface {} attached: {}", ifname, attached);
+        Ok(())
+    }
+
+    pub async fn set_network_interface_vlan(&self, ifname: &str, vlan: Option<u16>) -> Result<(), crate::Error> {
+        let result = sqlx::query(
+            r#"
+            UPDATE network_interfaces SET vlan = ? WHERE ifname = ?;
+            "#,
+        )
+            .bind(vlan)
+            .bind(ifname)
+            .execute(&self.pool)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Err(crate::Error::VMNotFound);
+        }
+        log::debug!("Set interface {} VLAN: {:?}", ifname, vlan);
+        Ok(())
+    }
+
+    pub async fn set_network_interface_options(&self, ifname: &str, options: &NicOptions) -> Result<(), crate::Error> {
+        sqlx::query(
+            r#"
+            UPDATE network_interfaces SET options = ? WHERE ifname = ?;
+            "#,
+        )
+            .bind(serde_json::to_string(options)?)
+            .bind(ifname)
+            .execute(&self.pool)
+            .await?;
+        log::debug!("Set options of interface {} to {:?}", ifname, options);
+        Ok(())
+    }
+
+    pub async fn get_vm_full(&self, vm_id: &str) -> Result<VmInfo, crate::Error> {
+        let vm_record = self.get_vm_by_id(vm_id).await?;
+        let drives = self.get_drives_by_vm_id(vm_id).await?;
+        let nics = self.get_network_interfaces_by_vm_id(vm_id).await?;
+        let mut addrs = vec![];
+        for nic in &nics {
+            let mut nic_addrs = self.get_ip_addresses_by_ifname(&nic.ifname).await?;
+            addrs.append(&mut nic_addrs);
+        }
+        Ok((vm_record, drives, nics, addrs))
+    }
+
+    pub async fn get_vm_by_ifname(&self, ifname: &str) -> Result<VirtualMachineRecord, crate::Error> {
+        let vm_record = sqlx::query_as::<_, VirtualMachineRecord>(
+            r#"
+            SELECT vm.id, vm.hostname, vm.vcpu, vm.memory, vm.ovmf, vm.vnc_display
+            FROM virtual_machines vm
+            JOIN network_interfaces ni ON vm.id = ni.vm_id
+            WHERE ni.ifname = ?;
+            "#,
+        )
+            .bind(ifname)
+            .fetch_optional(&self.pool)
+            .await?;
+        let vm_record = vm_record.ok_or(crate::Error::VMNotFound)?;
+        Ok(vm_record)
+    }
+
+    pub async fn delete_vm(&self, vm_id: &str) -> Result<(), crate::Error> {
+        sqlx::query(
+            r#"
+            DELETE FROM virtual_machines WHERE id = ?;
+            "#,
+        )
+            .bind(vm_id)
+            .execute(&self.pool)
+            .await?;
+        log::debug!("Deleted VM {}", vm_id);
+        Ok(())
+    }
+
+    pub async fn replace_drives(&self, vm_id: &str, drives: Vec<CreateDrive>) -> Result<(), crate::Error> {
+        let mut tx = self.pool.begin().await?;
+        sqlx::query(
+            r#"
+            DELETE FROM drives WHERE vm_id = ?;
+            "#,
+        )
+            .bind(vm_id)
+            .execute(&mut *tx)
+            .await?;
+        for drive in &drives {
+            Self::insert_drive(&mut tx, vm_id, drive).await?;
+        }
+        tx.commit().await?;
+        log::debug!("Replaced drives for VM {}", vm_id);
+        Ok(())
+    }
+
+    pub async fn create_backup(&self, vm_id: &str) -> Result<BackupRecord, crate::Error> {
+        let created_at = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or(0);
+        let record = sqlx::query_as::<_, BackupRecord>(
+            r#"
+            INSERT INTO backups (vm_id, created_at)
+            VALUES (?, ?)
+            RETURNING id, vm_id, created_at;
+            "#,
+        )
+            .bind(vm_id)
+            .bind(created_at as i64)
+            .fetch_one(&self.pool)
+            .await?;
+        log::debug!("Created backup record: {:?}", record);
+        Ok(record)
+    }
+
+    pub async fn add_backup_image(&self, backup_id: i64, drive_id: &str, incremental: bool, path: &str) -> Result<(), crate::Error> {
+        sqlx::query(
+            r#"
+            INSERT INTO backup_images (backup_id, drive_id, incremental, path)
+            VALUES (?, ?, ?, ?);
+            "#,
+        )
+            .bind(backup_id)
+            .bind(drive_id)
+            .bind(incremental)
+            .bind(path)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn get_backups_by_vm_id(&self, vm_id: &str) -> Result<Vec<BackupRecord>, crate::Error> {
+        let backups = sqlx::query_as::<_, BackupRecord>(
+            r#"
+            SELECT id, vm_id, created_at FROM backups WHERE vm_id = ? ORDER BY id;
+            "#,
+        )
+            .bind(vm_id)
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(backups)
+    }
+
+    pub async fn get_backup(&self, vm_id: &str, backup_id: i64) -> Result<BackupRecord, crate::Error> {
+        let backup = sqlx::query_as::<_, BackupRecord>(
+            r#"
+            SELECT id, vm_id, created_at FROM backups WHERE vm_id = ? AND id = ?;
+            "#,
+        )
+            .bind(vm_id)
+            .bind(backup_id)
+            .fetch_optional(&self.pool)
+            .await?;
+        backup.ok_or(crate::Error::BackupNotFound)
+    }
+
+    pub async fn get_backup_images(&self, backup_id: i64) -> Result<Vec<BackupImageRecord>, crate::Error> {
+        let images = sqlx::query_as::<_, BackupImageRecord>(
+            r#"
+            SELECT backup_id, drive_id, incremental, path FROM backup_images WHERE backup_id = ?;
+            "#,
+        )
+            .bind(backup_id)
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(images)
+    }
+
+    /// Most recent backup image of a drive, the parent of its next incremental image.
+    pub async fn get_latest_backup_image(&self, vm_id: &str, drive_id: &str) -> Result<Option<BackupImageRecord>, crate::Error> {
+        let image = sqlx::query_as::<_, BackupImageRecord>(
+            r#"
+            SELECT i.backup_id, i.drive_id, i.incremental, i.path FROM backup_images i
+            JOIN backups b ON b.id = i.backup_id
+            WHERE b.vm_id = ? AND i.drive_id = ?
+            ORDER BY i.backup_id DESC LIMIT 1;
+            "#,
+        )
+            .bind(vm_id)
+            .bind(drive_id)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(image)
+    }
+
+    /// Backup image of a drive following the one in `backup_id`, i.e. its child in the chain.
+    pub async fn get_next_backup_image(&self, vm_id: &str, drive_id: &str, backup_id: i64) -> Result<Option<BackupImageRecord>, crate::Error> {
+        let image = sqlx::query_as::<_, BackupImageRecord>(
+            r#"
+            SELECT i.backup_id, i.drive_id, i.incremental, i.path FROM backup_images i
+            JOIN backups b ON b.id = i.backup_id
+            WHERE b.vm_id = ? AND i.drive_id = ? AND i.backup_id > ?
+            ORDER BY i.backup_id LIMIT 1;
+            "#,
+        )
+            .bind(vm_id)
+            .bind(drive_id)
+            .bind(backup_id)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(image)
+    }
+
+    pub async fn set_backup_image_full(&self, backup_id: i64, drive_id: &str) -> Result<(), crate::Error> {
+        sqlx::query(
+            r#"
+            UPDATE backup_images SET incremental = FALSE WHERE backup_id = ? AND drive_id = ?;
+            "#,
+        )
+            .bind(backup_id)
+            .bind(drive_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn delete_backup(&self, backup_id: i64) -> Result<(), crate::Error> {
+        sqlx::query(
+            r#"
+            DELETE FROM backup_images WHERE backup_id = ?;
+            "#,
+        )
+            .bind(backup_id)
+            .execute(&self.pool)
+            .await?;
+        sqlx::query(
+            r#"
+            DELETE FROM backups WHERE id = ?;
+            "#,
+        )
+            .bind(backup_id)
+            .execute(&self.pool)
+            .await?;
+        log::debug!("Deleted backup {}", backup_id);
+        Ok(())
+    }
+
+    pub async fn create_network(&self, network: CreateNetwork) -> Result<NetworkRecord, crate::Error> {
+        let existing = sqlx::query_scalar::<_, i64>(
+            r#"
+            SELECT (SELECT COUNT(*) FROM networks WHERE name = ? OR bridge = ?)
+                + (SELECT COUNT(*) FROM socket_networks WHERE name = ?);
+            "#,
+        )
+            .bind(&network.name)
+            .bind(&network.bridge)
+            .bind(&network.name)
+            .fetch_one(&self.pool)
+            .await?;
+        if existing > 0 {
+            return Err(crate::Error::NetworkExists(network.name));
+        }
+        let record = sqlx::query_as::<_, NetworkRecord>(
+            r#"
+            INSERT INTO networks (name, bridge, address, prefix_len, nat, dhcp, mtu, isolated)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+            RETURNING name, bridge, address, prefix_len, nat, dhcp, mtu, isolated;
+            "#,
+        )
+            .bind(&network.name)
+            .bind(&network.bridge)
+            .bind(&network.address)
+            .bind(network.prefix_len as i64)
+            .bind(network.nat)
+            .bind(network.dhcp)
+            .bind(network.mtu.map(|mtu| mtu as i64))
+            .bind(network.isolated)
+            .fetch_one(&self.pool)
+            .await?;
+        log::debug!("Created network record: {:?}", record);
+        Ok(record)
+    }
+
+    pub async fn get_networks(&self) -> Result<Vec<NetworkRecord>, crate::Error> {
+        let networks = sqlx::query_as::<_, NetworkRecord>(
+            r#"
+            SELECT name, bridge, address, prefix_len, nat, dhcp, mtu, isolated FROM networks ORDER BY name;
+            "#,
+        )
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(networks)
+    }
+
+    pub async fn find_network(&self, name: &str) -> Result<Option<NetworkRecord>, crate::Error> {
+        let network = sqlx::query_as::<_, NetworkRecord>(
+            r#"
+            SELECT name, bridge, address, prefix_len, nat, dhcp, mtu, isolated FROM networks WHERE name = ?;
+            "#,
+        )
+            .bind(name)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(network)
+    }
+
+    pub async fn get_network(&self, name: &str) -> Result<NetworkRecord, crate::Error> {
+        self.find_network(name).await?
+            .ok_or(crate::Error::NetworkNotFound(name.to_string()))
+    }
+
+    /// Updates the mutable settings of a network, its bridge and subnet are fixed on creation.
+    pub async fn update_network(&self, network: &NetworkRecord) -> Result<(), crate::Error> {
+        sqlx::query(
+            r#"
+            UPDATE networks SET nat = ?, dhcp = ?, mtu = ? WHERE name = ?;
+            "#,
+        )
+            .bind(network.nat)
+            .bind(network.dhcp)
+            .bind(network.mtu.map(|mtu| mtu as i64))
+            .bind(&network.name)
+            .execute(&self.pool)
+            .await?;
+        log::debug!("Updated network {:?}", network);
+        Ok(())
+    }
+
+    pub async fn delete_network(&self, name: &str) -> Result<(), crate::Error> {
+        sqlx::query(
+            r#"
+            DELETE FROM networks WHERE name = ?;
+            "#,
+        )
+            .bind(name)
+            .execute(&self.pool)
+            .await?;
+        log::debug!("Deleted network {}", name);
+        Ok(())
+    }
+
+    /// Registers a socket network, its name must not be taken by a managed network and its
+    /// address and port by another socket network.
+    pub async fn create_socket_network(&self, network: CreateSocketNetwork) -> Result<SocketNetworkRecord, crate::Error> {
+        let existing = sqlx::query_scalar::<_, i64>(
+            r#"
+            SELECT (SELECT COUNT(*) FROM networks WHERE name = ?)
+                + (SELECT COUNT(*) FROM socket_networks WHERE name = ? OR (address = ? AND port = ?));
+            "#,
+        )
+            .bind(&network.name)
+            .bind(&network.name)
+            .bind(&network.address)
+            .bind(network.port as i64)
+            .fetch_one(&self.pool)
+            .await?;
+        if existing > 0 {
+            return Err(crate::Error::NetworkExists(network.name));
+        }
+        let record = sqlx::query_as::<_, SocketNetworkRecord>(
+            r#"
+            INSERT INTO socket_networks (name, mode, address, port, local_address)
+            VALUES (?, ?, ?, ?, ?)
+            RETURNING name, mode, address, port, local_address;
+            "#,
+        )
+            .bind(&network.name)
+            .bind(network.mode)
+            .bind(&network.address)
+            .bind(network.port as i64)
+            .bind(&network.local_address)
+            .fetch_one(&self.pool)
+            .await?;
+        log::debug!("Created socket network record: {:?}", record);
+        Ok(record)
+    }
+
+    pub async fn get_socket_networks(&self) -> Result<Vec<SocketNetworkRecord>, crate::Error> {
+        let networks = sqlx::query_as::<_, SocketNetworkRecord>(
+            r#"
+            SELECT name, mode, address, port, local_address FROM socket_networks ORDER BY name;
+            "#,
+        )
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(networks)
+    }
+
+    pub async fn find_socket_network(&self, name: &str) -> Result<Option<SocketNetworkRecord>, crate::Error> {
+        let network = sqlx::query_as::<_, SocketNetworkRecord>(
+            r#"
+            SELECT name, mode, address, port, local_address FROM socket_networks WHERE name = ?;
+            "#,
+        )
+            .bind(name)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(network)
+    }
+
+    pub async fn delete_socket_network(&self, name: &str) -> Result<(), crate::Error> {
+        let result = sqlx::query(
+            r#"
+            DELETE FROM socket_networks WHERE name = ?;
+            "#,
+        )
+            .bind(name)
+            .execute(&self.pool)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Err(crate::Error::NetworkNotFound(name.to_string()));
+        }
+        log::debug!("Deleted socket network {}", name);
+        Ok(())
+    }
+
+    /// Interfaces of a network in the order they were created.
+    pub async fn get_network_interfaces_by_network(&self, network: &str) -> Result<Vec<NetworkInterfaceRecord>, crate::Error> {
+        let nics = sqlx::query_as::<_, NetworkInterfaceRecord>(
+            r#"
+            SELECT ifname, vm_id, id, mac_address, network, attached, vlan, options FROM network_interfaces WHERE network = ? ORDER BY rowid;
+            "#,
+        )
+            .bind(network)
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(nics)
+    }
+
+    /// Static DHCP leases of a network, handing each interface its default IPv4 address.
+    pub async fn get_dhcp_hosts(&self, network: &str) -> Result<Vec<DhcpHostRecord>, crate::Error> {
+        let hosts = sqlx::query_as::<_, DhcpHostRecord>(
+            r#"
+            SELECT ni.mac_address, ip.address, vm.hostname
+            FROM ip_addresses ip
+            JOIN network_interfaces ni ON ni.ifname = ip.ifname
+            JOIN virtual_machines vm ON vm.id = ni.vm_id
+            WHERE ni.network = ? AND ip.family = 'ipv4' AND ip.is_default
+            ORDER BY ip.address;
+            "#,
+        )
+            .bind(network)
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(hosts)
+    }
+
+    pub async fn create_security_group(&self, name: &str, description: Option<&str>) -> Result<SecurityGroupRecord, crate::Error> {
+        if self.find_security_group(name).await?.is_some() {
+            return Err(crate::Error::SecurityGroupExists(name.to_string()));
+        }
+        let record = sqlx::query_as::<_, SecurityGroupRecord>(
+            r#"
+            INSERT INTO security_groups (name, description)
+            VALUES (?, ?)
+            RETURNING name, description;
+            "#,
+        )
+            .bind(name)
+            .bind(description)
+            .fetch_one(&self.pool)
+            .await?;
+        log::debug!("Created security group record: {:?}", record);
+        Ok(record)
+    }
+
+    pub async fn get_security_groups(&self) -> Result<Vec<SecurityGroupRecord>, crate::Error> {
+        let groups = sqlx::query_as::<_, SecurityGroupRecord>(
+            r#"
+            SELECT name, description FROM security_groups ORDER BY name;
+            "#,
+        )
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(groups)
+    }
+
+    async fn find_security_group(&self, name: &str) -> Result<Option<SecurityGroupRecord>, crate::Error> {
+        let group = sqlx::query_as::<_, SecurityGroupRecord>(
+            r#"
+            SELECT name, description FROM security_groups WHERE name = ?;
+            "#,
+        )
+            .bind(name)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(group)
+    }
+
+    pub async fn get_security_group(&self, name: &str) -> Result<SecurityGroupRecord, crate::Error> {
+        self.find_security_group(name).await?
+            .ok_or(crate::Error::SecurityGroupNotFound(name.to_string()))
+    }
+
+    pub async fn delete_security_group(&self, name: &str) -> Result<(), crate::Error> {
+        sqlx::query(
+            r#"
+            DELETE FROM security_group_rules WHERE group_name = ?;
+            "#,
+        )
+            .bind(name)
+            .execute(&self.pool)
+            .await?;
+        sqlx::query(
+            r#"
+            DELETE FROM security_groups WHERE name = ?;
+            "#,
+        )
+            .bind(name)
+            .execute(&self.pool)
+            .await?;
+        log::debug!("Deleted security group {}", name);
+        Ok(())
+    }
+
+    pub async fn add_security_group_rule(&self, group_name: &str, rule: AddSecurityGroupRule) -> Result<SecurityGroupRuleRecord, crate::Error> {
+        let record = sqlx::query_as::<_, SecurityGroupRuleRecord>(
+            r#"
+            INSERT INTO security_group_rules (group_name, direction, protocol, port_from, port_to, cidr)
+            VALUES (?, ?, ?, ?, ?, ?)
+            RETURNING id, group_name, direction, protocol, port_from, port_to, cidr;
+            "#,
+        )
+            .bind(group_name)
+            .bind(rule.direction)
+            .bind(rule.protocol)
+            .bind(rule.port_from.map(|port| port as i64))
+            .bind(rule.port_to.map(|port| port as i64))
+            .bind(&rule.cidr)
+            .fetch_one(&self.pool)
+            .await?;
+        log::debug!("Added security group rule: {:?}", record);
+        Ok(record)
+    }
+
+    pub async fn get_security_group_rules(&self, group_name: &str) -> Result<Vec<SecurityGroupRuleRecord>, crate::Error> {
+        let rules = sqlx::query_as::<_, SecurityGroupRuleRecord>(
+            r#"
+            SELECT id, group_name, direction, protocol, port_from, port_to, cidr
+            FROM security_group_rules WHERE group_name = ? ORDER BY id;
+            "#,
+        )
+            .bind(group_name)
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(rules)
+    }
+
+    /// Deletes a rule of a group, returns whether it existed.
+    pub async fn delete_security_group_rule(&self, group_name: &str, rule_id: i64) -> Result<bool, crate::Error> {
+        let result = sqlx::query(
+            r#"
+            DELETE FROM security_group_rules WHERE group_name = ? AND id = ?;
+            "#,
+        )
+            .bind(group_name)
+            .bind(rule_id)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn attach_security_group(&self, ifname: &str, group_name: &str) -> Result<(), crate::Error> {
+        sqlx::query(
+            r#"
+            INSERT OR IGNORE INTO network_interface_security_groups (ifname, group_name)
+            VALUES (?, ?);
+            "#,
+        )
+            .bind(ifname)
+            .bind(group_name)
+            .execute(&self.pool)
+            .await?;
+        log::debug!("Attached security group {} to {}", group_name, ifname);
+        Ok(())
+    }
+
+    pub async fn detach_security_group(&self, ifname: &str, group_name: &str) -> Result<(), crate::Error> {
+        sqlx::query(
+            r#"
+            DELETE FROM network_interface_security_groups WHERE ifname = ? AND group_name = ?;
+            "#,
+        )
+            .bind(ifname)
+            .bind(group_name)
+            .execute(&self.pool)
+            .await?;
+        log::debug!("Detached security group {} from {}", group_name, ifname);
+        Ok(())
+    }
+
+    pub async fn get_security_groups_by_ifname(&self, ifname: &str) -> Result<Vec<SecurityGroupRecord>, crate::Error> {
+        let groups = sqlx::query_as::<_, SecurityGroupRecord>(
+            r#"
+            SELECT sg.name, sg.description FROM security_groups sg
+            JOIN network_interface_security_groups nisg ON nisg.group_name = sg.name
+            WHERE nisg.ifname = ?
+            ORDER BY sg.name;
+            "#,
+        )
+            .bind(ifname)
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(groups)
+    }
+
+    pub async fn get_network_interfaces_by_security_group(&self, group_name: &str) -> Result<Vec<NetworkInterfaceRecord>, crate::Error> {
+        let nics = sqlx::query_as::<_, NetworkInterfaceRecord>(
+            r#"
+            SELECT ni.ifname, ni.vm_id, ni.id, ni.mac_address, ni.network, ni.attached, ni.vlan, ni.options FROM network_interfaces ni
+            JOIN network_interface_security_groups nisg ON nisg.ifname = ni.ifname
+            WHERE nisg.group_name = ?;
+            "#,
+        )
+            .bind(group_name)
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(nics)
+    }
+
+    /// Adds a port forward unless another one already claims the host port for the same
+    /// protocol on an overlapping host address.
+    pub async fn add_port_forward(&self, vm_id: &str, forward: AddPortForward) -> Result<PortForwardRecord, crate::Error> {
+        let conflict = sqlx::query_as::<_, PortForwardRecord>(
+            r#"
+            SELECT id, vm_id, ifname, host_ip, host_port, protocol, guest_port FROM port_forwards
+            WHERE host_port = ? AND protocol = ? AND (host_ip IS NULL OR ? IS NULL OR host_ip = ?);
+            "#,
+        )
+            .bind(forward.host_port as i64)
+            .bind(forward.protocol)
+            .bind(&forward.host_ip)
+            .bind(&forward.host_ip)
+            .fetch_optional(&self.pool)
+            .await?;
+        if let Some(conflict) = conflict {
+            return Err(crate::Error::PortForwardConflict(format!("host port {} is forwarded to VM {}", conflict.host_port, conflict.vm_id)));
+        }
+        let record = sqlx::query_as::<_, PortForwardRecord>(
+            r#"
+            INSERT INTO port_forwards (vm_id, ifname, host_ip, host_port, protocol, guest_port)
+            VALUES (?, ?, ?, ?, ?, ?)
+            RETURNING id, vm_id, ifname, host_ip, host_port, protocol, guest_port;
+            "#,
+        )
+            .bind(vm_id)
+            .bind(&forward.ifname)
+            .bind(&forward.host_ip)
+            .bind(forward.host_port as i64)
+            .bind(forward.protocol)
+            .bind(forward.guest_port as i64)
+            .fetch_one(&self.pool)
+            .await?;
+        log::debug!("Added port forward: {:?}", record);
+        Ok(record)
+    }
+
+    pub async fn get_port_forwards(&self) -> Result<Vec<PortForwardRecord>, crate::Error> {
+        let forwards = sqlx::query_as::<_, PortForwardRecord>(
+            r#"
+            SELECT id, vm_id, ifname, host_ip, host_port, protocol, guest_port FROM port_forwards ORDER BY id;
+            "#,
+        )
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(forwards)
+    }
+
+    pub async fn get_port_forwards_by_vm_id(&self, vm_id: &str) -> Result<Vec<PortForwardRecord>, crate::Error> {
+        let forwards = sqlx::query_as::<_, PortForwardRecord>(
+            r#"
+            SELECT id, vm_id, ifname, host_ip, host_port, protocol, guest_port FROM port_forwards
+            WHERE vm_id = ? ORDER BY id;
+            "#,
+        )
+            .bind(vm_id)
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(forwards)
+    }
+
+    pub async fn delete_port_forward(&self, vm_id: &str, forward_id: i64) -> Result<(), crate::Error> {
+        let result = sqlx::query(
+            r#"
+            DELETE FROM port_forwards WHERE vm_id = ? AND id = ?;
+            "#,
+        )
+            .bind(vm_id)
+            .bind(forward_id)
+            .execute(&self.pool)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Err(crate::Error::PortForwardNotFound);
+        }
+        log::debug!("Deleted port forward {} of VM {}", forward_id, vm_id);
+        Ok(())
+    }
+
+    /// Registers an address pool. Each network, and the default bridge, has at most one pool.
+    pub async fn create_ip_pool(&self, pool: CreateIpPool) -> Result<IpPoolRecord, crate::Error> {
+        let existing = sqlx::query_scalar::<_, i64>(
+            r#"
+            SELECT COUNT(*) FROM ip_pools WHERE name = ? OR network IS ?;
+            "#,
+        )
+            .bind(&pool.name)
+            .bind(&pool.network)
+            .fetch_one(&self.pool)
+            .await?;
+        if existing > 0 {
+            return Err(crate::Error::IpPoolExists(pool.name));
+        }
+        let record = sqlx::query_as::<_, IpPoolRecord>(
+            r#"
+            INSERT INTO ip_pools (name, cidr, gateway, reserved, nameservers, network)
+            VALUES (?, ?, ?, ?, ?, ?)
+            RETURNING name, cidr, gateway, reserved, nameservers, network;
+            "#,
+        )
+            .bind(&pool.name)
+            .bind(&pool.cidr)
+            .bind(&pool.gateway)
+            .bind(serde_json::to_string(&pool.reserved)?)
+            .bind(serde_json::to_string(&pool.nameservers)?)
+            .bind(&pool.network)
+            .fetch_one(&self.pool)
+            .await?;
+        log::debug!("Created IP pool: {:?}", record);
+        Ok(record)
+    }
+
+    pub async fn get_ip_pools(&self) -> Result<Vec<IpPoolRecord>, crate::Error> {
+        let pools = sqlx::query_as::<_, IpPoolRecord>(
+            r#"
+            SELECT name, cidr, gateway, reserved, nameservers, network FROM ip_pools ORDER BY name;
+            "#,
+        )
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(pools)
+    }
+
+    pub async fn get_ip_pool(&self, name: &str) -> Result<IpPoolRecord, crate::Error> {
+        let pool = sqlx::query_as::<_, IpPoolRecord>(
+            r#"
+            SELECT name, cidr, gateway, reserved, nameservers, network FROM ip_pools WHERE name = ?;
+            "#,
+        )
+            .bind(name)
+            .fetch_optional(&self.pool)
+            .await?;
+        pool.ok_or(crate::Error::IpPoolNotFound(name.to_string()))
+    }
+
+    /// Pool serving the interfaces of `network`, or of the default bridge for `None`.
+    pub async fn find_ip_pool_by_network(&self, network: Option<&str>) -> Result<Option<IpPoolRecord>, crate::Error> {
+        let pool = sqlx::query_as::<_, IpPoolRecord>(
+            r#"
+            SELECT name, cidr, gateway, reserved, nameservers, network FROM ip_pools WHERE network IS ?;
+            "#,
+        )
+            .bind(network)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(pool)
+    }
+
+    pub async fn delete_ip_pool(&self, name: &str) -> Result<(), crate::Error> {
+        let result = sqlx::query(
+            r#"
+            DELETE FROM ip_pools WHERE name = ?;
+            "#,
+        )
+            .bind(name)
+            .execute(&self.pool)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Err(crate::Error::IpPoolNotFound(name.to_string()));
+        }
+        log::debug!("Deleted IP pool {}", name);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn registry() -> VmRegistry {
+        let pool = sqlx::sqlite::SqlitePoolOptions::new()
+            .max_connections(1)
+            .connect("sqlite::memory:")
+            .await
+            .unwrap();
+        let registry = VmRegistry::new(pool, MacGenerator::new(None, None));
+        registry.create_tables().await.unwrap();
+        registry
+    }
+
+    fn address(ifname: &str, address: &str, family: IpFamily) -> AddIpAddress {
+        AddIpAddress {
+            ifname: ifname.to_string(),
+            address: address.to_string(),
+            family,
+            prefix_len: if family == IpFamily::Ipv4 { 24 } else { 64 },
+            gateway: None,
+            mode: AddressMode::Static,
+        }
+    }
+
+    #[tokio::test]
+    async fn remove_default_address() {
+        let registry = registry().await;
+        registry.create_vm(CreateVirtualMachine {
+            id: "vm".to_string(),
+            hostname: "vm".to_string(),
+            vcpu: 1,
+            memory: 256,
+            ovmf: false,
+            network_interfaces: vec![CreateNetworkInterface {
+                id: "net0".to_string(),
+                network: None,
+                vlan: None,
+                options: Default::default(),
+                mac_address: None,
+            }],
+            drives: vec![],
+        }).await.unwrap();
+        let ifname = registry.get_network_interfaces_by_vm_id("vm").await.unwrap()[0].ifname.clone();
+        for (addr, family) in [
+            ("10.0.0.2", IpFamily::Ipv4),
+            ("fd00::2", IpFamily::Ipv6),
+            ("10.0.0.4", IpFamily::Ipv4),
+            ("fd00::3", IpFamily::Ipv6),
+            ("10.0.0.3", IpFamily::Ipv4),
+        ] {
+            registry.add_ip_address(address(&ifname, addr, family)).await.unwrap();
+        }
+
+        registry.remove_ipv4_address(&ifname, "10.0.0.2").await.unwrap();
+
+        let defaults: Vec<String> = registry.get_ip_addresses_by_ifname(&ifname).await.unwrap()
+            .into_iter()
+            .filter(|record| record.is_default)
+            .map(|record| record.address)
+            .collect();
+        assert_eq!(defaults.len(), 2);
+        assert!(defaults.contains(&"10.0.0.4".to_string()));
+        assert!(defaults.contains(&"fd00::2".to_string()));
+    }
+}
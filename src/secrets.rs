@@ -0,0 +1,87 @@
+use std::{io::{Read, Write}, os::unix::fs::{DirBuilderExt, OpenOptionsExt}, path::{Path, PathBuf}};
+
+/// Host-side store of drive encryption keys, one directory per VM.
+#[derive(Debug, Clone)]
+pub struct SecretStore {
+    base: PathBuf,
+}
+
+impl SecretStore {
+    pub fn new(base: impl AsRef<Path>) -> Self {
+        Self {
+            base: base.as_ref().to_path_buf(),
+        }
+    }
+
+    fn path_for_vm(&self, vm_id: &str) -> PathBuf {
+        self.base.join(vm_id)
+    }
+
+    pub fn key_path(&self, vm_id: &str, drive_id: &str) -> PathBuf {
+        self.path_for_vm(vm_id).join(drive_id).with_added_extension("key")
+    }
+
+    /// Path of the key staged by [`SecretStore::stage_key`] during rotation.
+    pub fn staged_key_path(&self, vm_id: &str, drive_id: &str) -> PathBuf {
+        self.path_for_vm(vm_id).join(drive_id).with_added_extension("key.new")
+    }
+
+    pub fn has_key(&self, vm_id: &str, drive_id: &str) -> bool {
+        self.key_path(vm_id, drive_id).exists()
+    }
+
+    fn write_random_key(&self, vm_id: &str, path: &Path) -> Result<(), crate::Error> {
+        std::fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(self.path_for_vm(vm_id))?;
+        let mut bytes = [0u8; 32];
+        std::fs::File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+        let key = bytes.iter().map(|b| format!("{:02x}", b)).collect::<String>();
+        std::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(path)?
+            .write_all(key.as_bytes())?;
+        Ok(())
+    }
+
+    pub fn create_key(&self, vm_id: &str, drive_id: &str) -> Result<PathBuf, crate::Error> {
+        let path = self.key_path(vm_id, drive_id);
+        self.write_random_key(vm_id, &path)?;
+        log::debug!("Created key for drive {} of VM {}", drive_id, vm_id);
+        Ok(path)
+    }
+
+    pub fn stage_key(&self, vm_id: &str, drive_id: &str) -> Result<PathBuf, crate::Error> {
+        let path = self.staged_key_path(vm_id, drive_id);
+        self.write_random_key(vm_id, &path)?;
+        Ok(path)
+    }
+
+    /// Replaces the current key with the staged one once the image has been rewrapped.
+    pub fn commit_staged_key(&self, vm_id: &str, drive_id: &str) -> Result<(), crate::Error> {
+        std::fs::rename(self.staged_key_path(vm_id, drive_id), self.key_path(vm_id, drive_id))?;
+        log::debug!("Rotated key for drive {} of VM {}", drive_id, vm_id);
+        Ok(())
+    }
+
+    pub fn copy_key(&self, source_vm_id: &str, target_vm_id: &str, drive_id: &str) -> Result<(), crate::Error> {
+        std::fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(self.path_for_vm(target_vm_id))?;
+        std::fs::copy(self.key_path(source_vm_id, drive_id), self.key_path(target_vm_id, drive_id))?;
+        Ok(())
+    }
+
+    pub fn delete_vm(&self, vm_id: &str) -> Result<(), crate::Error> {
+        let path = self.path_for_vm(vm_id);
+        if path.exists() {
+            std::fs::remove_dir_all(path)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,187 @@
+use std::net::IpAddr;
+
+use nft::{batch::Batch, types::{Chain, ChainType, Field, Hook, Op, Policy, Table, Value}};
+
+use crate::{context::YaveContext, net::NetworkManager, registry::{AddSecurityGroupRule, RuleDirection, RuleProtocol, SecurityGroupRecord, SecurityGroupRuleRecord}};
+
+pub struct SecurityGroupManager<'ctx> {
+    context: &'ctx YaveContext,
+}
+
+impl<'ctx> SecurityGroupManager<'ctx> {
+    pub fn new(context: &'ctx YaveContext) -> Self {
+        Self { context }
+    }
+
+    pub async fn create_group(&self, name: &str, description: Option<&str>) -> Result<SecurityGroupRecord, crate::Error> {
+        self.context.registry().create_security_group(name, description).await
+    }
+
+    /// A group and its rules.
+    pub async fn get_group(&self, name: &str) -> Result<(SecurityGroupRecord, Vec<SecurityGroupRuleRecord>), crate::Error> {
+        let registry = self.context.registry();
+        let group = registry.get_security_group(name).await?;
+        let rules = registry.get_security_group_rules(name).await?;
+        Ok((group, rules))
+    }
+
+    pub async fn delete_group(&self, name: &str) -> Result<(), crate::Error> {
+        let registry = self.context.registry();
+        let group = registry.get_security_group(name).await?;
+        if !registry.get_network_interfaces_by_security_group(&group.name).await?.is_empty() {
+            return Err(crate::Error::SecurityGroupInUse(group.name));
+        }
+        registry.delete_security_group(&group.name).await
+    }
+
+    pub async fn add_rule(&self, name: &str, rule: AddSecurityGroupRule) -> Result<SecurityGroupRuleRecord, crate::Error> {
+        validate_rule(&rule)?;
+        let registry = self.context.registry();
+        let group = registry.get_security_group(name).await?;
+        let record = registry.add_security_group_rule(&group.name, rule).await?;
+        self.refresh(&group.name).await?;
+        Ok(record)
+    }
+
+    pub async fn delete_rule(&self, name: &str, rule_id: i64) -> Result<(), crate::Error> {
+        let registry = self.context.registry();
+        let group = registry.get_security_group(name).await?;
+        if !registry.delete_security_group_rule(&group.name, rule_id).await? {
+            return Err(crate::Error::InvalidSecurityGroupRule(format!("no rule {} in group {}", rule_id, group.name)));
+        }
+        self.refresh(&group.name).await
+    }
+
+    pub async fn attach(&self, ifname: &str, name: &str) -> Result<(), crate::Error> {
+        let registry = self.context.registry();
+        let group = registry.get_security_group(name).await?;
+        let nic = registry.get_network_interface_by_ifname(ifname).await?;
+        if !NetworkManager::new(self.context).filtered(nic.network.as_deref()).await? {
+            return Err(crate::Error::FilteringUnsupported(format!("{} is on an unfiltered network", nic.ifname)));
+        }
+        registry.attach_security_group(&nic.ifname, &group.name).await?;
+        if nic.attached {
+            NetworkManager::new(self.context).apply_interface_rules(&nic.ifname).await?;
+        }
+        Ok(())
+    }
+
+    pub async fn detach(&self, ifname: &str, name: &str) -> Result<(), crate::Error> {
+        let registry = self.context.registry();
+        let nic = registry.get_network_interface_by_ifname(ifname).await?;
+        registry.detach_security_group(&nic.ifname, name).await?;
+        if nic.attached {
+            NetworkManager::new(self.context).apply_interface_rules(&nic.ifname).await?;
+        }
+        Ok(())
+    }
+
+    /// Recompiles the rules of every running interface the group is attached to.
+    async fn refresh(&self, name: &str) -> Result<(), crate::Error> {
+        let nm = NetworkManager::new(self.context);
+        for nic in self.context.registry().get_network_interfaces_by_security_group(name).await? {
+            if nic.attached {
+                nm.apply_interface_rules(&nic.ifname).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn validate_rule(rule: &AddSecurityGroupRule) -> Result<(), crate::Error> {
+    let ports = rule.port_from.is_some() || rule.port_to.is_some();
+    if ports && !matches!(rule.protocol, RuleProtocol::Tcp | RuleProtocol::Udp) {
+        return Err(crate::Error::InvalidSecurityGroupRule("ports require tcp or udp".to_string()));
+    }
+    if let (Some(from), Some(to)) = (rule.port_from, rule.port_to)
+        && from > to
+    {
+        return Err(crate::Error::InvalidSecurityGroupRule(format!("port range {}-{} is empty", from, to)));
+    }
+    if let Some(cidr) = &rule.cidr
+        && parse_cidr(cidr).is_none()
+    {
+        return Err(crate::Error::InvalidSecurityGroupRule(format!("invalid CIDR {}", cidr)));
+    }
+    Ok(())
+}
+
+fn parse_cidr(cidr: &str) -> Option<(IpAddr, Option<u8>)> {
+    match cidr.split_once('/') {
+        Some((address, len)) => {
+            let address: IpAddr = address.parse().ok()?;
+            let len: u8 = len.parse().ok()?;
+            let max = if address.is_ipv4() { 32 } else { 128 };
+            (len <= max).then_some((address, Some(len)))
+        },
+        None => Some((cidr.parse().ok()?, None)),
+    }
+}
+
+/// Compiles the security groups of a TAP into two stateful chains of its bridge table:
+/// `egress`, reached from `guest` for frames the VM sends, and `ingress` for frames
+/// leaving through the TAP. Replies, ARP, neighbour discovery and DHCP are always
+/// allowed; other IP traffic needs a matching rule of any group and is dropped otherwise,
+/// as are all other ethertypes, VLAN-tagged frames included. Interfaces without groups get no chains and are not filtered.
+pub(crate) fn compile(table: &Table, ifname: &str, guest: &Chain, rules: &[SecurityGroupRuleRecord]) -> Batch {
+    let postrouting = table.base_chain("postrouting", ChainType::Filter, Hook::Postrouting, 0)
+        .policy(Policy::Accept);
+    let egress = table.chain("egress");
+    let ingress = table.chain("ingress");
+
+    let mut batch = Batch::new();
+    batch
+        .add(egress.clone())
+        .add(ingress.clone())
+        .add(postrouting.clone())
+        .add(guest.rule().jump(&egress.name))
+        .add(postrouting.rule().matches(Field::meta("oifname"), ifname).jump(&ingress.name));
+
+    for (chain, dhcp_port) in [(&egress, Value::set([67u16, 547])), (&ingress, Value::set([68u16, 546]))] {
+        batch
+            .add(chain.rule()
+                .matches_op(Field::ct("state"), Op::In, Value::set(["established", "related"]))
+                .accept())
+            .add(chain.rule().matches(Field::payload("ether", "type"), "arp").accept())
+            .add(chain.rule().not_matches(Field::payload("ether", "type"), Value::set(["ip", "ip6"])).drop())
+            .add(chain.rule()
+                .matches(Field::payload("icmpv6", "type"), Value::set(["nd-router-solicit", "nd-router-advert", "nd-neighbor-solicit", "nd-neighbor-advert"]))
+                .accept())
+            .add(chain.rule().matches(Field::payload("udp", "dport"), dhcp_port).accept());
+    }
+
+    for rule in rules {
+        let (chain, remote) = match rule.direction {
+            RuleDirection::Egress => (&egress, "daddr"),
+            RuleDirection::Ingress => (&ingress, "saddr"),
+        };
+        let mut compiled = chain.rule().comment(&format!("{}:{}", rule.group_name, rule.id));
+        if let Some((address, len)) = rule.cidr.as_deref().and_then(parse_cidr) {
+            let protocol = if address.is_ipv4() { "ip" } else { "ip6" };
+            let value = match len {
+                Some(len) => Value::prefix(&address.to_string(), len),
+                None => Value::from(address),
+            };
+            compiled = compiled.matches(Field::payload(protocol, remote), value);
+        }
+        compiled = match rule.protocol {
+            RuleProtocol::Any => compiled,
+            RuleProtocol::Icmp => compiled.matches(Field::meta("l4proto"), Value::set(["icmp", "ipv6-icmp"])),
+            RuleProtocol::Tcp | RuleProtocol::Udp => {
+                let protocol = if rule.protocol == RuleProtocol::Tcp { "tcp" } else { "udp" };
+                let compiled = compiled.matches(Field::meta("l4proto"), protocol);
+                match (rule.port_from, rule.port_to) {
+                    (Some(from), Some(to)) if from != to => compiled.matches(Field::payload(protocol, "dport"), Value::range(from, to)),
+                    (Some(port), _) | (None, Some(port)) => compiled.matches(Field::payload(protocol, "dport"), port),
+                    (None, None) => compiled,
+                }
+            },
+        };
+        batch.add(compiled.accept());
+    }
+
+    batch
+        .add(egress.rule().drop())
+        .add(ingress.rule().drop());
+    batch
+}
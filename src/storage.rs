@@ -1,21 +1,30 @@
-use std::path::PathBuf;
+use std::{os::unix::fs::PermissionsExt, path::{Path, PathBuf}};
 
-use qemu::Img;
+use qemu::{Img, base::ImgFormat};
+use vm_types::vm::DriveFormat;
+
+use crate::{registry::DriveRecord, secrets::SecretStore};
+
+const KEY_SECRET_ID: &str = "key0";
+const NEW_KEY_SECRET_ID: &str = "key1";
 
 pub struct VmStorage {
     base: PathBuf,
     qemu_img: PathBuf,
+    secrets: SecretStore,
 }
 
 pub enum DriveInstallMode {
     New {
         id: String,
         size: u64,
+        encrypted: bool,
     },
     Existing {
         id: String,
         resize: u64,
         image: String,
+        encrypted: bool,
     },
 }
 
@@ -28,9 +37,14 @@ impl VmStorage {
         Self {
             base: base.as_ref().to_path_buf(),
             qemu_img: qemu_img.as_ref().to_path_buf(),
+            secrets: SecretStore::new(base.as_ref().join("secrets")),
         }
     }
 
+    pub fn secrets(&self) -> &SecretStore {
+        &self.secrets
+    }
+
     pub fn path_for_vm(&self, vm_id: &str) -> std::path::PathBuf {
         self.base.join(format!("{}.vm", vm_id))
     }
@@ -39,23 +53,145 @@ impl VmStorage {
         std::fs::create_dir_all(&self.base)
     }
 
-    async fn create_drive_image(&self, path: &PathBuf, size: u64) -> Result<(), crate::Error> {
-        let args = Img::new(&self.qemu_img.to_string_lossy())
-            .create(qemu::base::ImgFormat::Raw, &path.to_string_lossy(), size)
-            .build();
+    async fn run_img(&self, args: Vec<String>) -> Result<(), crate::Error> {
         let mut command = tokio::process::Command::new(&args[0]);
         command.args(&args[1..]);
-        command.status().await?;
+        let status = command.status().await?;
+        if !status.success() {
+            return Err(crate::Error::Command(format!("{} {} exited with {}", args[0], args[1], status)));
+        }
         Ok(())
     }
 
-    async fn resize_drive_image(&self, path: &PathBuf, size: u64) -> Result<(), crate::Error> {
+    async fn create_drive_image(&self, path: &Path, size: u64) -> Result<(), crate::Error> {
+        let args = Img::new(&self.qemu_img.to_string_lossy())
+            .create(ImgFormat::Raw, &path.to_string_lossy(), size)
+            .build();
+        self.run_img(args).await
+    }
+
+    async fn resize_drive_image(&self, path: &Path, size: u64) -> Result<(), crate::Error> {
         let args = Img::new(&self.qemu_img.to_string_lossy())
             .resize(&path.to_string_lossy(), size)
             .build();
-        let mut command = tokio::process::Command::new(&args[0]);
-        command.args(&args[1..]);
-        command.status().await?;
+        self.run_img(args).await
+    }
+
+    async fn shrink_drive_image(&self, path: &Path, size: u64) -> Result<(), crate::Error> {
+        let args = Img::new(&self.qemu_img.to_string_lossy())
+            .shrink(&path.to_string_lossy(), size)
+            .build();
+        self.run_img(args).await
+    }
+
+    async fn create_encrypted_drive_image(&self, path: &Path, size: u64, key: &Path) -> Result<(), crate::Error> {
+        let args = Img::new(&self.qemu_img.to_string_lossy())
+            .create_encrypted(&path.to_string_lossy(), size, KEY_SECRET_ID, &key.to_string_lossy())
+            .build();
+        self.run_img(args).await
+    }
+
+    async fn convert_encrypted_drive_image(&self, src: &Path, dest: &Path, key: &Path) -> Result<(), crate::Error> {
+        let args = Img::new(&self.qemu_img.to_string_lossy())
+            .convert_encrypted(&src.to_string_lossy(), &dest.to_string_lossy(), KEY_SECRET_ID, &key.to_string_lossy())
+            .build();
+        self.run_img(args).await
+    }
+
+    async fn resize_encrypted_drive_image(&self, path: &Path, size: u64, shrink: bool, key: &Path) -> Result<(), crate::Error> {
+        let args = Img::new(&self.qemu_img.to_string_lossy())
+            .resize_encrypted(&path.to_string_lossy(), size, shrink, KEY_SECRET_ID, &key.to_string_lossy())
+            .build();
+        self.run_img(args).await
+    }
+
+    /// Memory state of a saved VM, restored on the next resumed start.
+    pub fn state_path(&self, vm_id: &str) -> PathBuf {
+        self.path_for_vm(vm_id).join("state")
+    }
+
+    /// Deletes the state saved by a suspend, the VM boots afresh on its next start.
+    pub fn discard_state(&self, vm_id: &str) -> Result<(), crate::Error> {
+        let path = self.state_path(vm_id);
+        if path.exists() {
+            std::fs::remove_file(&path)?;
+            log::info!("Discarded saved state of VM {}", vm_id);
+        }
+        Ok(())
+    }
+
+    /// Runtime files of a managed network, such as its DHCP server configuration.
+    pub fn network_path(&self, network: &str) -> PathBuf {
+        self.base.join("networks").join(network)
+    }
+
+    pub fn drive_path(&self, vm_id: &str, drive_id: &str) -> PathBuf {
+        self.path_for_vm(vm_id).join(drive_id).with_added_extension("img")
+    }
+
+    async fn image_info(&self, path: &Path) -> Result<serde_json::Value, crate::Error> {
+        let args = Img::new(&self.qemu_img.to_string_lossy())
+            .info(&path.to_string_lossy())
+            .build();
+        let output = tokio::process::Command::new(&args[0])
+            .args(&args[1..])
+            .output()
+            .await?;
+        if !output.status.success() {
+            return Err(crate::Error::Command(String::from_utf8_lossy(&output.stderr).to_string()));
+        }
+        Ok(serde_json::from_slice(&output.stdout)?)
+    }
+
+    /// Virtual size of the drive image in megabytes, as reported by `qemu-img info`.
+    pub async fn drive_size(&self, vm_id: &str, drive_id: &str) -> Result<u64, crate::Error> {
+        let path = self.drive_path(vm_id, drive_id);
+        if !path.exists() {
+            return Err(crate::Error::DriveNotFound);
+        }
+        let info = self.image_info(&path).await?;
+        let bytes = info["virtual-size"].as_u64()
+            .ok_or(crate::Error::Command("qemu-img info returned no virtual-size".to_string()))?;
+        Ok(bytes / (1024 * 1024))
+    }
+
+    pub async fn resize_drive(&self, vm_id: &str, drive_id: &str, size: u64, shrink: bool) -> Result<(), crate::Error> {
+        let path = self.drive_path(vm_id, drive_id);
+        if !path.exists() {
+            return Err(crate::Error::DriveNotFound);
+        }
+        if self.secrets.has_key(vm_id, drive_id) {
+            let key = self.secrets.key_path(vm_id, drive_id);
+            self.resize_encrypted_drive_image(&path, size, shrink, &key).await?;
+        } else if shrink {
+            self.shrink_drive_image(&path, size).await?;
+        } else {
+            self.resize_drive_image(&path, size).await?;
+        }
+        log::debug!("Resized drive image at {:?} to {}", path, size);
+        Ok(())
+    }
+
+    /// Rewraps an encrypted drive with a freshly generated key. The VM must be stopped.
+    pub async fn rotate_drive_key(&self, vm_id: &str, drive_id: &str) -> Result<(), crate::Error> {
+        let path = self.drive_path(vm_id, drive_id);
+        if !path.exists() {
+            return Err(crate::Error::DriveNotFound);
+        }
+        if !self.secrets.has_key(vm_id, drive_id) {
+            return Err(crate::Error::DriveNotEncrypted(drive_id.to_string()));
+        }
+        let old_key = self.secrets.key_path(vm_id, drive_id).to_string_lossy().to_string();
+        let new_key = self.secrets.stage_key(vm_id, drive_id)?.to_string_lossy().to_string();
+        let args = Img::new(&self.qemu_img.to_string_lossy())
+            .add_key(&path.to_string_lossy(), (KEY_SECRET_ID, &old_key), (NEW_KEY_SECRET_ID, &new_key))
+            .build();
+        self.run_img(args).await?;
+        let args = Img::new(&self.qemu_img.to_string_lossy())
+            .remove_key(&path.to_string_lossy(), (KEY_SECRET_ID, &old_key), (NEW_KEY_SECRET_ID, &new_key))
+            .build();
+        self.run_img(args).await?;
+        self.secrets.commit_staged_key(vm_id, drive_id)?;
         Ok(())
     }
 
@@ -63,18 +199,213 @@ impl VmStorage {
         self.base.join(image).with_added_extension("img")
     }
 
+    /// Writes a compressed, unencrypted qcow2 copy of a drive to `dest`. The VM must be stopped.
+    pub async fn export_drive(&self, vm_id: &str, drive_id: &str, dest: &Path) -> Result<(), crate::Error> {
+        let path = self.drive_path(vm_id, drive_id);
+        if !path.exists() {
+            return Err(crate::Error::DriveNotFound);
+        }
+        let key = self.secrets.has_key(vm_id, drive_id)
+            .then(|| self.secrets.key_path(vm_id, drive_id).to_string_lossy().to_string());
+        let args = Img::new(&self.qemu_img.to_string_lossy())
+            .convert_compressed(&path.to_string_lossy(), &dest.to_string_lossy(), key.as_deref().map(|key| (KEY_SECRET_ID, key)))
+            .build();
+        self.run_img(args).await?;
+        log::debug!("Exported drive image at {:?} to {:?}", path, dest);
+        Ok(())
+    }
+
+    async fn convert_drive_image(&self, src: &Path, dest: &Path, format: Option<DriveFormat>) -> Result<(), crate::Error> {
+        let format = match format {
+            Some(DriveFormat::Raw) => ImgFormat::Raw,
+            Some(DriveFormat::Qcow2) | None => ImgFormat::Qcow2,
+        };
+        let args = Img::new(&self.qemu_img.to_string_lossy())
+            .convert(format, &src.to_string_lossy(), &dest.to_string_lossy())
+            .build();
+        self.run_img(args).await
+    }
+
+    /// Creates a drive from an exported image, encrypting it under a new key if requested.
+    pub async fn import_drive(&self, vm_id: &str, drive_id: &str, src: &Path, format: Option<DriveFormat>, encrypted: bool) -> Result<(), crate::Error> {
+        std::fs::create_dir_all(self.path_for_vm(vm_id))?;
+        let path = self.drive_path(vm_id, drive_id);
+        if encrypted {
+            let key = self.secrets.create_key(vm_id, drive_id)?;
+            self.convert_encrypted_drive_image(src, &path, &key).await?;
+        } else {
+            self.convert_drive_image(src, &path, format).await?;
+        }
+        log::debug!("Imported drive image {:?} to {:?}", src, path);
+        Ok(())
+    }
+
+    /// Replaces the contents of an existing drive with `src`, keeping the old image and key
+    /// until the copy has completed. The VM must be stopped.
+    pub async fn restore_drive(&self, vm_id: &str, drive_id: &str, src: &Path, format: Option<DriveFormat>, encrypted: bool) -> Result<(), crate::Error> {
+        let path = self.drive_path(vm_id, drive_id);
+        let staged = path.with_added_extension("restore");
+        if encrypted {
+            let key = self.secrets.stage_key(vm_id, drive_id)?;
+            self.convert_encrypted_drive_image(src, &staged, &key).await?;
+            std::fs::rename(&staged, &path)?;
+            self.secrets.commit_staged_key(vm_id, drive_id)?;
+        } else {
+            self.convert_drive_image(src, &staged, format).await?;
+            std::fs::rename(&staged, &path)?;
+        }
+        log::debug!("Restored drive image {:?} from {:?}", path, src);
+        Ok(())
+    }
+
+    /// Creates an empty image for a drive defined in the registry, e.g. as the target of a block migration.
+    pub async fn create_empty_drive(&self, vm_id: &str, drive_id: &str, size: u64, format: Option<DriveFormat>, encrypted: bool) -> Result<(), crate::Error> {
+        std::fs::create_dir_all(self.path_for_vm(vm_id))?;
+        let path = self.drive_path(vm_id, drive_id);
+        if encrypted {
+            let key = self.secrets.create_key(vm_id, drive_id)?;
+            return self.create_encrypted_drive_image(&path, size, &key).await;
+        }
+        let format = match format {
+            Some(DriveFormat::Raw) => ImgFormat::Raw,
+            Some(DriveFormat::Qcow2) | None => ImgFormat::Qcow2,
+        };
+        let args = Img::new(&self.qemu_img.to_string_lossy())
+            .create(format, &path.to_string_lossy(), size)
+            .build();
+        self.run_img(args).await
+    }
+
+    /// Creates an empty qcow2 overlay of `backing`, `size` is in bytes.
+    pub async fn create_overlay(&self, path: &Path, backing: &Path, backing_format: ImgFormat, size: u64) -> Result<(), crate::Error> {
+        let args = Img::new(&self.qemu_img.to_string_lossy())
+            .create_overlay(&path.to_string_lossy(), &backing.to_string_lossy(), backing_format, size)
+            .build();
+        self.run_img(args).await
+    }
+
+    /// Read-only image in the shared `bases` directory that linked clones of a drive are backed by.
+    fn frozen_base_path(&self, vm_id: &str, drive_id: &str) -> PathBuf {
+        let stamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_millis())
+            .unwrap_or(0);
+        self.base.join("bases").join(format!("{}-{}-{}", vm_id, drive_id, stamp)).with_added_extension("img")
+    }
+
+    /// Freezes the current image of a drive into a shared base and turns the drive of both the
+    /// source and the clone into qcow2 overlays of it. Returns the base, the source image is
+    /// moved back if the overlays cannot be created.
+    async fn link_drive(&self, source_vm_id: &str, target_vm_id: &str, drive_id: &str) -> Result<PathBuf, crate::Error> {
+        let path = self.drive_path(source_vm_id, drive_id);
+        let info = self.image_info(&path).await?;
+        let format = match info["format"].as_str() {
+            Some("raw") => ImgFormat::Raw,
+            _ => ImgFormat::Qcow2,
+        };
+        let size = info["virtual-size"].as_u64()
+            .ok_or(crate::Error::Command("qemu-img info returned no virtual-size".to_string()))?;
+        let base = self.frozen_base_path(source_vm_id, drive_id);
+        std::fs::create_dir_all(self.base.join("bases"))?;
+        std::fs::rename(&path, &base)?;
+        let result = async {
+            std::fs::set_permissions(&base, std::fs::Permissions::from_mode(0o444))?;
+            self.create_overlay(&path, &base, format, size).await?;
+            self.create_overlay(&self.drive_path(target_vm_id, drive_id), &base, format, size).await
+        }.await;
+        if let Err(err) = result {
+            self.unlink_drive(source_vm_id, target_vm_id, drive_id, &base)?;
+            return Err(err);
+        }
+        log::debug!("Linked drive {} of VM {} to frozen base {:?}", drive_id, target_vm_id, base);
+        Ok(base)
+    }
+
+    /// Undoes [`VmStorage::link_drive`]: drops the overlays and moves the base back as the source image.
+    fn unlink_drive(&self, source_vm_id: &str, target_vm_id: &str, drive_id: &str, base: &Path) -> Result<(), crate::Error> {
+        let path = self.drive_path(source_vm_id, drive_id);
+        for overlay in [&path, &self.drive_path(target_vm_id, drive_id)] {
+            if overlay.exists() {
+                std::fs::remove_file(overlay)?;
+            }
+        }
+        std::fs::set_permissions(base, std::fs::Permissions::from_mode(0o644))?;
+        std::fs::rename(base, &path)?;
+        log::debug!("Moved frozen base {:?} back to drive {} of VM {}", base, drive_id, source_vm_id);
+        Ok(())
+    }
+
+    /// Creates the drive images of a clone. Full clones copy the images (and keys of encrypted
+    /// drives), linked clones share a frozen base with the source. The source must be stopped.
+    /// Returns the frozen bases of linked drives by drive id. On failure the source drives are
+    /// restored and the files of the clone removed.
+    pub async fn clone_vm(&self, source_vm_id: &str, target_vm_id: &str, drives: &[DriveRecord], linked: bool) -> Result<Vec<(String, PathBuf)>, crate::Error> {
+        let drives = drives.iter().filter(|drive| !drive.is_cdrom()).collect::<Vec<_>>();
+        if let Some(drive) = drives.iter().find(|drive| linked && drive.encrypted) {
+            return Err(crate::Error::LinkedCloneEncrypted(drive.id.clone()));
+        }
+        let mut bases = vec![];
+        let result = async {
+            std::fs::create_dir_all(self.path_for_vm(target_vm_id))?;
+            for drive in drives {
+                if linked {
+                    bases.push((drive.id.clone(), self.link_drive(source_vm_id, target_vm_id, &drive.id).await?));
+                    continue;
+                }
+                std::fs::copy(self.drive_path(source_vm_id, &drive.id), self.drive_path(target_vm_id, &drive.id))?;
+                if drive.encrypted {
+                    self.secrets.copy_key(source_vm_id, target_vm_id, &drive.id)?;
+                }
+                log::debug!("Copied drive {} of VM {} to VM {}", drive.id, source_vm_id, target_vm_id);
+            }
+            Ok(())
+        }.await;
+        if let Err(err) = result {
+            self.unclone_vm(source_vm_id, target_vm_id, &bases).await?;
+            return Err(err);
+        }
+        Ok(bases)
+    }
+
+    /// Undoes [`VmStorage::clone_vm`] given the frozen bases it returned.
+    pub async fn unclone_vm(&self, source_vm_id: &str, target_vm_id: &str, bases: &[(String, PathBuf)]) -> Result<(), crate::Error> {
+        for (drive_id, base) in bases {
+            self.unlink_drive(source_vm_id, target_vm_id, drive_id, base)?;
+        }
+        self.delete_vm(target_vm_id).await
+    }
+
+    /// Merges the backing chain of a qcow2 overlay into it.
+    pub async fn flatten_image(&self, path: &Path) -> Result<(), crate::Error> {
+        let args = Img::new(&self.qemu_img.to_string_lossy())
+            .flatten(&path.to_string_lossy())
+            .build();
+        self.run_img(args).await
+    }
+
+    /// Path of an ISO from the image library.
+    pub fn iso_path(&self, iso: &str) -> PathBuf {
+        self.base.join(iso).with_added_extension("iso")
+    }
+
     pub async fn install_vm(&self, vm_id: &str, options: &InstallOptions) -> Result<(), crate::Error> {
         let vm_path = self.path_for_vm(vm_id);
         std::fs::create_dir_all(&vm_path)?;
         for drive in options.drives.iter() {
             match drive {
-                DriveInstallMode::New { id, size } => {
-                    let drive_path = vm_path.join(id).with_added_extension("img");
+                DriveInstallMode::New { id, size, encrypted: false } => {
+                    let drive_path = self.drive_path(vm_id, id);
                     self.create_drive_image(&drive_path, *size).await?;
                     log::debug!("Created new drive image at {:?}", drive_path);
                 }
-                DriveInstallMode::Existing { id, resize, image } => {
-                    let drive_path = vm_path.join(id).with_added_extension("img");
+                DriveInstallMode::New { id, size, encrypted: true } => {
+                    let drive_path = self.drive_path(vm_id, id);
+                    let key = self.secrets.create_key(vm_id, id)?;
+                    self.create_encrypted_drive_image(&drive_path, *size, &key).await?;
+                    log::debug!("Created new encrypted drive image at {:?}", drive_path);
+                }
+                DriveInstallMode::Existing { id, resize, image, encrypted: false } => {
+                    let drive_path = self.drive_path(vm_id, id);
                     std::fs::copy(self.get_image_path(image), &drive_path)?;
                     log::debug!("Copied existing drive image to {:?}", drive_path);
                     if *resize > 0 {
@@ -82,6 +413,16 @@ impl VmStorage {
                         log::debug!("Resized drive image at {:?} to {}", drive_path, resize);
                     }
                 }
+                DriveInstallMode::Existing { id, resize, image, encrypted: true } => {
+                    let drive_path = self.drive_path(vm_id, id);
+                    let key = self.secrets.create_key(vm_id, id)?;
+                    self.convert_encrypted_drive_image(&self.get_image_path(image), &drive_path, &key).await?;
+                    log::debug!("Converted existing drive image to encrypted {:?}", drive_path);
+                    if *resize > 0 {
+                        self.resize_encrypted_drive_image(&drive_path, *resize, false, &key).await?;
+                        log::debug!("Resized drive image at {:?} to {}", drive_path, resize);
+                    }
+                }
             }
         }
         Ok(())
@@ -92,6 +433,7 @@ impl VmStorage {
         if vm_path.exists() {
             tokio::fs::remove_dir_all(vm_path).await?;
         }
+        self.secrets.delete_vm(vm_id)?;
         Ok(())
     }
 }
@@ -0,0 +1,107 @@
+use async_trait::async_trait;
+use tokio::process::Command;
+use vm_types::{Config, NetworkDefinition, SwitchBackend};
+
+/// Software switch the TAP interfaces of VMs are plugged into.
+#[async_trait]
+pub trait Switch: Send + Sync {
+    /// Adds `ifname` as a port of `bridge`, as an untagged member of `vlan` if set.
+    async fn attach(&self, ifname: &str, bridge: &str, vlan: Option<u16>) -> Result<(), crate::Error>;
+    /// Removes `ifname` from `bridge`.
+    async fn detach(&self, ifname: &str, bridge: &str) -> Result<(), crate::Error>;
+    /// Whether frames between its ports pass the nftables bridge hooks, which the
+    /// anti-spoofing and security group rules are installed in.
+    fn filterable(&self) -> bool;
+}
+
+/// Switch implementing the backend of `network`.
+pub fn switch(config: &Config, network: &NetworkDefinition) -> Box<dyn Switch> {
+    match network.backend {
+        SwitchBackend::Bridge => Box::new(LinuxBridge::new(network.uplink.as_deref())),
+        SwitchBackend::Ovs => Box::new(OpenVSwitch::new(&config.cli.ovs_vsctl)),
+    }
+}
+
+pub struct LinuxBridge {
+    uplink: Option<String>,
+}
+
+impl LinuxBridge {
+    pub fn new(uplink: Option<&str>) -> Self {
+        Self { uplink: uplink.map(str::to_string) }
+    }
+}
+
+#[async_trait]
+impl Switch for LinuxBridge {
+    /// With VLAN filtering on, the VLAN also has to be a tagged member of the uplink port to
+    /// leave the host and of the bridge itself to reach the host.
+    async fn attach(&self, ifname: &str, bridge: &str, vlan: Option<u16>) -> Result<(), crate::Error> {
+        crate::interface::set_master(ifname, bridge).await?;
+        if let Some(vlan) = vlan {
+            crate::interface::set_vlan_filtering(bridge).await?;
+            crate::interface::set_port_vlan(ifname, vlan).await?;
+            if let Some(uplink) = &self.uplink {
+                crate::interface::add_tagged_vlan(uplink, vlan, false).await?;
+            }
+            crate::interface::add_tagged_vlan(bridge, vlan, true).await?;
+        }
+        Ok(())
+    }
+
+    async fn detach(&self, ifname: &str, _bridge: &str) -> Result<(), crate::Error> {
+        crate::interface::set_nomaster(ifname).await?;
+        Ok(())
+    }
+
+    fn filterable(&self) -> bool {
+        true
+    }
+}
+
+pub struct OpenVSwitch {
+    bin: String,
+}
+
+impl OpenVSwitch {
+    pub fn new(bin: &str) -> Self {
+        Self { bin: bin.to_string() }
+    }
+
+    async fn vsctl(&self, args: &[String]) -> Result<(), crate::Error> {
+        let status = Command::new(&self.bin)
+            .args(args)
+            .status()
+            .await?;
+        if !status.success() {
+            return Err(crate::Error::Command(format!("{} exited with {}", self.bin, status)));
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Switch for OpenVSwitch {
+    /// Replaces any port of the same name, so re-attaching moves the TAP to `bridge` and `vlan`.
+    async fn attach(&self, ifname: &str, bridge: &str, vlan: Option<u16>) -> Result<(), crate::Error> {
+        let mut args = vec![
+            "--if-exists".to_string(), "del-port".to_string(), ifname.to_string(),
+            "--".to_string(), "add-port".to_string(), bridge.to_string(), ifname.to_string(),
+        ];
+        if let Some(vlan) = vlan {
+            args.push(format!("tag={}", vlan));
+        }
+        self.vsctl(&args).await
+    }
+
+    async fn detach(&self, ifname: &str, bridge: &str) -> Result<(), crate::Error> {
+        self.vsctl(&[
+            "--if-exists".to_string(), "del-port".to_string(), bridge.to_string(), ifname.to_string(),
+        ]).await
+    }
+
+    /// OVS forwards in its datapath, past the bridge netfilter hooks.
+    fn filterable(&self) -> bool {
+        false
+    }
+}
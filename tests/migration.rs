@@ -0,0 +1,79 @@
+//! Loopback block migration between two yave instances on this host, each with its own
+//! registry, storage and run directory. Needs KVM, QEMU and qemu-img as configured in
+//! `debug/config.toml`: `cargo test --test migration -- --ignored`.
+
+use std::path::Path;
+
+use vm_types::vm::{DriveBus, DriveFormat, DriveOptions};
+use yave::{builders::VmLaunchRequestBuilder, context::YaveContext, migration::{MigrationManager, MigrationTarget}, registry::{CreateDrive, CreateVirtualMachine}};
+
+const VM_ID: &str = "migrant";
+const MIGRATION_PORT: u16 = 47444;
+const NBD_PORT: u16 = 47445;
+
+async fn instance(dir: &Path) -> YaveContext {
+    let config = Path::new(env!("CARGO_MANIFEST_DIR")).join("debug").join("config.toml");
+    let context = YaveContext::load(config, dir.join("storage"), dir.join("run")).await.expect("Error loading context");
+    context.registry().create_tables().await.expect("Error creating tables");
+    context
+}
+
+fn definition(id: &str) -> CreateVirtualMachine {
+    CreateVirtualMachine {
+        id: id.to_string(),
+        hostname: id.to_string(),
+        vcpu: 1,
+        memory: 256,
+        ovmf: false,
+        network_interfaces: vec![],
+        drives: vec![CreateDrive {
+            id: "drive0".to_string(),
+            drive_bus: DriveBus::VirtioBlk { boot_index: Some(1) },
+            size: 64,
+            options: DriveOptions {
+                format: Some(DriveFormat::Qcow2),
+                ..Default::default()
+            },
+            encrypted: false,
+            media: None,
+        }],
+    }
+}
+
+#[tokio::test]
+#[ignore = "needs KVM and QEMU"]
+async fn block_migration_over_loopback() {
+    let dir = tempfile::tempdir().expect("Error creating temporary directory");
+    let source = instance(&dir.path().join("source")).await;
+    let destination = instance(&dir.path().join("destination")).await;
+
+    source.registry().create_vm(definition(VM_ID)).await.expect("Error creating source VM");
+    source.storage().create_empty_drive(VM_ID, "drive0", 64, Some(DriveFormat::Qcow2), false).await.expect("Error creating drive");
+    // Both QEMUs listen for VNC on this host, a placeholder moves the destination to the next display.
+    destination.registry().create_vm(definition("placeholder")).await.expect("Error creating placeholder VM");
+    destination.registry().create_vm(definition(VM_ID)).await.expect("Error creating destination VM");
+
+    let source_request = VmLaunchRequestBuilder::new(&source).build(VM_ID).await.expect("Error building launch request");
+    source.runtime().run_vm(&source_request, false).await.expect("Error starting source VM");
+
+    let incoming = MigrationManager::new(&destination);
+    incoming.incoming(VM_ID, "127.0.0.1", MIGRATION_PORT, Some(NBD_PORT)).await.expect("Error preparing incoming migration");
+    let target = MigrationTarget {
+        host: "127.0.0.1".to_string(),
+        port: MIGRATION_PORT,
+        nbd_port: Some(NBD_PORT),
+        auto_converge: false,
+    };
+    let outgoing = MigrationManager::new(&source);
+    let (migrated, received) = tokio::join!(
+        outgoing.migrate(VM_ID, &target, |_| {}),
+        incoming.wait_incoming(VM_ID, true, |_| {}),
+    );
+    migrated.expect("Error migrating VM");
+    received.expect("Error receiving VM");
+
+    let destination_request = VmLaunchRequestBuilder::new(&destination).build(VM_ID).await.expect("Error building launch request");
+    assert!(!source.runtime().is_running(&source_request).await.expect("Error checking source VM"));
+    assert!(destination.runtime().is_running(&destination_request).await.expect("Error checking destination VM"));
+    destination.runtime().shutdown_vm(&destination_request).await.expect("Error shutting down destination VM");
+}
@@ -25,6 +25,12 @@ pub struct PowerState {
     pub condition: String,
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Growpart {
+    pub mode: String,
+    pub devices: Vec<String>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MatchInterface {
     pub macaddress: String,
@@ -48,6 +54,11 @@ pub struct EthernetConfig {
     pub addresses: Vec<String>,
     pub routes: Option<Vec<RouteConfig>>,
     pub nameservers: Option<Nameservers>,
+    /// Unset for interfaces without IPv6 addresses, leaving the guest default.
+    #[serde(rename = "accept-ra", default, skip_serializing_if = "Option::is_none")]
+    pub accept_ra: Option<bool>,
+    #[serde(rename = "ipv6-address-generation", default, skip_serializing_if = "Option::is_none")]
+    pub ipv6_address_generation: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -68,6 +79,8 @@ pub struct UserDataCloudInit {
     pub ssh_pwauth: bool,
     pub power_state: PowerState,
     pub disable_root: bool,
+    pub growpart: Growpart,
+    pub resize_rootfs: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -1,4 +1,4 @@
-use std::path::Path;
+use std::{collections::HashMap, path::Path};
 
 use serde::{Deserialize, Serialize};
 
@@ -49,12 +49,96 @@ pub struct Config {
     pub ovmf: OVMF,
     pub api: API,
     pub network: Network,
+    #[serde(default)]
+    pub backup: Backup,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Backup {
+    #[serde(default = "default_backup_path")]
+    pub path: String,
+    #[serde(default = "default_backup_retention")]
+    pub retention: u32,
+}
+
+impl Default for Backup {
+    fn default() -> Self {
+        Self {
+            path: default_backup_path(),
+            retention: default_backup_retention(),
+        }
+    }
+}
+
+fn default_backup_path() -> String {
+    "backups".to_string()
+}
+
+fn default_backup_retention() -> u32 {
+    7
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct Network {
     pub default_bridge: Option<String>,
     pub nameservers: Vec<String>,
+    /// Named networks VM interfaces can be attached to.
+    #[serde(default)]
+    pub networks: HashMap<String, NetworkDefinition>,
+    /// First three octets of generated MAC addresses, `52:54` followed by four generated
+    /// octets if unset.
+    #[serde(default)]
+    pub mac_oui: Option<String>,
+    /// Mixed into generated MAC addresses, must differ between hosts sharing a segment.
+    /// Defaults to `/etc/machine-id`.
+    #[serde(default)]
+    pub mac_seed: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct NetworkDefinition {
+    pub bridge: String,
+    /// VLAN id the interfaces are untagged members of.
+    pub vlan: Option<u16>,
+    pub mtu: Option<u32>,
+    /// Switch the bridge is implemented by.
+    #[serde(default)]
+    pub backend: SwitchBackend,
+    /// Bridge port leading off the host, e.g. a bond, carrying `vlan` and the VLANs of the
+    /// interfaces tagged.
+    #[serde(default)]
+    pub uplink: Option<String>,
+    /// Interfaces on the network get no anti-spoofing rules and no security groups. Required
+    /// for `ovs` networks, whose ports nftables does not see.
+    #[serde(default)]
+    pub unfiltered: bool,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SwitchBackend {
+    /// Linux bridge, VLANs through bridge VLAN filtering.
+    #[default]
+    Bridge,
+    /// Open vSwitch bridge managed with `ovs-vsctl`.
+    Ovs,
+}
+
+impl Network {
+    /// Definition of the network called `name`, `None` stands for the default bridge.
+    pub fn resolve(&self, name: Option<&str>) -> Option<NetworkDefinition> {
+        match name {
+            Some(name) => self.networks.get(name).cloned(),
+            None => self.default_bridge.as_ref().map(|bridge| NetworkDefinition {
+                bridge: bridge.clone(),
+                vlan: None,
+                mtu: None,
+                backend: SwitchBackend::Bridge,
+                uplink: None,
+                unfiltered: false,
+            }),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -62,6 +146,30 @@ pub struct CLI {
     pub bin: String,
     pub img: String,
     pub genisoimage: String,
+    #[serde(default = "default_tar")]
+    pub tar: String,
+    #[serde(default = "default_nft")]
+    pub nft: String,
+    #[serde(default = "default_dnsmasq")]
+    pub dnsmasq: String,
+    #[serde(default = "default_ovs_vsctl")]
+    pub ovs_vsctl: String,
+}
+
+fn default_tar() -> String {
+    "/usr/bin/tar".to_string()
+}
+
+fn default_nft() -> String {
+    "/usr/sbin/nft".to_string()
+}
+
+fn default_dnsmasq() -> String {
+    "/usr/sbin/dnsmasq".to_string()
+}
+
+fn default_ovs_vsctl() -> String {
+    "/usr/bin/ovs-vsctl".to_string()
 }
 
 impl Config {
@@ -71,6 +179,7 @@ impl Config {
         config.cli.bin = resolve(path, &config.cli.bin);
         config.ovmf.code = resolve(path, &config.ovmf.code);
         config.ovmf.vars = resolve(path, &config.ovmf.vars);
+        config.backup.path = resolve(path.parent().unwrap_or(Path::new(".")), &config.backup.path);
         Ok(config)
     }
 }
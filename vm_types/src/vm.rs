@@ -11,6 +11,8 @@ pub struct VmLaunchRequest {
     pub vnc: Option<String>,
     pub drives: Vec<DriveConfig>,
     pub networks: Vec<NetworkConfig>,
+    /// Memory state saved by `VmRuntime::save_state`, if any.
+    pub state_file: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -22,13 +24,92 @@ pub enum DriveBus {
     VirtioBlk {
         boot_index: Option<u32>,
     },
+    VirtioScsi {
+        lun: u32,
+        boot_index: Option<u32>,
+    },
+    Nvme {
+        serial: String,
+        boot_index: Option<u32>,
+    },
+    UsbStorage {
+        boot_index: Option<u32>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DriveFormat {
+    Raw,
+    Qcow2,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CacheMode {
+    None,
+    Writeback,
+    Writethrough,
+    Directsync,
+    Unsafe,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AioMode {
+    Threads,
+    Native,
+    IoUring,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiscardMode {
+    Ignore,
+    Unmap,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DetectZeroes {
+    Off,
+    On,
+    Unmap,
+}
+
+/// Throttling limits, unset fields mean unlimited.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct IoLimits {
+    pub iops_total: Option<u64>,
+    pub iops_read: Option<u64>,
+    pub iops_write: Option<u64>,
+    pub bps_total: Option<u64>,
+    pub bps_read: Option<u64>,
+    pub bps_write: Option<u64>,
+}
+
+/// Backend options of a drive, unset fields fall back to QEMU defaults.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DriveOptions {
+    pub format: Option<DriveFormat>,
+    pub cache: Option<CacheMode>,
+    pub aio: Option<AioMode>,
+    pub discard: Option<DiscardMode>,
+    pub detect_zeroes: Option<DetectZeroes>,
+    pub io_limits: IoLimits,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DriveConfig {
     pub id: String,
-    pub path: String,
+    /// Image backing the drive, `None` for an empty CD-ROM tray.
+    pub path: Option<String>,
     pub drive_media: DriveBus,
+    pub options: DriveOptions,
+    /// Key file of a LUKS encrypted image.
+    pub key_file: Option<String>,
 }
 
 #[derive(Debug, Clone)]
@@ -36,6 +117,43 @@ pub struct NetworkConfig {
     pub id: String,
     pub mac: String,
     pub ifname: String,
+    pub options: NicOptions,
+    pub backend: NetdevBackend,
+}
+
+/// Host side of a network interface.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NetdevBackend {
+    /// TAP device named after the interface.
+    Tap,
+    /// Multicast group `address:port` shared by the interfaces of a network, joined on the
+    /// host interface with the address `local`.
+    Mcast { group: String, local: String },
+    /// UDP link sending from `local` to `remote`, both `address:port`.
+    Udp { local: String, remote: String },
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NicModel {
+    #[default]
+    Virtio,
+    /// Intel e1000, for guests without virtio drivers.
+    E1000,
+    Rtl8139,
+}
+
+/// Device options of a network interface.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NicOptions {
+    pub model: NicModel,
+    /// Moves the virtio data path into the host kernel through vhost-net.
+    pub vhost: bool,
+    /// One queue pair per vCPU, virtio only.
+    pub multiqueue: bool,
+    /// MTU of the TAP and the virtio device, overriding the one of the network.
+    pub mtu: Option<u32>,
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -1,23 +1,26 @@
-use std::convert::Infallible;
+use std::{convert::Infallible, net::IpAddr};
 
 use axum::{
     Json, Router,
     extract::{Path, State},
     response::{Sse, sse::KeepAlive},
-    routing::{delete, get, post},
+    routing::{delete, get, patch, post, put},
 };
 use axum_auth::AuthBasic;
 use futures_util::StreamExt;
 use tokio_stream::wrappers::ReceiverStream;
-use yave::builders::{CloudInitBuilder, VmLaunchRequestBuilder};
+use yave::{builders::{CloudInitBuilder, VmLaunchRequestBuilder}, clone::CloneManager, drive::DriveManager, ipam::{IpamManager, PoolUsage}, net::NetworkManager, port_forward::PortForwardManager, registry::{AddressMode, IpAddressRecord, IpPoolRecord, NetworkInterfaceRecord, NetworkRecord, PortForwardRecord, SocketNetworkRecord, SecurityGroupRecord, SecurityGroupRuleRecord}, security_group::SecurityGroupManager};
 
-use crate::{AppState, auth, v1::types::{DriveDef, IpV4AddressInfo}};
+use crate::{AppState, auth, v1::types::{AddIpRequest, DriveDef, IpAddressInfo, IpV4AddressInfo, SetLinkRequest, SetVlanRequest}};
 mod types;
 
 pub use types::{
     Error, ApiResponse, CreateVMRequest, StartVMRequest,
     InstallRequest, InstallStatus, VMInfo, NetworkInterface, 
-    NetworkConfig, AddIpV4Request, VMRuntime
+    NetworkConfig, AddIpV4Request, UpdateIpV4Request, VMRuntime, UpdateDriveRequest, DriveInfo, ChangeMediaRequest,
+    CloneVMRequest, NetworkInfo, CreateNetworkRequest, UpdateNetworkRequest, SocketNetworkInfo, CreateSocketNetworkRequest,
+    SecurityGroupInfo, SecurityGroupRuleInfo, CreateSecurityGroupRequest, AddSecurityGroupRuleRequest,
+    PortForwardInfo, CreatePortForwardRequest, IpPoolInfo, IpPoolAddress, CreateIpPoolRequest
 };
 
 pub fn router() -> Router<AppState> {
@@ -27,10 +30,12 @@ pub fn router() -> Router<AppState> {
         .route("/vm", post(create_vm))
         .route("/vm/{vm_id}", get(get_vm_info))
         .route("/vm/{vm_id}", delete(delete_vm))
+        .route("/vm/{vm_id}/clone", post(clone_vm))
         
         // Runtime endpoints
         .route("/vm/{vm_id}/start", post(start_vm))
         .route("/vm/{vm_id}/stop", post(stop_vm))
+        .route("/vm/{vm_id}/save", post(save_vm))
         .route("/vm/{vm_id}/reboot", post(reboot_vm))
         .route("/vm/{vm_id}/status", get(get_vm_status))
         
@@ -39,9 +44,53 @@ pub fn router() -> Router<AppState> {
         .route("/vm/{vm_id}/network/interfaces/{interface_id}/ipv4", get(get_ip_address))
         .route("/vm/{vm_id}/network/interfaces/{interface_id}/ipv4", post(add_ip_address))
         .route("/vm/{vm_id}/network/interfaces/{interface_id}/ipv4", delete(remove_ip_address))
+        .route("/vm/{vm_id}/network/interfaces/{interface_id}/ipv4/{address}", patch(update_ipv4_address))
+        .route("/vm/{vm_id}/network/interfaces/{interface_id}/ipv4/{address}", delete(remove_ipv4_address))
+        .route("/vm/{vm_id}/network/interfaces/{interface_id}/ipv4/{address}/default", post(set_default_ipv4_address))
+        .route("/vm/{vm_id}/network/interfaces/{interface_id}/ip", get(get_ip_addresses))
+        .route("/vm/{vm_id}/network/interfaces/{interface_id}/ip", post(add_ip))
+        .route("/vm/{vm_id}/network/interfaces/{interface_id}/vlan", put(set_interface_vlan))
+        .route("/vm/{vm_id}/network/interfaces/{interface_id}/options", put(set_interface_options))
+        .route("/vm/{vm_id}/network/interfaces/{interface_id}/link", put(set_interface_link))
+
+        // Managed networks endpoints
+        .route("/networks", get(list_networks))
+        .route("/networks", post(create_network))
+        .route("/networks/{name}", get(get_network))
+        .route("/networks/{name}", patch(update_network))
+        .route("/networks/{name}", delete(delete_network))
+        .route("/socket-networks", get(list_socket_networks))
+        .route("/socket-networks", post(create_socket_network))
+        .route("/socket-networks/{name}", delete(delete_network))
+
+        // IP pools endpoints
+        .route("/ip-pools", get(list_ip_pools))
+        .route("/ip-pools", post(create_ip_pool))
+        .route("/ip-pools/{name}", get(get_ip_pool))
+        .route("/ip-pools/{name}", delete(delete_ip_pool))
+
+        // Security groups endpoints
+        .route("/security-groups", get(list_security_groups))
+        .route("/security-groups", post(create_security_group))
+        .route("/security-groups/{name}", get(get_security_group))
+        .route("/security-groups/{name}", delete(delete_security_group))
+        .route("/security-groups/{name}/rules", post(add_security_group_rule))
+        .route("/security-groups/{name}/rules/{rule_id}", delete(delete_security_group_rule))
+        .route("/vm/{vm_id}/network/interfaces/{interface_id}/security-groups", get(get_interface_security_groups))
+        .route("/vm/{vm_id}/network/interfaces/{interface_id}/security-groups/{name}", post(attach_security_group))
+        .route("/vm/{vm_id}/network/interfaces/{interface_id}/security-groups/{name}", delete(detach_security_group))
+
+        // Port forwards endpoints
+        .route("/vm/{vm_id}/port-forwards", get(list_port_forwards))
+        .route("/vm/{vm_id}/port-forwards", post(create_port_forward))
+        .route("/vm/{vm_id}/port-forwards/{forward_id}", delete(delete_port_forward))
         
         // Drives endpoints
+        .route("/vm/{vm_id}/drives", get(get_drives))
         .route("/vm/{vm_id}/drives", post(reinstall_drives))
+        .route("/vm/{vm_id}/drives/{drive_id}", patch(update_drive))
+        .route("/vm/{vm_id}/drives/{drive_id}/rotate-key", post(rotate_drive_key))
+        .route("/vm/{vm_id}/cdrom", post(change_media))
 
         // Installation endpoints
         .route("/vm/{vm_id}/install", post(install_vm))
@@ -109,13 +158,15 @@ async fn delete_vm(
     let builder = VmLaunchRequestBuilder::new(&state.context);
     let launch_request = builder.build(&vm_id).await?;
     let runtime = state.context.runtime();
-    if runtime.is_running(&launch_request).await? {
-        runtime.shutdown_vm(&launch_request).await?;
+    match runtime.is_running(&launch_request).await? {
+        true => runtime.shutdown_vm(&launch_request).await?,
+        false => runtime.delete_interfaces(&launch_request).await?,
     }
     let storage = state.context.storage();
     storage.delete_vm(&vm_id).await?;
     let registry = state.context.registry();
     registry.delete_vm(&vm_id).await?;
+    PortForwardManager::new(&state.context).apply().await?;
     Ok(Json(ApiResponse::ok(
         "VM deleted successfully".to_string(),
     )))
@@ -134,11 +185,14 @@ async fn start_vm(
 ) -> Result<Json<ApiResponse<VMRuntime>>, Error> {
     auth::check(&auth, &state.context.config())?;
 
+    if payload.discard {
+        state.context.storage().discard_state(&vm_id)?;
+    }
     let builder = VmLaunchRequestBuilder::new(&state.context);
     let launch_request = builder.build(&vm_id).await?;
     let runtime = state.context.runtime();
 
-    runtime.run_vm(&launch_request).await?;
+    runtime.run_vm(&launch_request, payload.resume).await?;
 
     if let Ok(client) = runtime.qmp_connect(&launch_request).await {
         if let Some(vnc_password) = &payload.vnc_password {
@@ -175,6 +229,27 @@ async fn stop_vm(
     Ok(Json(ApiResponse::ok(status)))
 }
 
+/// Save virtual machine memory state and stop it
+async fn save_vm(
+    auth: AuthBasic,
+    State(state): State<AppState>,
+    Path(vm_id): Path<String>,
+) -> Result<Json<ApiResponse<VMRuntime>>, Error> {
+    auth::check(&auth, state.context.config())?;
+
+    let builder = VmLaunchRequestBuilder::new(&state.context);
+    let launch_request = builder.build(&vm_id).await?;
+    let runtime = state.context.runtime();
+
+    runtime.save_state(&launch_request, &state.context.storage().state_path(&vm_id)).await?;
+
+    let status = VMRuntime {
+        is_running: false,
+    };
+
+    Ok(Json(ApiResponse::ok(status)))
+}
+
 /// Restart virtual machine
 async fn reboot_vm(
     auth: AuthBasic,
@@ -232,11 +307,7 @@ async fn get_network_config(
     let interfaces = nic_records
         .into_iter()
         .map(|nic| {
-            NetworkInterface {
-                id: nic.id,
-                ifname: nic.ifname,
-                mac_address: nic.mac_address,
-            }
+            network_interface(nic)
         })
         .collect();
 
@@ -254,7 +325,11 @@ async fn add_ip_address(
     auth::check(&auth, &state.context.config())?;
 
     // Validate IP address format
-    validate_ip_address(&payload.ip_address)?;
+    if !validate_ip_address(&payload.ip_address)?.is_ipv4() {
+        return Err(Error::InvalidIp(
+            format!("Not an IPv4 address: {}", payload.ip_address),
+        ));
+    }
 
     let registry = state.context.registry();
     let nic_records = registry.get_network_interfaces_by_vm_id(&vm_id).await?;
@@ -265,23 +340,70 @@ async fn add_ip_address(
         .ok_or(Error::NetworkInterfaceNotFound)?;
 
     // Add IP address to the database
-    registry.add_ipv4_address(yave::registry::AddIPv4Address {
-        ifname: nic.ifname.clone(),
-        address: payload.ip_address.clone(),
-        netmask: payload.netmask,
-        gateway: payload.gateway.clone(),
-    }).await?;
+    NetworkManager::new(&state.context)
+        .add_address(&nic.ifname, &payload.ip_address, payload.netmask, payload.gateway.clone(), AddressMode::Static)
+        .await?;
 
-    let result = NetworkInterface {
-        id: interface_id,
-        ifname: nic.ifname,
-        mac_address: nic.mac_address,
-    };
+    Ok(Json(ApiResponse::ok(network_interface(nic))))
+}
+
+fn ip_address_info(record: IpAddressRecord) -> IpAddressInfo {
+    IpAddressInfo {
+        ip_address: record.address,
+        family: record.family,
+        prefix_len: record.prefix_len,
+        gateway: record.gateway,
+        mode: record.mode,
+        is_default: record.is_default,
+    }
+}
+
+/// List IPv4 and IPv6 addresses of a network interface
+async fn get_ip_addresses(
+    auth: AuthBasic,
+    State(state): State<AppState>,
+    Path((vm_id, interface_id)): Path<(String, String)>,
+) -> Result<Json<ApiResponse<Vec<IpAddressInfo>>>, Error> {
+    auth::check(&auth, state.context.config())?;
+
+    let ifname = interface_ifname(&state, &vm_id, &interface_id).await?;
+    let addresses = state.context.registry()
+        .get_ip_addresses_by_ifname(&ifname)
+        .await?
+        .into_iter()
+        .map(ip_address_info)
+        .collect();
+
+    Ok(Json(ApiResponse::ok(addresses)))
+}
+
+/// Add an IPv4 or IPv6 address to a network interface
+async fn add_ip(
+    auth: AuthBasic,
+    State(state): State<AppState>,
+    Path((vm_id, interface_id)): Path<(String, String)>,
+    Json(payload): Json<AddIpRequest>,
+) -> Result<Json<ApiResponse<IpAddressInfo>>, Error> {
+    auth::check(&auth, state.context.config())?;
+
+    let address = validate_ip_address(&payload.ip_address)?;
+    if let Some(gateway) = &payload.gateway
+        && validate_ip_address(gateway)?.is_ipv4() != address.is_ipv4()
+    {
+        return Err(Error::InvalidIp(
+            format!("Gateway {} is not in the family of {}", gateway, address),
+        ));
+    }
+
+    let ifname = interface_ifname(&state, &vm_id, &interface_id).await?;
+    let record = NetworkManager::new(&state.context)
+        .add_address(&ifname, &payload.ip_address, payload.prefix_len, payload.gateway, payload.mode)
+        .await?;
 
-    Ok(Json(ApiResponse::ok(result)))
+    Ok(Json(ApiResponse::ok(ip_address_info(record))))
 }
 
-/// Remove IP address from network interface
+/// Remove all IPv4 addresses from network interface
 async fn remove_ip_address(
     auth: AuthBasic,
     State(state): State<AppState>,
@@ -289,19 +411,629 @@ async fn remove_ip_address(
 ) -> Result<Json<ApiResponse<String>>, Error> {
     auth::check(&auth, &state.context.config())?;
 
+    let ifname = interface_ifname(&state, &vm_id, &interface_id).await?;
+    let nm = NetworkManager::new(&state.context);
+    for record in state.context.registry().get_ipv4_by_ifname(&ifname).await? {
+        nm.remove_address(&ifname, &record.address).await?;
+    }
+
+    Ok(Json(ApiResponse::ok(
+        format!("IPv4 addresses removed from {}", interface_id),
+    )))
+}
+
+fn ipv4_address_info(record: IpAddressRecord) -> IpV4AddressInfo {
+    IpV4AddressInfo {
+        ip_address: record.address,
+        netmask: record.prefix_len,
+        gateway: record.gateway,
+        is_default: record.is_default,
+    }
+}
+
+/// Remove one IPv4 address from network interface
+async fn remove_ipv4_address(
+    auth: AuthBasic,
+    State(state): State<AppState>,
+    Path((vm_id, interface_id, address)): Path<(String, String, String)>,
+) -> Result<Json<ApiResponse<Vec<IpV4AddressInfo>>>, Error> {
+    auth::check(&auth, state.context.config())?;
+
+    let ifname = interface_ifname(&state, &vm_id, &interface_id).await?;
+    NetworkManager::new(&state.context).remove_address(&ifname, &address).await?;
+    let addresses = state.context.registry()
+        .get_ipv4_by_ifname(&ifname)
+        .await?
+        .into_iter()
+        .map(ipv4_address_info)
+        .collect();
+
+    Ok(Json(ApiResponse::ok(addresses)))
+}
+
+/// Change netmask or gateway of an IPv4 address
+async fn update_ipv4_address(
+    auth: AuthBasic,
+    State(state): State<AppState>,
+    Path((vm_id, interface_id, address)): Path<(String, String, String)>,
+    Json(payload): Json<UpdateIpV4Request>,
+) -> Result<Json<ApiResponse<IpV4AddressInfo>>, Error> {
+    auth::check(&auth, state.context.config())?;
+
+    let ifname = interface_ifname(&state, &vm_id, &interface_id).await?;
+    let current = state.context.registry()
+        .get_ipv4_by_ifname(&ifname)
+        .await?
+        .into_iter()
+        .find(|record| record.address == address)
+        .ok_or(Error::Yave(yave::Error::IpAddressNotFound(address.clone())))?;
+    if let Some(Some(gateway)) = &payload.gateway
+        && !validate_ip_address(gateway)?.is_ipv4()
+    {
+        return Err(Error::InvalidIp(
+            format!("Not an IPv4 address: {}", gateway),
+        ));
+    }
+    let record = NetworkManager::new(&state.context)
+        .update_address(
+            &ifname,
+            &address,
+            payload.netmask.unwrap_or(current.prefix_len),
+            payload.gateway.unwrap_or(current.gateway),
+        )
+        .await?;
+
+    Ok(Json(ApiResponse::ok(ipv4_address_info(record))))
+}
+
+/// Make an IPv4 address the default of its network interface
+async fn set_default_ipv4_address(
+    auth: AuthBasic,
+    State(state): State<AppState>,
+    Path((vm_id, interface_id, address)): Path<(String, String, String)>,
+) -> Result<Json<ApiResponse<Vec<IpV4AddressInfo>>>, Error> {
+    auth::check(&auth, state.context.config())?;
+
+    let ifname = interface_ifname(&state, &vm_id, &interface_id).await?;
+    NetworkManager::new(&state.context).set_default_address(&ifname, &address).await?;
+    let addresses = state.context.registry()
+        .get_ipv4_by_ifname(&ifname)
+        .await?
+        .into_iter()
+        .map(ipv4_address_info)
+        .collect();
+
+    Ok(Json(ApiResponse::ok(addresses)))
+}
+
+// ============================================================================
+// Managed Network Handlers
+// ============================================================================
+
+fn network_info(network: NetworkRecord) -> NetworkInfo {
+    NetworkInfo {
+        name: network.name,
+        bridge: network.bridge,
+        address: network.address,
+        prefix_len: network.prefix_len,
+        nat: network.nat,
+        dhcp: network.dhcp,
+        mtu: network.mtu,
+        isolated: network.isolated,
+    }
+}
+
+fn socket_network_info(network: SocketNetworkRecord) -> SocketNetworkInfo {
+    SocketNetworkInfo {
+        name: network.name,
+        mode: network.mode,
+        address: network.address,
+        port: network.port,
+        local_address: network.local_address,
+    }
+}
+
+/// List managed networks
+async fn list_networks(
+    auth: AuthBasic,
+    State(state): State<AppState>,
+) -> Result<Json<ApiResponse<Vec<NetworkInfo>>>, Error> {
+    auth::check(&auth, state.context.config())?;
+
+    let networks = state.context.registry().get_networks().await?;
+
+    Ok(Json(ApiResponse::ok(networks.into_iter().map(network_info).collect())))
+}
+
+/// Create a managed network and bring up its bridge
+async fn create_network(
+    auth: AuthBasic,
+    State(state): State<AppState>,
+    Json(payload): Json<CreateNetworkRequest>,
+) -> Result<Json<ApiResponse<NetworkInfo>>, Error> {
+    auth::check(&auth, state.context.config())?;
+
+    validate_ip_address(&payload.address)?;
+
+    let network = NetworkManager::new(&state.context)
+        .create_network(yave::registry::CreateNetwork {
+            name: payload.name,
+            bridge: payload.bridge,
+            address: payload.address,
+            prefix_len: payload.prefix_len,
+            nat: payload.nat,
+            dhcp: payload.dhcp,
+            mtu: payload.mtu,
+            isolated: payload.isolated,
+        })
+        .await?;
+
+    Ok(Json(ApiResponse::ok(network_info(network))))
+}
+
+/// Get a managed network
+async fn get_network(
+    auth: AuthBasic,
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<Json<ApiResponse<NetworkInfo>>, Error> {
+    auth::check(&auth, state.context.config())?;
+
+    let network = state.context.registry().get_network(&name).await?;
+
+    Ok(Json(ApiResponse::ok(network_info(network))))
+}
+
+/// Change NAT, DHCP or MTU of a managed network
+async fn update_network(
+    auth: AuthBasic,
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Json(payload): Json<UpdateNetworkRequest>,
+) -> Result<Json<ApiResponse<NetworkInfo>>, Error> {
+    auth::check(&auth, state.context.config())?;
+
+    let network = NetworkManager::new(&state.context)
+        .update_network(&name, payload.nat, payload.dhcp, payload.mtu)
+        .await?;
+
+    Ok(Json(ApiResponse::ok(network_info(network))))
+}
+
+/// Delete a managed network no interface is attached to
+async fn delete_network(
+    auth: AuthBasic,
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<Json<ApiResponse<String>>, Error> {
+    auth::check(&auth, state.context.config())?;
+
+    NetworkManager::new(&state.context).delete_network(&name).await?;
+
+    Ok(Json(ApiResponse::ok(format!("Network {} deleted", name))))
+}
+
+/// List socket networks
+async fn list_socket_networks(
+    auth: AuthBasic,
+    State(state): State<AppState>,
+) -> Result<Json<ApiResponse<Vec<SocketNetworkInfo>>>, Error> {
+    auth::check(&auth, state.context.config())?;
+
+    let networks = state.context.registry().get_socket_networks().await?;
+
+    Ok(Json(ApiResponse::ok(networks.into_iter().map(socket_network_info).collect())))
+}
+
+/// Create a network linking guests through QEMU sockets
+async fn create_socket_network(
+    auth: AuthBasic,
+    State(state): State<AppState>,
+    Json(payload): Json<CreateSocketNetworkRequest>,
+) -> Result<Json<ApiResponse<SocketNetworkInfo>>, Error> {
+    auth::check(&auth, state.context.config())?;
+
+    let network = NetworkManager::new(&state.context)
+        .create_socket_network(yave::registry::CreateSocketNetwork {
+            name: payload.name,
+            mode: payload.mode,
+            address: payload.address,
+            port: payload.port,
+            local_address: payload.local_address,
+        })
+        .await?;
+
+    Ok(Json(ApiResponse::ok(socket_network_info(network))))
+}
+
+// ============================================================================
+// Security Group Handlers
+// ============================================================================
+
+fn security_group_info(group: SecurityGroupRecord, rules: Vec<SecurityGroupRuleRecord>) -> SecurityGroupInfo {
+    SecurityGroupInfo {
+        name: group.name,
+        description: group.description,
+        rules: rules
+            .into_iter()
+            .map(|rule| SecurityGroupRuleInfo {
+                id: rule.id,
+                direction: rule.direction,
+                protocol: rule.protocol,
+                port_from: rule.port_from,
+                port_to: rule.port_to,
+                cidr: rule.cidr,
+            })
+            .collect(),
+    }
+}
+
+/// List security groups with their rules
+async fn list_security_groups(
+    auth: AuthBasic,
+    State(state): State<AppState>,
+) -> Result<Json<ApiResponse<Vec<SecurityGroupInfo>>>, Error> {
+    auth::check(&auth, state.context.config())?;
+
     let registry = state.context.registry();
-    let (_, _, nic_records, _) = registry.get_vm_full(&vm_id).await?;
+    let mut groups = vec![];
+    for group in registry.get_security_groups().await? {
+        let rules = registry.get_security_group_rules(&group.name).await?;
+        groups.push(security_group_info(group, rules));
+    }
+
+    Ok(Json(ApiResponse::ok(groups)))
+}
 
-    let _nic = nic_records
+/// Create an empty security group
+async fn create_security_group(
+    auth: AuthBasic,
+    State(state): State<AppState>,
+    Json(payload): Json<CreateSecurityGroupRequest>,
+) -> Result<Json<ApiResponse<SecurityGroupInfo>>, Error> {
+    auth::check(&auth, state.context.config())?;
+
+    let group = SecurityGroupManager::new(&state.context)
+        .create_group(&payload.name, payload.description.as_deref())
+        .await?;
+
+    Ok(Json(ApiResponse::ok(security_group_info(group, vec![]))))
+}
+
+/// Get a security group with its rules
+async fn get_security_group(
+    auth: AuthBasic,
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<Json<ApiResponse<SecurityGroupInfo>>, Error> {
+    auth::check(&auth, state.context.config())?;
+
+    let (group, rules) = SecurityGroupManager::new(&state.context).get_group(&name).await?;
+
+    Ok(Json(ApiResponse::ok(security_group_info(group, rules))))
+}
+
+/// Delete a security group no interface is attached to
+async fn delete_security_group(
+    auth: AuthBasic,
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<Json<ApiResponse<String>>, Error> {
+    auth::check(&auth, state.context.config())?;
+
+    SecurityGroupManager::new(&state.context).delete_group(&name).await?;
+
+    Ok(Json(ApiResponse::ok(format!("Security group {} deleted", name))))
+}
+
+/// Add a rule to a security group, applied to running interfaces at once
+async fn add_security_group_rule(
+    auth: AuthBasic,
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+    Json(payload): Json<AddSecurityGroupRuleRequest>,
+) -> Result<Json<ApiResponse<SecurityGroupInfo>>, Error> {
+    auth::check(&auth, state.context.config())?;
+
+    let sgm = SecurityGroupManager::new(&state.context);
+    sgm.add_rule(&name, yave::registry::AddSecurityGroupRule {
+        direction: payload.direction,
+        protocol: payload.protocol,
+        port_from: payload.port_from,
+        port_to: payload.port_to,
+        cidr: payload.cidr,
+    }).await?;
+    let (group, rules) = sgm.get_group(&name).await?;
+
+    Ok(Json(ApiResponse::ok(security_group_info(group, rules))))
+}
+
+/// Remove a rule from a security group
+async fn delete_security_group_rule(
+    auth: AuthBasic,
+    State(state): State<AppState>,
+    Path((name, rule_id)): Path<(String, i64)>,
+) -> Result<Json<ApiResponse<SecurityGroupInfo>>, Error> {
+    auth::check(&auth, state.context.config())?;
+
+    let sgm = SecurityGroupManager::new(&state.context);
+    sgm.delete_rule(&name, rule_id).await?;
+    let (group, rules) = sgm.get_group(&name).await?;
+
+    Ok(Json(ApiResponse::ok(security_group_info(group, rules))))
+}
+
+/// Resolve the interface name of a VM's network interface
+async fn interface_ifname(state: &AppState, vm_id: &str, interface_id: &str) -> Result<String, Error> {
+    let nic_records = state.context.registry().get_network_interfaces_by_vm_id(vm_id).await?;
+    nic_records
         .into_iter()
         .find(|ni| ni.id == interface_id)
-        .ok_or(Error::NetworkInterfaceNotFound)?;
+        .map(|ni| ni.ifname)
+        .ok_or(Error::NetworkInterfaceNotFound)
+}
+
+/// Set the VLAN of a network interface, re-attaching it if it is up
+async fn set_interface_vlan(
+    auth: AuthBasic,
+    State(state): State<AppState>,
+    Path((vm_id, interface_id)): Path<(String, String)>,
+    Json(payload): Json<SetVlanRequest>,
+) -> Result<Json<ApiResponse<NetworkInterface>>, Error> {
+    auth::check(&auth, state.context.config())?;
 
-    println!("Removing all IP addresses from interface {}", interface_id);
+    let ifname = interface_ifname(&state, &vm_id, &interface_id).await?;
+    NetworkManager::new(&state.context).set_vlan(&ifname, payload.vlan).await?;
+    let nic = state.context.registry().get_network_interface_by_ifname(&ifname).await?;
 
-    Ok(Json(ApiResponse::ok(
-        "IP address removal requested successfully".to_string(),
-    )))
+    Ok(Json(ApiResponse::ok(network_interface(nic))))
+}
+
+/// Set the model, vhost, multiqueue and MTU of a network interface, applied on the next start
+async fn set_interface_options(
+    auth: AuthBasic,
+    State(state): State<AppState>,
+    Path((vm_id, interface_id)): Path<(String, String)>,
+    Json(payload): Json<vm_types::vm::NicOptions>,
+) -> Result<Json<ApiResponse<NetworkInterface>>, Error> {
+    auth::check(&auth, state.context.config())?;
+
+    let ifname = interface_ifname(&state, &vm_id, &interface_id).await?;
+    NetworkManager::new(&state.context).set_options(&ifname, &payload).await?;
+    let nic = state.context.registry().get_network_interface_by_ifname(&ifname).await?;
+
+    Ok(Json(ApiResponse::ok(network_interface(nic))))
+}
+
+/// Set the link of a network interface of a running VM up or down
+async fn set_interface_link(
+    auth: AuthBasic,
+    State(state): State<AppState>,
+    Path((vm_id, interface_id)): Path<(String, String)>,
+    Json(payload): Json<SetLinkRequest>,
+) -> Result<Json<ApiResponse<()>>, Error> {
+    auth::check(&auth, state.context.config())?;
+
+    let ifname = interface_ifname(&state, &vm_id, &interface_id).await?;
+    NetworkManager::new(&state.context).set_link(&ifname, payload.up).await?;
+
+    Ok(Json(ApiResponse::ok(())))
+}
+
+fn network_interface(nic: NetworkInterfaceRecord) -> NetworkInterface {
+    NetworkInterface {
+        id: nic.id,
+        ifname: nic.ifname,
+        mac_address: nic.mac_address,
+        network: nic.network,
+        vlan: nic.vlan,
+        options: nic.options,
+    }
+}
+
+/// List security groups attached to a network interface
+async fn get_interface_security_groups(
+    auth: AuthBasic,
+    State(state): State<AppState>,
+    Path((vm_id, interface_id)): Path<(String, String)>,
+) -> Result<Json<ApiResponse<Vec<SecurityGroupInfo>>>, Error> {
+    auth::check(&auth, state.context.config())?;
+
+    let ifname = interface_ifname(&state, &vm_id, &interface_id).await?;
+    let registry = state.context.registry();
+    let mut groups = vec![];
+    for group in registry.get_security_groups_by_ifname(&ifname).await? {
+        let rules = registry.get_security_group_rules(&group.name).await?;
+        groups.push(security_group_info(group, rules));
+    }
+
+    Ok(Json(ApiResponse::ok(groups)))
+}
+
+/// Attach a security group to a network interface
+async fn attach_security_group(
+    auth: AuthBasic,
+    State(state): State<AppState>,
+    Path((vm_id, interface_id, name)): Path<(String, String, String)>,
+) -> Result<Json<ApiResponse<String>>, Error> {
+    auth::check(&auth, state.context.config())?;
+
+    let ifname = interface_ifname(&state, &vm_id, &interface_id).await?;
+    SecurityGroupManager::new(&state.context).attach(&ifname, &name).await?;
+
+    Ok(Json(ApiResponse::ok(format!("Security group {} attached to {}", name, interface_id))))
+}
+
+/// Detach a security group from a network interface
+async fn detach_security_group(
+    auth: AuthBasic,
+    State(state): State<AppState>,
+    Path((vm_id, interface_id, name)): Path<(String, String, String)>,
+) -> Result<Json<ApiResponse<String>>, Error> {
+    auth::check(&auth, state.context.config())?;
+
+    let ifname = interface_ifname(&state, &vm_id, &interface_id).await?;
+    SecurityGroupManager::new(&state.context).detach(&ifname, &name).await?;
+
+    Ok(Json(ApiResponse::ok(format!("Security group {} detached from {}", name, interface_id))))
+}
+
+// ============================================================================
+// IP Pool Handlers
+// ============================================================================
+
+fn ip_pool_info(pool: IpPoolRecord, usage: PoolUsage) -> IpPoolInfo {
+    IpPoolInfo {
+        name: pool.name,
+        cidr: pool.cidr,
+        gateway: pool.gateway,
+        reserved: pool.reserved,
+        nameservers: pool.nameservers,
+        network: pool.network,
+        total: usage.total,
+        used: usage.used,
+        free: usage.total.saturating_sub(usage.used),
+        addresses: usage.addresses
+            .into_iter()
+            .map(|record| IpPoolAddress {
+                ip_address: record.address,
+                ifname: record.ifname,
+            })
+            .collect(),
+    }
+}
+
+/// List IP pools with their utilisation
+async fn list_ip_pools(
+    auth: AuthBasic,
+    State(state): State<AppState>,
+) -> Result<Json<ApiResponse<Vec<IpPoolInfo>>>, Error> {
+    auth::check(&auth, state.context.config())?;
+
+    let pools = IpamManager::new(&state.context).get_pools().await?
+        .into_iter()
+        .map(|(pool, usage)| ip_pool_info(pool, usage))
+        .collect();
+
+    Ok(Json(ApiResponse::ok(pools)))
+}
+
+/// Create an IP pool
+async fn create_ip_pool(
+    auth: AuthBasic,
+    State(state): State<AppState>,
+    Json(payload): Json<CreateIpPoolRequest>,
+) -> Result<Json<ApiResponse<IpPoolInfo>>, Error> {
+    auth::check(&auth, state.context.config())?;
+
+    let ipam = IpamManager::new(&state.context);
+    let pool = ipam.create_pool(yave::registry::CreateIpPool {
+        name: payload.name,
+        cidr: payload.cidr,
+        gateway: payload.gateway,
+        reserved: payload.reserved,
+        nameservers: payload.nameservers,
+        network: payload.network,
+    }).await?;
+    let (pool, usage) = ipam.get_pool(&pool.name).await?;
+
+    Ok(Json(ApiResponse::ok(ip_pool_info(pool, usage))))
+}
+
+/// Get an IP pool with its utilisation
+async fn get_ip_pool(
+    auth: AuthBasic,
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<Json<ApiResponse<IpPoolInfo>>, Error> {
+    auth::check(&auth, state.context.config())?;
+
+    let (pool, usage) = IpamManager::new(&state.context).get_pool(&name).await?;
+
+    Ok(Json(ApiResponse::ok(ip_pool_info(pool, usage))))
+}
+
+/// Delete an IP pool, assigned addresses are kept
+async fn delete_ip_pool(
+    auth: AuthBasic,
+    State(state): State<AppState>,
+    Path(name): Path<String>,
+) -> Result<Json<ApiResponse<String>>, Error> {
+    auth::check(&auth, state.context.config())?;
+
+    IpamManager::new(&state.context).delete_pool(&name).await?;
+
+    Ok(Json(ApiResponse::ok(format!("IP pool {} deleted", name))))
+}
+
+// ============================================================================
+// Port Forward Handlers
+// ============================================================================
+
+fn port_forward_info(forward: PortForwardRecord, nics: &[NetworkInterfaceRecord]) -> PortForwardInfo {
+    PortForwardInfo {
+        id: forward.id,
+        interface_id: nics.iter()
+            .find(|ni| ni.ifname == forward.ifname)
+            .map(|ni| ni.id.clone())
+            .unwrap_or(forward.ifname),
+        host_ip: forward.host_ip,
+        host_port: forward.host_port,
+        protocol: forward.protocol,
+        guest_port: forward.guest_port,
+    }
+}
+
+/// List port forwards of a VM
+async fn list_port_forwards(
+    auth: AuthBasic,
+    State(state): State<AppState>,
+    Path(vm_id): Path<String>,
+) -> Result<Json<ApiResponse<Vec<PortForwardInfo>>>, Error> {
+    auth::check(&auth, state.context.config())?;
+
+    let nics = state.context.registry().get_network_interfaces_by_vm_id(&vm_id).await?;
+    let forwards = PortForwardManager::new(&state.context).get_forwards(&vm_id).await?
+        .into_iter()
+        .map(|forward| port_forward_info(forward, &nics))
+        .collect();
+
+    Ok(Json(ApiResponse::ok(forwards)))
+}
+
+/// Forward a host port to a VM network interface
+async fn create_port_forward(
+    auth: AuthBasic,
+    State(state): State<AppState>,
+    Path(vm_id): Path<String>,
+    Json(payload): Json<CreatePortForwardRequest>,
+) -> Result<Json<ApiResponse<PortForwardInfo>>, Error> {
+    auth::check(&auth, state.context.config())?;
+
+    let ifname = interface_ifname(&state, &vm_id, &payload.interface_id).await?;
+    let forward = PortForwardManager::new(&state.context).add_forward(&vm_id, yave::registry::AddPortForward {
+        ifname,
+        host_ip: payload.host_ip,
+        host_port: payload.host_port,
+        protocol: payload.protocol,
+        guest_port: payload.guest_port,
+    }).await?;
+    let nics = state.context.registry().get_network_interfaces_by_vm_id(&vm_id).await?;
+
+    Ok(Json(ApiResponse::ok(port_forward_info(forward, &nics))))
+}
+
+/// Remove a port forward of a VM
+async fn delete_port_forward(
+    auth: AuthBasic,
+    State(state): State<AppState>,
+    Path((vm_id, forward_id)): Path<(String, i64)>,
+) -> Result<Json<ApiResponse<String>>, Error> {
+    auth::check(&auth, state.context.config())?;
+
+    PortForwardManager::new(&state.context).delete_forward(&vm_id, forward_id).await?;
+
+    Ok(Json(ApiResponse::ok(format!("Port forward {} deleted", forward_id))))
 }
 
 // ============================================================================
@@ -378,32 +1110,73 @@ async fn create_vm(
     let mut install_drives = vec![];
 
     for (idx, drive) in payload.drives.iter().enumerate() {
+        yave::drive::validate_drive_options(&drive.options())?;
         let drive_id = format!("drive{}", idx);
         drives_spec.push(yave::registry::CreateDrive {
             id: drive_id.clone(),
-            drive_bus: vm_types::vm::DriveBus::VirtioBlk {
-                boot_index: Some(idx as u32 + 1),
-            },
+            drive_bus: drive.drive_bus(idx, &drive_id),
+            size: drive.size(),
+            options: drive.options(),
+            encrypted: drive.encrypted(),
+            media: None,
         });
 
         match drive {
-            DriveDef::Empty { size } => {
+            DriveDef::Empty { size, .. } => {
                 install_drives.push(yave::storage::DriveInstallMode::New {
                     id: drive_id,
                     size: *size,
+                    encrypted: drive.encrypted(),
                 });
             }
-            DriveDef::From { size, image } => {
+            DriveDef::From { size, image, .. } => {
                 let image = image.clone();
                 install_drives.push(yave::storage::DriveInstallMode::Existing {
                     id: drive_id,
                     resize: *size,
                     image,
+                    encrypted: drive.encrypted(),
                 });
             }
         }
     }
 
+    if let Some(iso) = &payload.iso {
+        if !state.context.storage().iso_path(iso).exists() {
+            return Err(Error::Yave(yave::Error::ImageNotFound(iso.clone())));
+        }
+        drives_spec.push(yave::registry::CreateDrive::cdrom("cdrom0", iso, payload.boot_iso));
+    }
+
+    let mut network_interfaces = vec![];
+    for (idx, nic) in payload.network_interfaces.iter().enumerate() {
+        if let Some(network) = &nic.network
+            && !NetworkManager::new(&state.context).network_exists(network).await?
+        {
+            return Err(Error::Yave(yave::Error::NetworkNotFound(network.clone())));
+        }
+        if let Some(vlan) = nic.vlan {
+            yave::net::validate_vlan(vlan)?;
+        }
+        yave::net::validate_nic_options(&nic.options)?;
+        network_interfaces.push(yave::registry::CreateNetworkInterface {
+            id: format!("net{}", idx),
+            network: nic.network.clone(),
+            vlan: nic.vlan,
+            options: nic.options.clone(),
+            mac_address: nic.mac_address.clone(),
+        });
+    }
+    if network_interfaces.is_empty() {
+        network_interfaces.push(yave::registry::CreateNetworkInterface {
+            id: "net0".to_string(),
+            network: None,
+            vlan: None,
+            options: Default::default(),
+            mac_address: None,
+        });
+    }
+
     let vm = registry
         .create_vm(yave::registry::CreateVirtualMachine {
             id: payload.id.clone(),
@@ -411,12 +1184,14 @@ async fn create_vm(
             vcpu: payload.vcpu,
             memory: payload.memory,
             ovmf: true,
-            network_interfaces: vec![yave::registry::CreateNetworkInterface {
-                id: "net0".to_string(),
-            }],
+            network_interfaces,
             drives: drives_spec,
         })
         .await?;
+    if let Err(err) = IpamManager::new(&state.context).allocate_vm(&vm.id).await {
+        registry.delete_vm(&vm.id).await?;
+        return Err(err.into());
+    }
 
     let storage = state.context.storage();
     storage
@@ -439,6 +1214,34 @@ async fn create_vm(
     Ok(Json(ApiResponse::ok(info)))
 }
 
+/// Clone a stopped virtual machine
+async fn clone_vm(
+    auth: AuthBasic,
+    State(state): State<AppState>,
+    Path(vm_id): Path<String>,
+    Json(payload): Json<CloneVMRequest>,
+) -> Result<Json<ApiResponse<VMInfo>>, Error> {
+    auth::check(&auth, state.context.config())?;
+
+    let vm = CloneManager::new(&state.context)
+        .clone_vm(&vm_id, yave::registry::CloneVirtualMachine {
+            id: payload.id.clone(),
+            hostname: payload.hostname.clone(),
+            linked: payload.linked,
+        })
+        .await?;
+
+    let info = VMInfo {
+        id: vm.id,
+        hostname: vm.hostname,
+        memory: vm.memory,
+        vcpu: vm.vcpu,
+        vnc_display: vm.vnc_display,
+    };
+
+    Ok(Json(ApiResponse::ok(info)))
+}
+
 async fn get_ip_address(
     auth: AuthBasic,
     State(state): State<AppState>,
@@ -458,12 +1261,7 @@ async fn get_ip_address(
         .get_ipv4_by_ifname(&nic.ifname)
         .await?
         .into_iter()
-        .map(|ip_record| IpV4AddressInfo {
-            ip_address: ip_record.address,
-            netmask: ip_record.netmask,
-            gateway: ip_record.gateway,
-            is_default: ip_record.is_default,
-        })
+        .map(ipv4_address_info)
         .collect();
 
     Ok(Json(ApiResponse::ok(ip_addresses)))
@@ -473,6 +1271,33 @@ async fn get_ip_address(
 // Drive Handlers
 // ============================================================================
 
+/// List drives of a virtual machine
+async fn get_drives(
+    auth: AuthBasic,
+    State(state): State<AppState>,
+    Path(vm_id): Path<String>,
+) -> Result<Json<ApiResponse<Vec<DriveInfo>>>, Error> {
+    auth::check(&auth, state.context.config())?;
+
+    let registry = state.context.registry();
+    registry.get_vm_by_id(&vm_id).await?;
+    let drives = registry
+        .get_drives_by_vm_id(&vm_id)
+        .await?
+        .into_iter()
+        .map(|drive| DriveInfo {
+            id: drive.id,
+            size: drive.size,
+            bus: drive.drive_bus,
+            options: drive.options,
+            encrypted: drive.encrypted,
+            media: drive.media,
+        })
+        .collect();
+
+    Ok(Json(ApiResponse::ok(drives)))
+}
+
 async fn reinstall_drives(
     auth: AuthBasic,
     State(state): State<AppState>,
@@ -489,33 +1314,52 @@ async fn reinstall_drives(
     let mut install_drives = vec![];
 
     for (idx, drive) in payload.iter().enumerate() {
+        yave::drive::validate_drive_options(&drive.options())?;
         let drive_id = format!("drive{}", idx);
 
         spec_drives.push(yave::registry::CreateDrive {
             id: drive_id.clone(),
-            drive_bus: vm_types::vm::DriveBus::VirtioBlk {
-                boot_index: Some(idx as u32 + 1),
-            },
+            drive_bus: drive.drive_bus(idx, &drive_id),
+            size: drive.size(),
+            options: drive.options(),
+            encrypted: drive.encrypted(),
+            media: None,
         });
 
         match drive {
-            DriveDef::Empty { size } => {
+            DriveDef::Empty { size, .. } => {
                 install_drives.push(yave::storage::DriveInstallMode::New {
                     id: drive_id,
                     size: *size,
+                    encrypted: drive.encrypted(),
                 });
             }
-            DriveDef::From { size, image } => {
+            DriveDef::From { size, image, .. } => {
                 let image = image.clone();
                 install_drives.push(yave::storage::DriveInstallMode::Existing {
                     id: drive_id,
                     resize: *size,
                     image,
+                    encrypted: drive.encrypted(),
                 });
             }
         }
     }
 
+    // CD-ROM drives only reference the image library, keep them as they are
+    for drive in registry.get_drives_by_vm_id(&vm.id).await? {
+        if matches!(drive.drive_bus, vm_types::vm::DriveBus::Ide { media_type: vm_types::vm::DiskMediaKind::Cdrom, .. }) {
+            spec_drives.push(yave::registry::CreateDrive {
+                id: drive.id,
+                drive_bus: drive.drive_bus,
+                size: drive.size,
+                options: drive.options,
+                encrypted: drive.encrypted,
+                media: drive.media,
+            });
+        }
+    }
+
     registry
         .replace_drives(&vm.id, spec_drives)
         .await?;
@@ -532,37 +1376,79 @@ async fn reinstall_drives(
     Ok(Json(ApiResponse::ok(())))
 }
 
+/// Resize a drive or change its I/O limits, online if the VM is running
+async fn update_drive(
+    auth: AuthBasic,
+    State(state): State<AppState>,
+    Path((vm_id, drive_id)): Path<(String, String)>,
+    Json(payload): Json<UpdateDriveRequest>,
+) -> Result<Json<ApiResponse<()>>, Error> {
+    auth::check(&auth, state.context.config())?;
+
+    let drives = DriveManager::new(&state.context);
+    if let Some(size) = payload.size {
+        drives.resize_drive(&vm_id, &drive_id, size, payload.force).await?;
+    }
+    if let Some(io_limits) = payload.io_limits {
+        drives.set_io_limits(&vm_id, &drive_id, io_limits).await?;
+    }
+
+    Ok(Json(ApiResponse::ok(())))
+}
+
+/// Rewrap an encrypted drive with a new key, the VM must be stopped
+async fn rotate_drive_key(
+    auth: AuthBasic,
+    State(state): State<AppState>,
+    Path((vm_id, drive_id)): Path<(String, String)>,
+) -> Result<Json<ApiResponse<()>>, Error> {
+    auth::check(&auth, state.context.config())?;
+
+    DriveManager::new(&state.context)
+        .rotate_key(&vm_id, &drive_id)
+        .await?;
+
+    Ok(Json(ApiResponse::ok(())))
+}
+
+/// Insert an ISO into a CD-ROM drive or eject it, online if the VM is running
+async fn change_media(
+    auth: AuthBasic,
+    State(state): State<AppState>,
+    Path(vm_id): Path<String>,
+    Json(payload): Json<ChangeMediaRequest>,
+) -> Result<Json<ApiResponse<()>>, Error> {
+    auth::check(&auth, state.context.config())?;
+
+    DriveManager::new(&state.context)
+        .change_media(&vm_id, payload.drive_id.as_deref(), payload.iso.as_deref())
+        .await?;
+
+    Ok(Json(ApiResponse::ok(())))
+}
+
 // ============================================================================
 // Helper Functions
 // ============================================================================
 
-/// Validate IP address format
-fn validate_ip_address(ip: &str) -> Result<(), Error> {
-    let parts: Vec<&str> = ip.split('.').collect();
+/// Validate an IP address of either family
+fn validate_ip_address(ip: &str) -> Result<IpAddr, Error> {
+    let address: IpAddr = ip.parse()
+        .map_err(|_| Error::InvalidIp(format!("Invalid address: {}", ip)))?;
 
-    if parts.len() != 4 {
+    let valid = match address {
+        IpAddr::V4(v4) => {
+            let first = v4.octets()[0];
+            first != 0 && first <= 223 && !v4.is_loopback()
+        }
+        IpAddr::V6(v6) => !v6.is_unspecified() && !v6.is_loopback() && !v6.is_multicast(),
+    };
+    if !valid {
         return Err(Error::InvalidIp(
-            "IP must have 4 octets".to_string(),
+            format!("Address not assignable: {}", ip),
         ));
     }
 
-    for (i, part) in parts.iter().enumerate() {
-        match part.parse::<u8>() {
-            Ok(num) => {
-                if i == 0 && (num == 0 || num > 223) {
-                    return Err(Error::InvalidIp(
-                        "Invalid first octet".to_string(),
-                    ));
-                }
-            }
-            Err(_) => {
-                return Err(Error::InvalidIp(
-                    format!("Invalid octet: {}", part),
-                ))
-            }
-        }
-    }
-
-    Ok(())
+    Ok(address)
 }
 
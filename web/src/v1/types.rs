@@ -1,5 +1,7 @@
 use axum::{http::StatusCode, response::IntoResponse, Json};
 use serde::{Deserialize, Serialize};
+use vm_types::vm::{DiskMediaKind, DriveBus, DriveFormat, DriveOptions, IoLimits, NicOptions};
+use yave::registry::{AddressMode, AddressRange, ForwardProtocol, IpFamily, RuleDirection, RuleProtocol, SocketMode};
 
 use crate::auth;
 
@@ -71,10 +73,142 @@ impl Error {
                 StatusCode::CONFLICT,
                 "VM_ALREADY_RUNNING".to_string(),
             ),
+            Error::Yave(yave::Error::SavedState(_)) => (
+                StatusCode::CONFLICT,
+                "SAVED_STATE".to_string(),
+            ),
+            Error::Yave(yave::Error::DriveNotFound) => (
+                StatusCode::NOT_FOUND,
+                "DRIVE_NOT_FOUND".to_string(),
+            ),
+            Error::Yave(yave::Error::DriveShrink(_)) => (
+                StatusCode::BAD_REQUEST,
+                "DRIVE_SHRINK_REFUSED".to_string(),
+            ),
+            Error::Yave(yave::Error::DriveNotEncrypted(_)) => (
+                StatusCode::BAD_REQUEST,
+                "DRIVE_NOT_ENCRYPTED".to_string(),
+            ),
+            Error::Yave(yave::Error::DriveNotCdrom(_)) => (
+                StatusCode::BAD_REQUEST,
+                "DRIVE_NOT_CDROM".to_string(),
+            ),
+            Error::Yave(yave::Error::ImageNotFound(_)) => (
+                StatusCode::NOT_FOUND,
+                "IMAGE_NOT_FOUND".to_string(),
+            ),
+            Error::Yave(yave::Error::VMExists(_)) => (
+                StatusCode::CONFLICT,
+                "VM_EXISTS".to_string(),
+            ),
+            Error::Yave(yave::Error::LinkedCloneEncrypted(_)) => (
+                StatusCode::BAD_REQUEST,
+                "LINKED_CLONE_UNSUPPORTED".to_string(),
+            ),
+            Error::Yave(yave::Error::NetworkNotFound(_)) => (
+                StatusCode::BAD_REQUEST,
+                "NETWORK_NOT_FOUND".to_string(),
+            ),
+            Error::Yave(yave::Error::NetworkExists(_)) => (
+                StatusCode::CONFLICT,
+                "NETWORK_EXISTS".to_string(),
+            ),
+            Error::Yave(yave::Error::NetworkInUse(_)) => (
+                StatusCode::CONFLICT,
+                "NETWORK_IN_USE".to_string(),
+            ),
+            Error::Yave(yave::Error::InvalidNetwork(_)) => (
+                StatusCode::BAD_REQUEST,
+                "INVALID_NETWORK".to_string(),
+            ),
+            Error::Yave(yave::Error::NetworkFull(_)) => (
+                StatusCode::CONFLICT,
+                "NETWORK_FULL".to_string(),
+            ),
+            Error::Yave(yave::Error::FilteringUnsupported(_)) => (
+                StatusCode::BAD_REQUEST,
+                "FILTERING_UNSUPPORTED".to_string(),
+            ),
+            Error::Yave(yave::Error::InvalidNetworkAddress(_)) => (
+                StatusCode::BAD_REQUEST,
+                "INVALID_NETWORK_ADDRESS".to_string(),
+            ),
+            Error::Yave(yave::Error::InvalidVlan(_)) => (
+                StatusCode::BAD_REQUEST,
+                "INVALID_VLAN".to_string(),
+            ),
+            Error::Yave(yave::Error::InvalidDriveOptions(_)) => (
+                StatusCode::BAD_REQUEST,
+                "INVALID_DRIVE_OPTIONS".to_string(),
+            ),
+            Error::Yave(yave::Error::InvalidNicOptions(_)) => (
+                StatusCode::BAD_REQUEST,
+                "INVALID_NIC_OPTIONS".to_string(),
+            ),
+            Error::Yave(yave::Error::InvalidMacAddress(_)) => (
+                StatusCode::BAD_REQUEST,
+                "INVALID_MAC_ADDRESS".to_string(),
+            ),
+            Error::Yave(yave::Error::MacAddressInUse(_)) => (
+                StatusCode::CONFLICT,
+                "MAC_ADDRESS_IN_USE".to_string(),
+            ),
+            Error::Yave(yave::Error::SecurityGroupNotFound(_)) => (
+                StatusCode::NOT_FOUND,
+                "SECURITY_GROUP_NOT_FOUND".to_string(),
+            ),
+            Error::Yave(yave::Error::SecurityGroupExists(_)) => (
+                StatusCode::CONFLICT,
+                "SECURITY_GROUP_EXISTS".to_string(),
+            ),
+            Error::Yave(yave::Error::SecurityGroupInUse(_)) => (
+                StatusCode::CONFLICT,
+                "SECURITY_GROUP_IN_USE".to_string(),
+            ),
+            Error::Yave(yave::Error::InvalidSecurityGroupRule(_)) => (
+                StatusCode::BAD_REQUEST,
+                "INVALID_SECURITY_GROUP_RULE".to_string(),
+            ),
+            Error::Yave(yave::Error::IpAddressNotFound(_)) => (
+                StatusCode::NOT_FOUND,
+                "IP_ADDRESS_NOT_FOUND".to_string(),
+            ),
+            Error::Yave(yave::Error::IpPoolNotFound(_)) => (
+                StatusCode::NOT_FOUND,
+                "IP_POOL_NOT_FOUND".to_string(),
+            ),
+            Error::Yave(yave::Error::IpPoolExists(_)) => (
+                StatusCode::CONFLICT,
+                "IP_POOL_EXISTS".to_string(),
+            ),
+            Error::Yave(yave::Error::IpPoolExhausted(_)) => (
+                StatusCode::CONFLICT,
+                "IP_POOL_EXHAUSTED".to_string(),
+            ),
+            Error::Yave(yave::Error::InvalidIpPool(_)) => (
+                StatusCode::BAD_REQUEST,
+                "INVALID_IP_POOL".to_string(),
+            ),
+            Error::Yave(yave::Error::PortForwardNotFound) => (
+                StatusCode::NOT_FOUND,
+                "PORT_FORWARD_NOT_FOUND".to_string(),
+            ),
+            Error::Yave(yave::Error::PortForwardConflict(_)) => (
+                StatusCode::CONFLICT,
+                "PORT_FORWARD_CONFLICT".to_string(),
+            ),
+            Error::Yave(yave::Error::InvalidPortForward(_)) => (
+                StatusCode::BAD_REQUEST,
+                "INVALID_PORT_FORWARD".to_string(),
+            ),
             Error::Yave(yave::Error::VMNotRunning(_)) => (
                 StatusCode::BAD_REQUEST,
                 "VM_NOT_RUNNING".to_string(),
             ),
+            Error::Yave(yave::Error::InvalidIpAddress(_)) => (
+                StatusCode::BAD_REQUEST,
+                "INVALID_IP_ADDRESS".to_string(),
+            ),
             Error::InvalidIp(_) => (
                 StatusCode::BAD_REQUEST,
                 "INVALID_IP_ADDRESS".to_string(),
@@ -109,20 +243,120 @@ impl IntoResponse for Error {
 // VM Types
 // ============================================================================
 
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, Default)]
+pub enum DriveBusDef {
+    #[default]
+    #[serde(rename = "virtio_blk")]
+    VirtioBlk,
+    #[serde(rename = "virtio_scsi")]
+    VirtioScsi,
+    #[serde(rename = "nvme")]
+    Nvme,
+    #[serde(rename = "ide")]
+    Ide,
+    #[serde(rename = "usb")]
+    Usb,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[serde(tag = "type")]
 pub enum DriveDef {
     #[serde(rename = "empty")]
     Empty {
         size: u64,
+        #[serde(default)]
+        bus: DriveBusDef,
+        #[serde(default)]
+        options: DriveOptions,
+        #[serde(default)]
+        encrypted: bool,
     },
     #[serde(rename = "from")]
     From {
         size: u64,
         image: String,
+        #[serde(default)]
+        bus: DriveBusDef,
+        #[serde(default)]
+        options: DriveOptions,
+        #[serde(default)]
+        encrypted: bool,
+    }
+}
+
+impl DriveDef {
+    pub fn size(&self) -> u64 {
+        match self {
+            DriveDef::Empty { size, .. } => *size,
+            DriveDef::From { size, .. } => *size,
+        }
+    }
+
+    /// Bus of the drive at position `idx`, which also determines its boot order.
+    pub fn drive_bus(&self, idx: usize, drive_id: &str) -> DriveBus {
+        let bus = match self {
+            DriveDef::Empty { bus, .. } => bus,
+            DriveDef::From { bus, .. } => bus,
+        };
+        let boot_index = Some(idx as u32 + 1);
+        match bus {
+            DriveBusDef::VirtioBlk => DriveBus::VirtioBlk { boot_index },
+            DriveBusDef::VirtioScsi => DriveBus::VirtioScsi { lun: idx as u32, boot_index },
+            DriveBusDef::Nvme => DriveBus::Nvme { serial: drive_id.to_string(), boot_index },
+            DriveBusDef::Ide => DriveBus::Ide { media_type: DiskMediaKind::Disk, boot_index },
+            DriveBusDef::Usb => DriveBus::UsbStorage { boot_index },
+        }
+    }
+
+    pub fn encrypted(&self) -> bool {
+        match self {
+            DriveDef::Empty { encrypted, .. } => *encrypted,
+            DriveDef::From { encrypted, .. } => *encrypted,
+        }
+    }
+
+    /// Drive options. New empty drives are always created as raw images,
+    /// encrypted drives are always LUKS encrypted qcow2 images.
+    pub fn options(&self) -> DriveOptions {
+        let (options, format) = match self {
+            DriveDef::Empty { options, .. } => (options, Some(DriveFormat::Raw)),
+            DriveDef::From { options, .. } => (options, options.format),
+        };
+        DriveOptions {
+            format: if self.encrypted() { Some(DriveFormat::Qcow2) } else { format },
+            ..options.clone()
+        }
     }
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DriveInfo {
+    pub id: String,
+    pub size: u64,
+    pub bus: DriveBus,
+    pub options: DriveOptions,
+    pub encrypted: bool,
+    pub media: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ChangeMediaRequest {
+    /// CD-ROM drive, the first one of the VM if omitted.
+    #[serde(default)]
+    pub drive_id: Option<String>,
+    /// ISO to insert, ejects the current media if omitted.
+    #[serde(default)]
+    pub iso: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct UpdateDriveRequest {
+    pub size: Option<u64>,
+    #[serde(default)]
+    pub force: bool,
+    pub io_limits: Option<IoLimits>,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct CreateVMRequest {
     pub id: String,
@@ -130,6 +364,42 @@ pub struct CreateVMRequest {
     pub memory: u32,
     pub vcpu: u32,
     pub drives: Vec<DriveDef>,
+    /// ISO from the image library attached as a CD-ROM.
+    #[serde(default)]
+    pub iso: Option<String>,
+    /// Boot from the ISO ahead of the disks.
+    #[serde(default)]
+    pub boot_iso: bool,
+    /// Defaults to a single interface on the default bridge.
+    #[serde(default)]
+    pub network_interfaces: Vec<NetworkInterfaceDef>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct NetworkInterfaceDef {
+    /// Named network from the configuration, the default bridge if unset.
+    #[serde(default)]
+    pub network: Option<String>,
+    /// VLAN id overriding the one of the network.
+    #[serde(default)]
+    pub vlan: Option<u16>,
+    /// MAC address of the interface, generated if unset.
+    #[serde(default)]
+    pub mac_address: Option<String>,
+    /// Device model, vhost, multiqueue and MTU.
+    #[serde(flatten)]
+    pub options: NicOptions,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CloneVMRequest {
+    pub id: String,
+    /// Hostname of the clone, defaults to the source's.
+    #[serde(default)]
+    pub hostname: Option<String>,
+    /// Share a frozen base image with the source instead of copying the drives.
+    #[serde(default)]
+    pub linked: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -150,6 +420,22 @@ pub struct NetworkInterface {
     pub id: String,
     pub ifname: String,
     pub mac_address: String,
+    pub network: Option<String>,
+    pub vlan: Option<u16>,
+    #[serde(flatten)]
+    pub options: NicOptions,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SetLinkRequest {
+    pub up: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SetVlanRequest {
+    /// VLAN id of the interface, the network's if unset.
+    #[serde(default)]
+    pub vlan: Option<u16>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -159,6 +445,20 @@ pub struct AddIpV4Request {
     pub gateway: Option<String>,
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct UpdateIpV4Request {
+    #[serde(default)]
+    pub netmask: Option<u32>,
+    /// Omitted keeps the gateway, `null` removes it.
+    #[serde(default, deserialize_with = "present")]
+    pub gateway: Option<Option<String>>,
+}
+
+/// Tells a field set to `null` apart from a missing one.
+fn present<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Option<Option<String>>, D::Error> {
+    Option::<String>::deserialize(deserializer).map(Some)
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct IpV4AddressInfo {
     pub ip_address: String,
@@ -167,11 +467,168 @@ pub struct IpV4AddressInfo {
     pub is_default: bool,
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AddIpRequest {
+    /// Address to assign, or any address of the /64 prefix for SLAAC.
+    pub ip_address: String,
+    pub prefix_len: u32,
+    #[serde(default)]
+    pub gateway: Option<String>,
+    #[serde(default)]
+    pub mode: AddressMode,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct IpAddressInfo {
+    pub ip_address: String,
+    pub family: IpFamily,
+    pub prefix_len: u32,
+    pub gateway: Option<String>,
+    pub mode: AddressMode,
+    pub is_default: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct IpPoolAddress {
+    pub ip_address: String,
+    pub ifname: String,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct IpPoolInfo {
+    pub name: String,
+    pub cidr: String,
+    pub gateway: Option<String>,
+    pub reserved: Vec<AddressRange>,
+    pub nameservers: Vec<String>,
+    pub network: Option<String>,
+    /// Addresses the pool can hand out.
+    pub total: usize,
+    pub used: usize,
+    pub free: usize,
+    /// Assigned addresses within the subnet.
+    pub addresses: Vec<IpPoolAddress>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CreateIpPoolRequest {
+    pub name: String,
+    pub cidr: String,
+    #[serde(default)]
+    pub gateway: Option<String>,
+    #[serde(default)]
+    pub reserved: Vec<AddressRange>,
+    #[serde(default)]
+    pub nameservers: Vec<String>,
+    /// Network served by the pool, the default bridge if omitted.
+    #[serde(default)]
+    pub network: Option<String>,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct NetworkConfig {
     pub interfaces: Vec<NetworkInterface>,
 }
 
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct NetworkInfo {
+    pub name: String,
+    pub bridge: String,
+    pub address: String,
+    pub prefix_len: u32,
+    pub nat: bool,
+    pub dhcp: bool,
+    pub mtu: Option<u32>,
+    pub isolated: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CreateNetworkRequest {
+    pub name: String,
+    pub bridge: String,
+    /// Host address on the bridge, the gateway of the guests.
+    pub address: String,
+    pub prefix_len: u32,
+    #[serde(default)]
+    pub nat: bool,
+    #[serde(default)]
+    pub dhcp: bool,
+    #[serde(default)]
+    pub mtu: Option<u32>,
+    /// Bridge without uplink: no host address, NAT or DHCP.
+    #[serde(default)]
+    pub isolated: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SocketNetworkInfo {
+    pub name: String,
+    pub mode: SocketMode,
+    pub address: String,
+    pub port: u16,
+    pub local_address: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CreateSocketNetworkRequest {
+    pub name: String,
+    pub mode: SocketMode,
+    /// Multicast group for `mcast`, address both ends bind and send to for `udp`.
+    pub address: String,
+    pub port: u16,
+    /// Host address `mcast` datagrams are sent from, loopback if unset.
+    #[serde(default)]
+    pub local_address: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct UpdateNetworkRequest {
+    #[serde(default)]
+    pub nat: Option<bool>,
+    #[serde(default)]
+    pub dhcp: Option<bool>,
+    #[serde(default)]
+    pub mtu: Option<u32>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SecurityGroupRuleInfo {
+    pub id: i64,
+    pub direction: RuleDirection,
+    pub protocol: RuleProtocol,
+    pub port_from: Option<u16>,
+    pub port_to: Option<u16>,
+    pub cidr: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SecurityGroupInfo {
+    pub name: String,
+    pub description: Option<String>,
+    pub rules: Vec<SecurityGroupRuleInfo>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CreateSecurityGroupRequest {
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct AddSecurityGroupRuleRequest {
+    pub direction: RuleDirection,
+    pub protocol: RuleProtocol,
+    /// Destination port or first port of a range, tcp and udp only.
+    #[serde(default)]
+    pub port_from: Option<u16>,
+    #[serde(default)]
+    pub port_to: Option<u16>,
+    /// Remote address or subnet, any if omitted.
+    #[serde(default)]
+    pub cidr: Option<String>,
+}
+
 // ============================================================================
 // Installation Types
 // ============================================================================
@@ -200,9 +657,36 @@ pub enum InstallStatus {
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct StartVMRequest {
     pub vnc_password: Option<String>,
+    /// Continue from the state saved by `POST /vm/{id}/save` instead of booting.
+    #[serde(default)]
+    pub resume: bool,
+    /// Throw away the saved state and boot. Starting a VM with a saved state fails otherwise.
+    #[serde(default)]
+    pub discard: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct VMRuntime {
     pub is_running: bool,
 }
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PortForwardInfo {
+    pub id: i64,
+    pub interface_id: String,
+    pub host_ip: Option<String>,
+    pub host_port: u16,
+    pub protocol: ForwardProtocol,
+    pub guest_port: u16,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct CreatePortForwardRequest {
+    pub interface_id: String,
+    /// Host address to expose the port on, all local addresses if omitted.
+    #[serde(default)]
+    pub host_ip: Option<String>,
+    pub host_port: u16,
+    pub protocol: ForwardProtocol,
+    pub guest_port: u16,
+}